@@ -0,0 +1,68 @@
+// Feeds arbitrary bytes through the same path trim_video takes to parse an
+// upload. Any panic, hang or runaway allocation is a bug.
+//
+// Run locally with:
+//     cargo +nightly fuzz run parse_movie
+// Seeding the corpus with real files helps it get past the top level quickly:
+//     mkdir -p fuzz/corpus/parse_movie && cp videos/*.mp4 fuzz/corpus/parse_movie/
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate trim;
+
+use trim::atoms::*;
+use trim::parser::*;
+use trim::reader::*;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = AtomNode::parse_all(AtomReader::new(data));
+
+    let mut parser = MParser::from_bytes(data.to_vec());
+    if let Ok(atoms) = MovieAtoms::parse_file(&mut parser) {
+        // walk the sample tables too, they index into each other
+        for trak in atoms.moov.iter().flat_map(|m| m.traks.iter()) {
+            if let Ok(index) = trak.sample_index() {
+                let _ = index.iter().count();
+                let _ = index.get(index.len() / 2);
+                let _ = index.sample_at_decode_time(1000);
+            }
+            if let Some(ref tkhd) = trak.tkhd {
+                let _ = tkhd.orientation();
+                let _ = tkhd.display_size();
+            }
+            for entry in trak.sample_entries() {
+                if let Ok(Some(config)) = entry.avc_config() {
+                    let _ = config.first_sps();
+                    let _ = config.first_pps();
+                }
+                if let Ok(Some(config)) = entry.hevc_config() {
+                    let _ = config.first_vps();
+                    let _ = config.first_sps();
+                    let _ = config.first_pps();
+                }
+                if let Ok(Some(config)) = entry.av1_config() {
+                    let _ = config.sequence_header();
+                }
+                let _ = entry.vp_config();
+                if let Ok(Some(esds)) = entry.esds() {
+                    let _ = esds.audio_specific_config();
+                }
+                let _ = entry.opus_config();
+                let _ = entry.flac_config();
+                let _ = entry.alac_config();
+                let _ = entry.ac3_config();
+                let _ = entry.ec3_config();
+                let _ = entry.pcm_format();
+                let _ = entry.codec_string();
+                let _ = entry.colour_information();
+                let _ = entry.colour();
+                let _ = entry.display_aspect_ratio();
+                let _ = entry.mastering_display();
+                let _ = entry.content_light_level();
+                let _ = entry.bit_rate();
+                let _ = entry.field_handling();
+                let _ = entry.dolby_vision_config();
+            }
+        }
+    }
+});
@@ -0,0 +1,171 @@
+use bitreader::*;
+
+/* ================================ AC-3 / E-AC-3 ================================ */
+
+// Samples per channel in an AC-3 frame, and in a six block E-AC-3 one
+pub const AC3_FRAME_LENGTH: u32 = 1536;
+
+const SAMPLE_RATES: [u32; 3] = [48000, 44100, 32000];
+
+// kb/s for each bit_rate_code, frmsizecod / 2
+const BIT_RATES: [u32; 19] = [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640];
+
+// The full bandwidth channels of each acmod, and the layout as front/rear
+const ACMODS: [(u8, &str); 8] = [
+    (2, "1+1"), (1, "1/0"), (2, "2/0"), (3, "3/0"), (3, "2/1"), (4, "3/1"), (4, "2/2"), (5, "3/2")
+];
+
+// Channels of the chan_loc bits in dec3, most significant first: Lc/Rc,
+// Lrs/Rrs, Cs, Ts, Lsd/Rsd, Lw/Rw, Lvh/Rvh, Cvh and LFE2
+const CHAN_LOC_CHANNELS: [u8; 9] = [2, 2, 1, 1, 2, 2, 2, 1, 1];
+
+// AC3SpecificBox, dac3
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ac3SpecificBox {
+    pub fscod: u8,
+    pub bsid: u8,
+    // bit stream mode: main audio, commentary, karaoke...
+    pub bsmod: u8,
+    // audio coding mode, the channel layout without the LFE
+    pub acmod: u8,
+    pub lfeon: bool,
+    pub bit_rate_code: u8
+}
+
+impl Ac3SpecificBox {
+    pub fn parse(data: &[u8]) -> Result<Ac3SpecificBox, String> {
+        let mut r = BitReader::new(data);
+
+        let dac3 = Ac3SpecificBox {
+            fscod:         r.read_u8(2)?,
+            bsid:          r.read_u8(5)?,
+            bsmod:         r.read_u8(3)?,
+            acmod:         r.read_u8(3)?,
+            lfeon:         r.read_bit()?,
+            bit_rate_code: r.read_u8(5)?
+        };
+
+        if dac3.fscod == 3 {
+            return Err("dac3: reserved fscod".to_string());
+        }
+        Ok(dac3)
+    }
+
+    pub fn sample_rate(&self) -> u32 { SAMPLE_RATES[self.fscod as usize % 3] }
+
+    // kb/s, None for a reserved code
+    pub fn bit_rate(&self) -> Option<u32> {
+        BIT_RATES.get(self.bit_rate_code as usize).cloned()
+    }
+
+    pub fn channel_count(&self) -> u8 { channel_count(self.acmod, self.lfeon) }
+
+    pub fn channel_layout(&self) -> String { channel_layout(self.acmod, self.lfeon) }
+}
+
+// EC3SpecificBox, dec3
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ec3SpecificBox {
+    // kb/s
+    pub data_rate: u16,
+    pub substreams: Vec<Ec3Substream>,
+    // Dolby Atmos carried as joint object coding
+    pub atmos_complexity_index: Option<u8>
+}
+
+// An independent substream and the dependent ones extending it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ec3Substream {
+    pub fscod: u8,
+    pub bsid: u8,
+    // an associated service, such as commentary, rather than main audio
+    pub asvc: bool,
+    pub bsmod: u8,
+    pub acmod: u8,
+    pub lfeon: bool,
+    pub num_dep_sub: u8,
+    // the channels the dependent substreams add
+    pub chan_loc: u16
+}
+
+impl Ec3SpecificBox {
+    pub fn parse(data: &[u8]) -> Result<Ec3SpecificBox, String> {
+        let mut r = BitReader::new(data);
+
+        let data_rate = r.read_u16(13)?;
+        let num_ind_sub = r.read_u8(3)? + 1;
+
+        let mut substreams = vec![];
+        for _ in 0..num_ind_sub {
+            let fscod = r.read_u8(2)?;
+            let bsid  = r.read_u8(5)?;
+            r.skip_bits(1)?; // reserved
+            let asvc  = r.read_bit()?;
+            let bsmod = r.read_u8(3)?;
+            let acmod = r.read_u8(3)?;
+            let lfeon = r.read_bit()?;
+            r.skip_bits(3)?; // reserved
+            let num_dep_sub = r.read_u8(4)?;
+
+            let mut substream = Ec3Substream { fscod, bsid, asvc, bsmod, acmod, lfeon, num_dep_sub, chan_loc: 0 };
+            if substream.num_dep_sub > 0 {
+                substream.chan_loc = r.read_u16(9)?;
+            } else {
+                r.skip_bits(1)?;
+            }
+            substreams.push(substream);
+        }
+
+        // flag_ec3_extension_type_a and complexity_index_type_a, when present
+        let mut atmos_complexity_index = None;
+        if r.bits_remaining() >= 16 {
+            r.skip_bits(7)?;
+            if r.read_bit()? {
+                atmos_complexity_index = Some(r.read_u8(8)?);
+            }
+        }
+
+        Ok(Ec3SpecificBox { data_rate, substreams, atmos_complexity_index })
+    }
+
+    // Of the first independent substream, which carries the main program
+    pub fn sample_rate(&self) -> Option<u32> {
+        let fscod = self.substreams.first()?.fscod;
+        SAMPLE_RATES.get(fscod as usize).cloned()
+    }
+
+    pub fn channel_count(&self) -> u8 {
+        self.substreams.first().map(|s| s.channel_count()).unwrap_or(0)
+    }
+
+    pub fn channel_layout(&self) -> String {
+        self.substreams.first().map(|s| s.channel_layout()).unwrap_or_default()
+    }
+}
+
+impl Ec3Substream {
+    pub fn channel_count(&self) -> u8 {
+        let extra: u8 = CHAN_LOC_CHANNELS.iter().enumerate()
+            .filter(|&(i, _)| self.chan_loc & (0x100 >> i) != 0)
+            .map(|(_, &channels)| channels)
+            .sum();
+        channel_count(self.acmod, self.lfeon) + extra
+    }
+
+    // The core layout, plus the dependent substream channels as a count
+    pub fn channel_layout(&self) -> String {
+        let layout = channel_layout(self.acmod, self.lfeon);
+        let extra = self.channel_count() - channel_count(self.acmod, self.lfeon);
+        if extra > 0 { format!("{} + {}", layout, extra) } else { layout }
+    }
+}
+
+fn channel_count(acmod: u8, lfeon: bool) -> u8 {
+    ACMODS[acmod as usize & 7].0 + if lfeon { 1 } else { 0 }
+}
+
+// e.g. "3/2.1" for 5.1, front/rear channels and the LFE
+fn channel_layout(acmod: u8, lfeon: bool) -> String {
+    let layout = ACMODS[acmod as usize & 7].1;
+    if lfeon { format!("{}.1", layout) } else { layout.to_string() }
+}
@@ -0,0 +1,51 @@
+use reader::*;
+
+/* ==================================== ALAC ==================================== */
+
+// ALACSpecificConfig, the magic cookie in the alac box of an alac entry.
+// QuickTime files keep it in a wave box instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlacSpecificConfig {
+    // samples per channel in every frame but the last, normally 4096
+    pub frame_length: u32,
+    pub compatible_version: u8,
+    pub bit_depth: u8,
+    pub pb: u8,
+    pub mb: u8,
+    pub kb: u8,
+    pub num_channels: u8,
+    pub max_run: u16,
+    pub max_frame_bytes: u32,
+    pub avg_bit_rate: u32,
+    pub sample_rate: u32
+}
+
+impl AlacSpecificConfig {
+    // data is the payload of the alac box. The cookie is 24 bytes, after a
+    // version and flags in the ISO form.
+    pub fn parse(data: &[u8]) -> Result<AlacSpecificConfig, String> {
+        let cookie = match data.len() {
+            24 => data,
+            _  => &data[4.min(data.len())..]
+        };
+
+        let config = AlacSpecificConfig {
+            frame_length:       read_u32_at(cookie, 0)?,
+            compatible_version: read_u8_at(cookie, 4)?,
+            bit_depth:          read_u8_at(cookie, 5)?,
+            pb:                 read_u8_at(cookie, 6)?,
+            mb:                 read_u8_at(cookie, 7)?,
+            kb:                 read_u8_at(cookie, 8)?,
+            num_channels:       read_u8_at(cookie, 9)?,
+            max_run:            read_u16_at(cookie, 10)?,
+            max_frame_bytes:    read_u32_at(cookie, 12)?,
+            avg_bit_rate:       read_u32_at(cookie, 16)?,
+            sample_rate:        read_u32_at(cookie, 20)?
+        };
+
+        if config.compatible_version != 0 {
+            return Err(format!("alac: unknown compatible version {}", config.compatible_version));
+        }
+        Ok(config)
+    }
+}
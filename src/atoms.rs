@@ -1,32 +1,60 @@
+use fixed::*;
+use layout::*;
+use matrix::*;
 use parser::*;
+use samples::*;
+use stbl::*;
+use stsd::*;
 use std::fmt;
 
 /* ============================ Traits and helpers ============================ */
 
-pub fn atom_type_and_size(parser: &mut MParserView) -> Result<(u32, String), String> {
-    let size = try!(parser.read_u32());
-    let typ  = try!(TypeParserAction::try_parse(parser));
+// Returns the size of the whole atom and its type, leaving the cursor after
+// the header. Size 1 means a 64 bit size follows the type and size 0 means
+// the atom runs to the end of what we're allowed to read.
+pub fn atom_type_and_size(parser: &mut MParserView) -> Result<(u64, String), String> {
+    let position = parser.get_position();
+    let size = parser.read_u32()?;
+    let typ  = TypeParserAction::try_parse(parser)?;
+
+    let size = match size {
+        0 => (parser.get_limit() - position) as u64,
+        1 => parser.read_u64()?,
+        s => s as u64
+    };
 
     Ok((size, typ))
 }
 
-fn loop_and_get_children(parser: &mut MParserView, atoms: &[&str]) -> Result<Vec<usize>, String> {
+pub fn loop_and_get_children(parser: &mut MParserView, atoms: &[&str]) -> Result<Vec<usize>, String> {
     let mut atom_positions = vec![];
+    let end = parser.get_limit();
 
-    //try!(parser.move_cursor(8));
-    loop {
+    while parser.get_remaining_bytes() >= 8 {
+        let actual_pos = parser.get_position();
         let (size, typ) = match atom_type_and_size(parser) {
             Ok((s,t)) => (s, t),
             Err(_)    => break
         };
 
-        let actual_pos = parser.get_position() - 8;
+        if size < 8 {
+            return Err(format!("{} @ {} has invalid size {}", typ, actual_pos, size));
+        }
+
+        let atom_end = (actual_pos as u64).saturating_add(size);
+        if atom_end > end as u64 {
+            if parser.get_limits().child_must_fit_in_parent {
+                return Err(format!("{} @ {} with size {} overruns its parent which ends at {}",
+                                   typ, actual_pos, size, end));
+            }
+            break;
+        }
+
+        // atoms we don't know about are skipped over rather than visited
         if atoms.contains(&typ.as_str()) {
             atom_positions.push(actual_pos);
-            parser.set_position(actual_pos + (size as usize));
-        } else {
-            break;
         }
+        parser.set_position(atom_end as usize);
     }
 
     atom_positions.reverse();
@@ -35,37 +63,45 @@ fn loop_and_get_children(parser: &mut MParserView, atoms: &[&str]) -> Result<Vec
 
 pub trait AtomParser {
     fn parse(&mut self, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        if depth > parser.get_limits().max_depth {
+            return Err(format!("Atoms nested deeper than {}", parser.get_limits().max_depth));
+        }
+
         let atom_position = parser.get_position();
-        try!(self.parse_self(parser));
+        self.parse_self(parser)?;
 
         // get children and add them to the stack
         let mut atom_position_stack = vec![];
         {
-            let mut view = parser.get_view_at(atom_position);
-            let mut children = try!(self.get_children(&mut view));
+            let mut view = parser.get_view_at(atom_position + self.header_size());
+            let mut children = self.get_children(&mut view)?;
             atom_position_stack.append(&mut children);
         }
 
-        while atom_position_stack.len() > 0
+        // pop the next atom position off the stack
+        while let Some(stack_pos) = atom_position_stack.pop()
         {
-            // pop next atom position off stack which should exist
-            let stack_pos = atom_position_stack.pop().unwrap();
-
             // visit (parse the atom)
             {
-                let mut view = parser.get_view_at(stack_pos);
-
-                let (size, typ) = match atom_type_and_size(&mut view) {
-                    Ok((s,t)) => (s, t),
-                    Err(_)    => break
+                let (size, typ) = {
+                    let mut view = parser.get_view_at(stack_pos);
+                    match atom_type_and_size(&mut view) {
+                        Ok((s,t)) => (s, t),
+                        Err(_)    => break
+                    }
                 };
 
+                parser.count_atom()?;
+
                 for _ in 0..(depth*3) {
                     print!(" ");
                 }
                 println!("{} @ {} with size {} (stack len = {})", typ, stack_pos, size, atom_position_stack.len());
 
-                try!(self.parse_child(&typ, &mut view, depth + 1));
+                // children parse themselves from their own header and can't
+                // read past their own end
+                let mut view = parser.get_bounded_view(stack_pos, stack_pos.saturating_add(size as usize));
+                self.parse_child(&typ, &mut view, depth + 1)?;
             }
         };
 
@@ -74,6 +110,9 @@ pub trait AtomParser {
 
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String>;
 
+    // Bytes between the start of the atom and its first child
+    fn header_size(&self) -> usize { 8 }
+
     fn get_children(&self, _: &mut MParserView) -> Result<Vec<usize>, String>
     {
         Ok(vec![])
@@ -103,11 +142,14 @@ impl AtomParser for MovieAtoms {
         Ok(())
     }
 
+    // Not a real atom, the top level boxes start right where we're pointed
+    fn header_size(&self) -> usize { 0 }
+
     fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
         match atom {
             "moov" => {
                 let mut moov = MoovAtom::new();
-                try!(moov.parse(parser, depth));
+                moov.parse(parser, depth)?;
                 self.moov = Some(moov);
             },
             _      => { println!("Need to parse {}", atom); }
@@ -118,12 +160,32 @@ impl AtomParser for MovieAtoms {
 
     fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
         let atoms = vec!["moov"];
-        let children = try!(loop_and_get_children(parser, &atoms));
+        let children = loop_and_get_children(parser, &atoms)?;
 
         Ok(children)
     }
 }
 
+impl MovieAtoms {
+    // Finds the moov by walking the top level boxes and parses it
+    pub fn parse_file(parser: &mut MParser) -> Result<MovieAtoms, String> {
+        let moov_pos = match FileLayout::walk(&parser.data).find("moov") {
+            Some(moov) => moov.location,
+            None       => return Err("Could not find moov atom".to_string())
+        };
+
+        let mut atoms = MovieAtoms::new();
+        let mut view = parser.get_view_at(moov_pos);
+        atoms.parse(&mut view, 0)?;
+
+        Ok(atoms)
+    }
+}
+
+impl Default for MovieAtoms {
+    fn default() -> MovieAtoms { MovieAtoms::new() }
+}
+
 pub struct MoovAtom {
     pub location: usize,
     pub size: u32,
@@ -142,12 +204,16 @@ impl MoovAtom {
     }
 }
 
+impl Default for MoovAtom {
+    fn default() -> MoovAtom { MoovAtom::new() }
+}
+
 impl AtomParser for MoovAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location = parser.get_position();
-        self.size     = try!(parser.read_u32());
+        self.size     = parser.read_u32()?;
 
-        try!(parser.move_cursor(4));
+        parser.move_cursor(4)?;
 
         Ok(())
     }
@@ -156,12 +222,12 @@ impl AtomParser for MoovAtom {
         match atom {
             "mvhd" => {
                 let mut mvhd = MovieHeaderAtom::new();
-                try!(mvhd.parse(parser, depth));
+                mvhd.parse(parser, depth)?;
                 self.mvhd = Some(mvhd);
             },
             "trak" => {
                 let mut trak = TrakAtom::new();
-                try!(trak.parse(parser, depth));
+                trak.parse(parser, depth)?;
                 self.traks.push(trak);
             },
             _      => { println!("Need to parse {}", atom); }
@@ -171,7 +237,7 @@ impl AtomParser for MoovAtom {
 
     fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
         let atoms = vec!["mvhd", "iods", "trak", "udta"];
-        let children = try!(loop_and_get_children(parser, &atoms));
+        let children = loop_and_get_children(parser, &atoms)?;
 
         Ok(children)
     }
@@ -182,7 +248,7 @@ impl fmt::Display for MoovAtom {
         write!(f, "Atom moov @ {} of size {}; ends at {}",
                self.location,
                self.size,
-               (self.location as u32) + self.size)
+               (self.location as u64) + (self.size as u64))
     }
 }
 
@@ -191,12 +257,14 @@ pub struct MovieHeaderAtom {
     pub size: u32,
     pub version: u8,
     pub flags: u32,
-    pub creation_time: u32,
-    pub modification_time: u32,
+    // 64 bit in version 1
+    pub creation_time: u64,
+    pub modification_time: u64,
     pub time_scale: u32,
-    pub duration: u32,
-    pub rate: f32,
-    pub volume: f32,
+    pub duration: u64,
+    pub rate: Fixed16_16,
+    pub volume: Fixed8_8,
+    pub matrix: Matrix,
     pub preview_time: u32,
     pub preview_duration: u32,
     pub poster_time: u32,
@@ -218,8 +286,9 @@ impl MovieHeaderAtom {
             modification_time: 0,
             time_scale: 0,
             duration: 0,
-            rate: 0.0f32,
-            volume: 0.0f32,
+            rate: Fixed16_16::from_int(1),
+            volume: Fixed8_8::from_int(1),
+            matrix: Matrix::identity(),
             preview_time: 0,
             preview_duration: 0,
             poster_time: 0,
@@ -234,24 +303,32 @@ impl MovieHeaderAtom {
 impl AtomParser for MovieHeaderAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location                   = parser.get_position();
-        self.size                       = try!(parser.read_u32());
-        try!(parser.move_cursor(4)); // reserved
-        self.version                    = try!(parser.read_u8());
-        self.flags                      = try!(parser.read_flags());
-        self.creation_time              = try!(parser.read_u32());
-        self.modification_time          = try!(parser.read_u32());
-        self.time_scale                 = try!(parser.read_u32());
-        self.duration                   = try!(parser.read_u32());
-        self.rate                       = try!(parser.read_fixed32());
-        self.volume                     = try!(parser.read_fixed16());
-        try!(parser.move_cursor(36)); // matrix
-        self.preview_time               = try!(parser.read_u32());
-        self.preview_duration           = try!(parser.read_u32());
-        self.poster_time                = try!(parser.read_u32());
-        self.selection_time             = try!(parser.read_u32());
-        self.selection_duration         = try!(parser.read_u32());
-        self.current_time               = try!(parser.read_u32());
-        self.next_track_id              = try!(parser.read_u32());
+        self.size                       = parser.read_u32()?;
+        parser.move_cursor(4)?; // type
+        self.version                    = parser.read_u8()?;
+        self.flags                      = parser.read_flags()?;
+        if self.version == 1 {
+            self.creation_time          = parser.read_u64()?;
+            self.modification_time      = parser.read_u64()?;
+            self.time_scale             = parser.read_u32()?;
+            self.duration               = parser.read_u64()?;
+        } else {
+            self.creation_time          = parser.read_u32()? as u64;
+            self.modification_time      = parser.read_u32()? as u64;
+            self.time_scale             = parser.read_u32()?;
+            self.duration               = parser.read_u32()? as u64;
+        }
+        self.rate                       = parser.read_fixed16_16()?;
+        self.volume                     = parser.read_fixed8_8()?;
+        parser.move_cursor(10)?; // reserved
+        self.matrix                     = parser.read_matrix()?;
+        self.preview_time               = parser.read_u32()?;
+        self.preview_duration           = parser.read_u32()?;
+        self.poster_time                = parser.read_u32()?;
+        self.selection_time             = parser.read_u32()?;
+        self.selection_duration         = parser.read_u32()?;
+        self.current_time               = parser.read_u32()?;
+        self.next_track_id              = parser.read_u32()?;
 
         Ok(())
     }
@@ -261,6 +338,7 @@ pub struct TrakAtom {
     pub location: usize,
     pub size: u32,
     pub tkhd: Option<TrakHeaderAtom>,
+    pub edts: Option<EditAtom>,
     pub mdia: Option<MediaAtom>
 }
 
@@ -270,15 +348,67 @@ impl TrakAtom {
             location: 0,
             size: 0,
             tkhd: None,
+            edts: None,
             mdia: None
         }
     }
 }
 
+impl TrakAtom {
+    pub fn stbl(&self) -> Option<&SampleTableAtom> {
+        self.mdia.as_ref()?.minf.as_ref()?.stbl.as_ref()
+    }
+
+    pub fn time_scale(&self) -> Option<u32> {
+        Some(self.mdia.as_ref()?.mdhd.as_ref()?.time_scale)
+    }
+
+    pub fn sample_entries(&self) -> &[SampleEntry] {
+        match self.stbl().and_then(|stbl| stbl.stsd.as_ref()) {
+            Some(stsd) => &stsd.entries,
+            None       => &[]
+        }
+    }
+
+    // index is 1 based, as in stsc
+    pub fn sample_entry(&self, index: u32) -> Option<&SampleEntry> {
+        self.stbl()?.stsd.as_ref()?.entry(index)
+    }
+
+    // Of the first sample entry, None when there's no entry
+    pub fn codec_string(&self) -> Result<Option<String>, String> {
+        match self.sample_entry(1) {
+            Some(entry) => Ok(Some(entry.codec_string()?)),
+            None        => Ok(None)
+        }
+    }
+
+    pub fn elst(&self) -> Option<&EditListAtom> {
+        self.edts.as_ref()?.elst.as_ref()
+    }
+
+    pub fn sample_index(&self) -> Result<SampleIndex<'_>, String> {
+        let stbl = match self.stbl() {
+            Some(stbl) => stbl,
+            None       => return Err("trak has no stbl".to_string())
+        };
+        let time_scale = match self.time_scale() {
+            Some(t) => t,
+            None    => return Err("trak has no mdhd".to_string())
+        };
+
+        SampleIndex::new(stbl, time_scale)
+    }
+
+    pub fn samples(&self) -> Result<Samples<'_>, String> {
+        Ok(Samples::new(self.sample_index()?))
+    }
+}
+
 impl AtomParser for TrakAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location = parser.get_position();
-        self.size     = try!(parser.read_u32());
+        self.size     = parser.read_u32()?;
         Ok(())
     }
 
@@ -286,12 +416,17 @@ impl AtomParser for TrakAtom {
         match atom {
             "tkhd" => {
                 let mut tkhd = TrakHeaderAtom::new();
-                try!(tkhd.parse(parser, depth));
+                tkhd.parse(parser, depth)?;
                 self.tkhd = Some(tkhd);
             },
+            "edts" => {
+                let mut edts = EditAtom::new();
+                edts.parse(parser, depth)?;
+                self.edts = Some(edts);
+            },
             "mdia" => {
                 let mut mdia = MediaAtom::new();
-                try!(mdia.parse(parser, depth));
+                mdia.parse(parser, depth)?;
                 self.mdia = Some(mdia);
             },
             _      => { println!("Need to parse {}", atom); }
@@ -305,7 +440,7 @@ impl AtomParser for TrakAtom {
             "tkhd", "tapt", "clip", "matt", "edts", "tref",
             "txas", "load", "imap", "mdia", "udta"
         ];
-        let children = try!(loop_and_get_children(parser, &atoms));
+        let children = loop_and_get_children(parser, &atoms)?;
 
         Ok(children)
     }
@@ -316,15 +451,17 @@ pub struct TrakHeaderAtom {
     pub size: u32,
     pub version: u8,
     pub flags: u32,
-    pub creation_time: u32,
-    pub modification_time: u32,
+    // 64 bit in version 1
+    pub creation_time: u64,
+    pub modification_time: u64,
     pub track_id: u32,
-    pub duration: u32,
+    pub duration: u64,
     pub layer: u16,
     pub alternate_group: u16,
-    pub volume: f32,
-    pub track_width: f32,
-    pub track_height: f32
+    pub volume: Fixed8_8,
+    pub matrix: Matrix,
+    pub track_width: UFixed16_16,
+    pub track_height: UFixed16_16
 }
 
 impl TrakHeaderAtom {
@@ -340,33 +477,171 @@ impl TrakHeaderAtom {
             duration: 0,
             layer: 0,
             alternate_group: 0,
-            volume: 0.0f32,
-            track_width: 0.0f32,
-            track_height: 0.0f32
+            volume: Fixed8_8::from_int(0),
+            matrix: Matrix::identity(),
+            track_width: UFixed16_16::from_int(0),
+            track_height: UFixed16_16::from_int(0)
         }
     }
+
+    // File offset of the matrix, for rewriting it in place. The header is
+    // always read as 8 bytes, see parse_self.
+    pub fn matrix_location(&self) -> usize {
+        self.location + 8 + if self.version == 1 { 52 } else { 40 }
+    }
+
+    pub fn orientation(&self) -> Option<Orientation> { self.matrix.orientation() }
+
+    // Width and height as shown, after the matrix
+    pub fn display_size(&self) -> (f64, f64) {
+        self.matrix.display_size(self.track_width.to_f64(), self.track_height.to_f64())
+    }
 }
 
 impl AtomParser for TrakHeaderAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location                  = parser.get_position();
-        self.size                      = try!(parser.read_u32());
-        try!(parser.move_cursor(4));
-        self.version                   = try!(parser.read_u8());
-        self.flags                     = try!(parser.read_flags());
-        self.creation_time             = try!(parser.read_u32());
-        self.modification_time         = try!(parser.read_u32());
-        self.track_id                  = try!(parser.read_u32());
-        try!(parser.move_cursor(4));  // reserved
-        self.duration                  = try!(parser.read_u32());
-        try!(parser.move_cursor(8));  // reserved
-        self.layer                     = try!(parser.read_u16());
-        self.alternate_group           = try!(parser.read_u16());
-        self.volume                    = try!(parser.read_fixed16());
-        try!(parser.move_cursor(2));  // reserved
-        try!(parser.move_cursor(36)); // matrix
-        self.track_width               = try!(parser.read_fixed32());
-        self.track_height              = try!(parser.read_fixed32());
+        self.size                      = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                   = parser.read_u8()?;
+        self.flags                     = parser.read_flags()?;
+        if self.version == 1 {
+            self.creation_time         = parser.read_u64()?;
+            self.modification_time     = parser.read_u64()?;
+            self.track_id              = parser.read_u32()?;
+            parser.move_cursor(4)?;  // reserved
+            self.duration              = parser.read_u64()?;
+        } else {
+            self.creation_time         = parser.read_u32()? as u64;
+            self.modification_time     = parser.read_u32()? as u64;
+            self.track_id              = parser.read_u32()?;
+            parser.move_cursor(4)?;  // reserved
+            self.duration              = parser.read_u32()? as u64;
+        }
+        parser.move_cursor(8)?;  // reserved
+        self.layer                     = parser.read_u16()?;
+        self.alternate_group           = parser.read_u16()?;
+        self.volume                    = parser.read_fixed8_8()?;
+        parser.move_cursor(2)?;  // reserved
+        self.matrix                    = parser.read_matrix()?;
+        self.track_width               = parser.read_ufixed16_16()?;
+        self.track_height              = parser.read_ufixed16_16()?;
+
+        Ok(())
+    }
+}
+
+pub struct EditAtom {
+    pub location: usize,
+    pub size: u32,
+    pub elst: Option<EditListAtom>
+}
+
+impl EditAtom {
+    fn new() -> EditAtom {
+        EditAtom { location: 0, size: 0, elst: None }
+    }
+}
+
+impl AtomParser for EditAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        match atom {
+            "elst" => {
+                let mut elst = EditListAtom::new();
+                elst.parse(parser, depth)?;
+                self.elst = Some(elst);
+            },
+            _      => { println!("Need to parse {}", atom); }
+        };
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec!["elst"];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+// One edit: segment_duration in movie time scale units of the media starting
+// at media_time, in media time scale units. A media_time of -1 is an empty
+// edit, i.e. nothing is presented for that long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EditListEntry {
+    pub segment_duration: u64,
+    pub media_time: i64,
+    pub media_rate: Fixed16_16
+}
+
+impl EditListEntry {
+    pub fn is_empty_edit(&self) -> bool { self.media_time == -1 }
+}
+
+pub struct EditListAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<EditListEntry>
+}
+
+impl EditListAtom {
+    pub fn new() -> EditListAtom {
+        EditListAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+
+    // Where the track's presentation starts: how long it's delayed by empty
+    // edits, in movie time scale units, and the media time the first real
+    // edit starts from. Later edits aren't looked at.
+    pub fn start(&self) -> (u64, i64) {
+        let mut delay = 0u64;
+        for entry in &self.entries {
+            if entry.is_empty_edit() {
+                delay = delay.saturating_add(entry.segment_duration);
+            } else {
+                return (delay, entry.media_time);
+            }
+        }
+        (delay, 0)
+    }
+}
+
+impl Default for EditListAtom {
+    fn default() -> EditListAtom { EditListAtom::new() }
+}
+
+impl AtomParser for EditListAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+
+        let count = parser.read_u32()?;
+        let entry_size = if self.version == 1 { 20 } else { 12 };
+        parser.check_table_entries(count as u64, entry_size, "elst")?;
+
+        for _ in 0..count {
+            let (segment_duration, media_time) = if self.version == 1 {
+                (parser.read_u64()?, parser.read_u64()? as i64)
+            } else {
+                (parser.read_u32()? as u64, parser.read_u32()? as i32 as i64)
+            };
+            let media_rate = parser.read_fixed16_16()?;
+
+            self.entries.push(EditListEntry { segment_duration, media_time, media_rate });
+        }
 
         Ok(())
     }
@@ -377,7 +652,7 @@ pub struct MediaAtom {
     pub size: u32,
     pub mdhd: Option<MediaHeaderAtom>,
     pub hdlr: Option<HandlerReferenceAtom>,
-    //pub minf: Option<MediaInfoAtom>,
+    pub minf: Option<MediaInfoAtom>,
     //pub udta: Option<UserDataAtom>
 }
 
@@ -387,7 +662,8 @@ impl MediaAtom {
             location: 0,
             size: 0,
             mdhd: None,
-            hdlr: None
+            hdlr: None,
+            minf: None
         }
     }
 }
@@ -395,8 +671,8 @@ impl MediaAtom {
 impl AtomParser for MediaAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location                 = parser.get_position();
-        self.size                     = try!(parser.read_u32());
-        try!(parser.move_cursor(4));
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
 
         Ok(())
     }
@@ -405,14 +681,19 @@ impl AtomParser for MediaAtom {
         match atom {
             "mdhd" => {
                 let mut mdhd = MediaHeaderAtom::new();
-                try!(mdhd.parse(parser, depth));
+                mdhd.parse(parser, depth)?;
                 self.mdhd = Some(mdhd);
             },
             "hdlr" => {
                 let mut hdlr = HandlerReferenceAtom::new();
-                try!(hdlr.parse(parser, depth));
+                hdlr.parse(parser, depth)?;
                 self.hdlr = Some(hdlr);
             },
+            "minf" => {
+                let mut minf = MediaInfoAtom::new();
+                minf.parse(parser, depth)?;
+                self.minf = Some(minf);
+            },
             _      => { println!("Need to parse {}", atom); }
         };
 
@@ -421,7 +702,7 @@ impl AtomParser for MediaAtom {
 
     fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
         let atoms = vec!["mdhd", "elng", "hdlr", "minf", "udta"];
-        let children = try!(loop_and_get_children(parser, &atoms));
+        let children = loop_and_get_children(parser, &atoms)?;
 
         Ok(children)
     }
@@ -432,10 +713,11 @@ pub struct MediaHeaderAtom {
     pub size: u32,
     pub version: u8,
     pub flags: u32,
-    pub creation_time: u32,
-    pub modification_time: u32,
+    // 64 bit in version 1
+    pub creation_time: u64,
+    pub modification_time: u64,
     pub time_scale: u32,
-    pub duration: u32,
+    pub duration: u64,
     pub language: u16,
     pub quality: u16
 }
@@ -454,16 +736,23 @@ impl MediaHeaderAtom {
 impl AtomParser for MediaHeaderAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location                 = parser.get_position();
-        self.size                     = try!(parser.read_u32());
-        try!(parser.move_cursor(4));
-        self.version                  = try!(parser.read_u8());
-        self.flags                    = try!(parser.read_flags());
-        self.creation_time            = try!(parser.read_u32());
-        self.modification_time        = try!(parser.read_u32());
-        self.time_scale               = try!(parser.read_u32());
-        self.duration                 = try!(parser.read_u32());
-        self.language                 = try!(parser.read_u16());
-        self.quality                  = try!(parser.read_u16());
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        if self.version == 1 {
+            self.creation_time        = parser.read_u64()?;
+            self.modification_time    = parser.read_u64()?;
+            self.time_scale           = parser.read_u32()?;
+            self.duration             = parser.read_u64()?;
+        } else {
+            self.creation_time        = parser.read_u32()? as u64;
+            self.modification_time    = parser.read_u32()? as u64;
+            self.time_scale           = parser.read_u32()?;
+            self.duration             = parser.read_u32()? as u64;
+        }
+        self.language                 = parser.read_u16()?;
+        self.quality                  = parser.read_u16()?;
 
         Ok(())
     }
@@ -491,11 +780,400 @@ impl HandlerReferenceAtom {
 impl AtomParser for HandlerReferenceAtom {
     fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
         self.location = parser.get_position();
-        self.size = try!(parser.read_u32());
-        self.version = try!(parser.read_u8());
-        self.flags = try!(parser.read_flags());
-        self.component_type = try!(parser.read_u32());
-        self.component_subtype = try!(parser.read_u32());
+        self.size = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version = parser.read_u8()?;
+        self.flags = parser.read_flags()?;
+        self.component_type = parser.read_u32()?;
+        self.component_subtype = parser.read_u32()?;
+
+        Ok(())
+    }
+}
+
+pub struct MediaInfoAtom {
+    pub location: usize,
+    pub size: u32,
+    pub vmhd: Option<VideoMediaHeaderAtom>,
+    pub smhd: Option<SoundMediaHeaderAtom>,
+    pub nmhd: Option<NullMediaHeaderAtom>,
+    pub gmhd: Option<BaseMediaHeaderAtom>,
+    // QuickTime files put a data handler hdlr here
+    pub hdlr: Option<HandlerReferenceAtom>,
+    pub dinf: Option<DataInfoAtom>,
+    pub stbl: Option<SampleTableAtom>
+}
+
+impl MediaInfoAtom {
+    fn new() -> MediaInfoAtom {
+        MediaInfoAtom {
+            location: 0,
+            size: 0,
+            vmhd: None,
+            smhd: None,
+            nmhd: None,
+            gmhd: None,
+            hdlr: None,
+            dinf: None,
+            stbl: None
+        }
+    }
+}
+
+impl AtomParser for MediaInfoAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        match atom {
+            "vmhd" => {
+                let mut vmhd = VideoMediaHeaderAtom::new();
+                vmhd.parse(parser, depth)?;
+                self.vmhd = Some(vmhd);
+            },
+            "smhd" => {
+                let mut smhd = SoundMediaHeaderAtom::new();
+                smhd.parse(parser, depth)?;
+                self.smhd = Some(smhd);
+            },
+            "nmhd" => {
+                let mut nmhd = NullMediaHeaderAtom::new();
+                nmhd.parse(parser, depth)?;
+                self.nmhd = Some(nmhd);
+            },
+            "gmhd" => {
+                let mut gmhd = BaseMediaHeaderAtom::new();
+                gmhd.parse(parser, depth)?;
+                self.gmhd = Some(gmhd);
+            },
+            "hdlr" => {
+                let mut hdlr = HandlerReferenceAtom::new();
+                hdlr.parse(parser, depth)?;
+                self.hdlr = Some(hdlr);
+            },
+            "dinf" => {
+                let mut dinf = DataInfoAtom::new();
+                dinf.parse(parser, depth)?;
+                self.dinf = Some(dinf);
+            },
+            "stbl" => {
+                let mut stbl = SampleTableAtom::new();
+                stbl.parse(parser, depth)?;
+                self.stbl = Some(stbl);
+            },
+            _      => { println!("Need to parse {}", atom); }
+        };
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec!["vmhd", "smhd", "nmhd", "gmhd", "hdlr", "dinf", "stbl"];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+pub struct VideoMediaHeaderAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub graphics_mode: u16,
+    pub opcolor: [u16; 3]
+}
+
+impl VideoMediaHeaderAtom {
+    fn new() -> VideoMediaHeaderAtom {
+        VideoMediaHeaderAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            graphics_mode: 0, opcolor: [0; 3]
+        }
+    }
+}
+
+impl AtomParser for VideoMediaHeaderAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.graphics_mode            = parser.read_u16()?;
+        self.opcolor[0]               = parser.read_u16()?;
+        self.opcolor[1]               = parser.read_u16()?;
+        self.opcolor[2]               = parser.read_u16()?;
+
+        Ok(())
+    }
+}
+
+pub struct SoundMediaHeaderAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub balance: Fixed8_8
+}
+
+impl SoundMediaHeaderAtom {
+    fn new() -> SoundMediaHeaderAtom {
+        SoundMediaHeaderAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            balance: Fixed8_8::from_int(0)
+        }
+    }
+}
+
+impl AtomParser for SoundMediaHeaderAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.balance                  = parser.read_fixed8_8()?;
+        parser.move_cursor(2)?; // reserved
+
+        Ok(())
+    }
+}
+
+pub struct NullMediaHeaderAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32
+}
+
+impl NullMediaHeaderAtom {
+    fn new() -> NullMediaHeaderAtom {
+        NullMediaHeaderAtom { location: 0, size: 0, version: 0, flags: 0 }
+    }
+}
+
+impl AtomParser for NullMediaHeaderAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+
+        Ok(())
+    }
+}
+
+// QuickTime's gmhd, used by timecode, text and other non audio/video tracks.
+// The gmin child carries the actual header fields.
+pub struct BaseMediaHeaderAtom {
+    pub location: usize,
+    pub size: u32,
+    pub gmin: Option<BaseMediaInfoAtom>
+}
+
+impl BaseMediaHeaderAtom {
+    fn new() -> BaseMediaHeaderAtom {
+        BaseMediaHeaderAtom { location: 0, size: 0, gmin: None }
+    }
+}
+
+impl AtomParser for BaseMediaHeaderAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        match atom {
+            "gmin" => {
+                let mut gmin = BaseMediaInfoAtom::new();
+                gmin.parse(parser, depth)?;
+                self.gmin = Some(gmin);
+            },
+            _      => { println!("Need to parse {}", atom); }
+        };
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec!["gmin"];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+pub struct BaseMediaInfoAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub graphics_mode: u16,
+    pub opcolor: [u16; 3],
+    pub balance: Fixed8_8
+}
+
+impl BaseMediaInfoAtom {
+    fn new() -> BaseMediaInfoAtom {
+        BaseMediaInfoAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            graphics_mode: 0, opcolor: [0; 3], balance: Fixed8_8::from_int(0)
+        }
+    }
+}
+
+impl AtomParser for BaseMediaInfoAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.graphics_mode            = parser.read_u16()?;
+        self.opcolor[0]               = parser.read_u16()?;
+        self.opcolor[1]               = parser.read_u16()?;
+        self.opcolor[2]               = parser.read_u16()?;
+        self.balance                  = parser.read_fixed8_8()?;
+        parser.move_cursor(2)?; // reserved
+
+        Ok(())
+    }
+}
+
+pub struct DataInfoAtom {
+    pub location: usize,
+    pub size: u32,
+    pub dref: Option<DataReferenceAtom>
+}
+
+impl DataInfoAtom {
+    fn new() -> DataInfoAtom {
+        DataInfoAtom { location: 0, size: 0, dref: None }
+    }
+}
+
+impl AtomParser for DataInfoAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        match atom {
+            "dref" => {
+                let mut dref = DataReferenceAtom::new();
+                dref.parse(parser, depth)?;
+                self.dref = Some(dref);
+            },
+            _      => { println!("Need to parse {}", atom); }
+        };
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec!["dref"];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+pub struct DataReferenceAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entry_count: u32,
+    pub entries: Vec<DataReferenceEntry>
+}
+
+impl DataReferenceAtom {
+    fn new() -> DataReferenceAtom {
+        DataReferenceAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            entry_count: 0, entries: vec![]
+        }
+    }
+}
+
+impl AtomParser for DataReferenceAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.entry_count              = parser.read_u32()?;
+        parser.check_table_entries(self.entry_count as u64, 12, "dref")?;
+
+        Ok(())
+    }
+
+    // size, type, version, flags and entry count come before the entries
+    fn header_size(&self) -> usize { 16 }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        let mut entry = DataReferenceEntry::new(atom);
+        entry.parse(parser, depth)?;
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec!["url ", "urn ", "alis", "rsrc"];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+pub struct DataReferenceEntry {
+    pub location: usize,
+    pub size: u32,
+    pub typ: String,
+    pub version: u8,
+    pub flags: u32,
+    // the url, urn or alias record, empty when the media is in this file
+    pub data: Vec<u8>
+}
+
+impl DataReferenceEntry {
+    fn new(typ: &str) -> DataReferenceEntry {
+        DataReferenceEntry {
+            location: 0, size: 0, typ: typ.to_string(), version: 0, flags: 0,
+            data: vec![]
+        }
+    }
+
+    // Flag 1 means the media data is in the same file as the movie
+    pub fn is_self_contained(&self) -> bool { self.flags & 1 == 1 }
+}
+
+impl AtomParser for DataReferenceEntry {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let remaining                 = parser.get_remaining_bytes();
+        self.data                     = parser.read_bytes(remaining)?;
 
         Ok(())
     }
@@ -0,0 +1,397 @@
+use bitreader::*;
+use reader::*;
+
+/* ==================================== AV1 ==================================== */
+
+// AV1CodecConfigurationRecord, the payload of av1C. The fields repeat the
+// sequence header, which usually follows in config_obus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Av1Config {
+    pub version: u8,
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay: Option<u8>,
+    pub config_obus: Vec<u8>
+}
+
+impl Av1Config {
+    pub fn parse(data: &[u8]) -> Result<Av1Config, String> {
+        let marker_and_version = read_u8_at(data, 0)?;
+        if marker_and_version != 0x81 {
+            return Err(format!("av1C: unknown marker and version {:#x}", marker_and_version));
+        }
+
+        let profile_and_level = read_u8_at(data, 1)?;
+        let flags = read_u8_at(data, 2)?;
+        let delay = read_u8_at(data, 3)?;
+
+        Ok(Av1Config {
+            version:                    marker_and_version & 0x7f,
+            seq_profile:                profile_and_level >> 5,
+            seq_level_idx_0:            profile_and_level & 0x1f,
+            seq_tier_0:                 flags & 0x80 != 0,
+            high_bitdepth:              flags & 0x40 != 0,
+            twelve_bit:                 flags & 0x20 != 0,
+            monochrome:                 flags & 0x10 != 0,
+            chroma_subsampling_x:       flags & 0x08 != 0,
+            chroma_subsampling_y:       flags & 0x04 != 0,
+            chroma_sample_position:     flags & 0x3,
+            initial_presentation_delay: if delay & 0x10 != 0 { Some((delay & 0xf) + 1) } else { None },
+            config_obus:                data[4..].to_vec()
+        })
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (true, true)  => 12,
+            (true, false) => 10,
+            _             => 8
+        }
+    }
+
+    // The sequence header in config_obus, if there is one
+    pub fn sequence_header(&self) -> Result<Option<SequenceHeader>, String> {
+        for obu in Obus::new(&self.config_obus) {
+            let obu = obu?;
+            if obu.obu_type == OBU_SEQUENCE_HEADER {
+                return Ok(Some(SequenceHeader::parse(obu.payload)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub const OBU_SEQUENCE_HEADER: u8 = 1;
+pub const OBU_TEMPORAL_DELIMITER: u8 = 2;
+pub const OBU_FRAME_HEADER: u8 = 3;
+pub const OBU_TILE_GROUP: u8 = 4;
+pub const OBU_METADATA: u8 = 5;
+pub const OBU_FRAME: u8 = 6;
+pub const OBU_REDUNDANT_FRAME_HEADER: u8 = 7;
+pub const OBU_PADDING: u8 = 15;
+
+pub struct Obu<'a> {
+    pub obu_type: u8,
+    // temporal and spatial id, when the header has an extension
+    pub extension: Option<(u8, u8)>,
+    pub payload: &'a [u8]
+}
+
+// The OBUs of a temporal unit (a sample) or of av1C's config_obus. An OBU
+// without a size field runs to the end of the data.
+pub struct Obus<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool
+}
+
+impl<'a> Obus<'a> {
+    pub fn new(data: &'a [u8]) -> Obus<'a> {
+        Obus { data, offset: 0, failed: false }
+    }
+
+    fn read_obu(&mut self) -> Result<Obu<'a>, String> {
+        let header = read_u8_at(self.data, self.offset)?;
+        if header & 0x80 != 0 {
+            return Err("OBU forbidden bit is set".to_string());
+        }
+
+        let obu_type = (header >> 3) & 0xf;
+        let mut offset = self.offset + 1;
+        let extension = if header & 0x4 != 0 {
+            let e = read_u8_at(self.data, offset)?;
+            offset += 1;
+            Some((e >> 5, (e >> 3) & 0x3))
+        } else {
+            None
+        };
+
+        let size = if header & 0x2 != 0 {
+            let (size, length) = read_leb128(&self.data[offset.min(self.data.len())..])?;
+            offset += length;
+            size as usize
+        } else {
+            self.data.len().saturating_sub(offset)
+        };
+
+        let payload = read_bytes_at(self.data, offset, size)?;
+        self.offset = offset + size;
+        Ok(Obu { obu_type, extension, payload })
+    }
+}
+
+impl<'a> Iterator for Obus<'a> {
+    type Item = Result<Obu<'a>, String>;
+
+    fn next(&mut self) -> Option<Result<Obu<'a>, String>> {
+        if self.failed || self.offset >= self.data.len() {
+            return None;
+        }
+
+        let obu = self.read_obu();
+        if let Err(ref e) = obu {
+            self.failed = true;
+            return Some(Err(format!("OBU at {}: {}", self.offset, e)));
+        }
+        Some(obu)
+    }
+}
+
+// Returns the value and how many bytes it took
+pub fn read_leb128(data: &[u8]) -> Result<(u64, usize), String> {
+    let mut value = 0u64;
+    for i in 0..8 {
+        let byte = read_u8_at(data, i)?;
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err("leb128 longer than 8 bytes".to_string())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceHeader {
+    pub seq_profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    // of the first operating point
+    pub seq_level_idx: u8,
+    pub seq_tier: bool,
+    // time_scale and num_units_in_display_tick when there's timing info
+    pub timing: Option<(u32, u32)>,
+    // only when every picture lasts the same number of ticks
+    pub num_ticks_per_picture: Option<u64>,
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub full_range: bool
+}
+
+impl SequenceHeader {
+    // Everything up to and including color_config()
+    pub fn parse(payload: &[u8]) -> Result<SequenceHeader, String> {
+        let mut r = BitReader::new(payload);
+
+        let seq_profile = r.read_u8(3)?;
+        if seq_profile > 2 {
+            return Err(format!("sequence header: seq_profile is {}", seq_profile));
+        }
+        let still_picture = r.read_bit()?;
+        let reduced_still_picture_header = r.read_bit()?;
+
+        let mut timing = None;
+        let mut num_ticks_per_picture = None;
+        let seq_level_idx;
+        let mut seq_tier = false;
+        if reduced_still_picture_header {
+            seq_level_idx = r.read_u8(5)?;
+        } else {
+            let mut decoder_model_info_present = false;
+            let mut buffer_delay_length = 0;
+            if r.read_bit()? {
+                let num_units_in_display_tick = r.read_u32(32)?;
+                let time_scale = r.read_u32(32)?;
+                if r.read_bit()? {
+                    num_ticks_per_picture = Some(read_uvlc(&mut r)? + 1);
+                }
+                timing = Some((time_scale, num_units_in_display_tick));
+
+                decoder_model_info_present = r.read_bit()?;
+                if decoder_model_info_present {
+                    buffer_delay_length = r.read_bits(5)? as usize + 1;
+                    r.skip_bits(32 + 5 + 5)?; // num_units_in_decoding_tick and two lengths
+                }
+            }
+
+            let initial_display_delay_present = r.read_bit()?;
+            let operating_points = r.read_u8(5)? + 1;
+            let mut first = None;
+            for _ in 0..operating_points {
+                r.skip_bits(12)?; // operating_point_idc
+                let level = r.read_u8(5)?;
+                let tier = level > 7 && r.read_bit()?;
+                if decoder_model_info_present && r.read_bit()? {
+                    // decoder and encoder buffer delays and low_delay_mode_flag
+                    r.skip_bits(2 * buffer_delay_length + 1)?;
+                }
+                if initial_display_delay_present && r.read_bit()? {
+                    r.skip_bits(4)?;
+                }
+                first = first.or(Some((level, tier)));
+            }
+            let (level, tier) = first.unwrap_or((0, false));
+            seq_level_idx = level;
+            seq_tier = tier;
+        }
+
+        let width_bits = r.read_bits(4)? as usize + 1;
+        let height_bits = r.read_bits(4)? as usize + 1;
+        let max_frame_width = r.read_u32(width_bits)? + 1;
+        let max_frame_height = r.read_u32(height_bits)? + 1;
+
+        if !reduced_still_picture_header && r.read_bit()? {
+            r.skip_bits(4 + 3)?; // frame id lengths
+        }
+        r.skip_bits(3)?; // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+
+        if !reduced_still_picture_header {
+            r.skip_bits(4)?; // interintra, masked compound, warped motion, dual filter
+            let enable_order_hint = r.read_bit()?;
+            if enable_order_hint {
+                r.skip_bits(2)?; // enable_jnt_comp, enable_ref_frame_mvs
+            }
+            let force_screen_content_tools = if r.read_bit()? { 2 } else { r.read_u8(1)? };
+            if force_screen_content_tools > 0 && !r.read_bit()? {
+                r.skip_bits(1)?; // seq_force_integer_mv
+            }
+            if enable_order_hint {
+                r.skip_bits(3)?; // order_hint_bits_minus_1
+            }
+        }
+        r.skip_bits(3)?; // enable_superres, enable_cdef, enable_restoration
+
+        // color_config()
+        let high_bitdepth = r.read_bit()?;
+        let bit_depth = if seq_profile == 2 && high_bitdepth {
+            if r.read_bit()? { 12 } else { 10 }
+        } else if high_bitdepth {
+            10
+        } else {
+            8
+        };
+        let monochrome = seq_profile != 1 && r.read_bit()?;
+
+        let (mut colour_primaries, mut transfer_characteristics, mut matrix_coefficients) = (2, 2, 2);
+        if r.read_bit()? {
+            colour_primaries         = r.read_u8(8)?;
+            transfer_characteristics = r.read_u8(8)?;
+            matrix_coefficients      = r.read_u8(8)?;
+        }
+
+        let full_range;
+        let (subsampling_x, subsampling_y);
+        if monochrome {
+            full_range = r.read_bit()?;
+            subsampling_x = true;
+            subsampling_y = true;
+        } else if colour_primaries == 1 && transfer_characteristics == 13 && matrix_coefficients == 0 {
+            // sRGB
+            full_range = true;
+            subsampling_x = false;
+            subsampling_y = false;
+        } else {
+            full_range = r.read_bit()?;
+            match seq_profile {
+                0 => { subsampling_x = true; subsampling_y = true; },
+                1 => { subsampling_x = false; subsampling_y = false; },
+                _ if bit_depth == 12 => {
+                    subsampling_x = r.read_bit()?;
+                    subsampling_y = subsampling_x && r.read_bit()?;
+                },
+                _ => { subsampling_x = true; subsampling_y = false; }
+            }
+        }
+
+        Ok(SequenceHeader {
+            seq_profile,
+            still_picture,
+            reduced_still_picture_header,
+            seq_level_idx,
+            seq_tier,
+            timing,
+            num_ticks_per_picture,
+            max_frame_width,
+            max_frame_height,
+            bit_depth,
+            monochrome,
+            subsampling_x,
+            subsampling_y,
+            colour_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+            full_range
+        })
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        let (time_scale, num_units_in_display_tick) = self.timing?;
+        let ticks = (num_units_in_display_tick as u64) * self.num_ticks_per_picture?;
+        if ticks == 0 || time_scale == 0 {
+            return None;
+        }
+        Some((time_scale as f64) / (ticks as f64))
+    }
+
+    pub fn chroma_format(&self) -> &'static str {
+        match (self.monochrome, self.subsampling_x, self.subsampling_y) {
+            (true, _, _)     => "4:0:0",
+            (_, true, true)  => "4:2:0",
+            (_, true, false) => "4:2:2",
+            _                => "4:4:4"
+        }
+    }
+}
+
+// uvlc() from the AV1 spec, a leading zero count then that many bits
+fn read_uvlc(r: &mut BitReader) -> Result<u64, String> {
+    let mut leading_zeros = 0;
+    while !r.read_bit()? {
+        leading_zeros += 1;
+    }
+    if leading_zeros >= 32 {
+        return Ok(u32::MAX as u64);
+    }
+    Ok(r.read_bits(leading_zeros)? + (1u64 << leading_zeros) - 1)
+}
+
+pub const KEY_FRAME: u8 = 0;
+
+// Whether a temporal unit has a shown key frame, which is what a sync sample
+// needs. A shown existing frame doesn't count, since whether it's a key frame
+// depends on decoder state.
+pub fn sample_is_keyframe(sample: &[u8], reduced_still_picture_header: bool) -> Result<bool, String> {
+    for obu in Obus::new(sample) {
+        let obu = obu?;
+        if obu.obu_type != OBU_FRAME && obu.obu_type != OBU_FRAME_HEADER {
+            continue;
+        }
+        if reduced_still_picture_header {
+            return Ok(true);
+        }
+
+        let mut r = BitReader::new(obu.payload);
+        if r.read_bit()? {
+            // show_existing_frame
+            continue;
+        }
+        let frame_type = r.read_u8(2)?;
+        let show_frame = r.read_bit()?;
+        if frame_type == KEY_FRAME && show_frame {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub fn sample_has_sequence_header(sample: &[u8]) -> Result<bool, String> {
+    for obu in Obus::new(sample) {
+        if obu?.obu_type == OBU_SEQUENCE_HEADER {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
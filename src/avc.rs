@@ -0,0 +1,555 @@
+use bitreader::*;
+use reader::*;
+
+/* ============================== H.264 / AVC ============================== */
+
+// AVCDecoderConfigurationRecord, the payload of avcC. avc1 entries carry
+// all their parameter sets here, avc3 entries may also send them in band.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AvcDecoderConfig {
+    pub configuration_version: u8,
+    pub profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub level_indication: u8,
+    // bytes in the length before each NAL unit of a sample: 1, 2 or 4
+    pub length_size: u8,
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+    // only in High profile records, and often left out even there
+    pub chroma_format: Option<u8>,
+    pub bit_depth_luma: Option<u8>,
+    pub bit_depth_chroma: Option<u8>,
+    pub sps_ext: Vec<Vec<u8>>
+}
+
+impl AvcDecoderConfig {
+    pub fn parse(data: &[u8]) -> Result<AvcDecoderConfig, String> {
+        let configuration_version = read_u8_at(data, 0)?;
+        if configuration_version != 1 {
+            return Err(format!("avcC: unknown configuration version {}", configuration_version));
+        }
+
+        let length_size = (read_u8_at(data, 4)? & 0x3) + 1;
+        if length_size == 3 {
+            return Err("avcC: NAL unit length size can't be 3".to_string());
+        }
+
+        let mut config = AvcDecoderConfig {
+            configuration_version,
+            profile_indication:    read_u8_at(data, 1)?,
+            profile_compatibility: read_u8_at(data, 2)?,
+            level_indication:      read_u8_at(data, 3)?,
+            length_size,
+            sps: vec![],
+            pps: vec![],
+            chroma_format: None,
+            bit_depth_luma: None,
+            bit_depth_chroma: None,
+            sps_ext: vec![]
+        };
+
+        let sps_count = read_u8_at(data, 5)? & 0x1f;
+        let mut offset = 6;
+        config.sps = read_parameter_sets(data, &mut offset, sps_count as usize)?;
+        let pps_count = read_u8_at(data, offset)?;
+        offset += 1;
+        config.pps = read_parameter_sets(data, &mut offset, pps_count as usize)?;
+
+        // the extension is optional in practice, even for these profiles
+        let high = [100, 110, 122, 144].contains(&config.profile_indication);
+        if high && offset + 4 <= data.len() {
+            config.chroma_format    = Some(read_u8_at(data, offset)? & 0x3);
+            config.bit_depth_luma   = Some((read_u8_at(data, offset + 1)? & 0x7) + 8);
+            config.bit_depth_chroma = Some((read_u8_at(data, offset + 2)? & 0x7) + 8);
+            let ext_count = read_u8_at(data, offset + 3)?;
+            offset += 4;
+            config.sps_ext = read_parameter_sets(data, &mut offset, ext_count as usize)?;
+        }
+
+        Ok(config)
+    }
+
+    // The first SPS decoded, which is the one nearly every file has
+    pub fn first_sps(&self) -> Result<Option<AvcSps>, String> {
+        match self.sps.first() {
+            Some(nal) => Ok(Some(AvcSps::parse(nal)?)),
+            None      => Ok(None)
+        }
+    }
+
+    pub fn first_pps(&self) -> Result<Option<AvcPps>, String> {
+        match self.pps.first() {
+            Some(nal) => Ok(Some(AvcPps::parse(nal)?)),
+            None      => Ok(None)
+        }
+    }
+}
+
+// Reads count parameter sets, each a 16 bit length and a NAL unit, leaving
+// offset after the last one
+pub fn read_parameter_sets(data: &[u8], offset: &mut usize, count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut sets = Vec::with_capacity(count.min(32));
+
+    for _ in 0..count {
+        let length = read_u16_at(data, *offset)? as usize;
+        sets.push(read_bytes_at(data, *offset + 2, length)?.to_vec());
+        *offset += 2 + length;
+    }
+
+    Ok(sets)
+}
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_IDR_SLICE: u8 = 5;
+pub const NAL_SEI: u8 = 6;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+
+pub fn nal_unit_type(nal: &[u8]) -> u8 {
+    nal.first().map(|b| b & 0x1f).unwrap_or(0)
+}
+
+// The NAL units of a sample in the length prefixed form used in mp4, where
+// length_size comes from avcC or hvcC.
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+    length_size: u8,
+    offset: usize,
+    failed: bool
+}
+
+impl<'a> NalUnits<'a> {
+    pub fn new(data: &'a [u8], length_size: u8) -> NalUnits<'a> {
+        NalUnits { data, length_size, offset: 0, failed: false }
+    }
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = Result<&'a [u8], String>;
+
+    fn next(&mut self) -> Option<Result<&'a [u8], String>> {
+        if self.failed || self.offset >= self.data.len() {
+            return None;
+        }
+
+        let length_size = self.length_size as usize;
+        let length = match length_size {
+            1 => read_u8_at(self.data, self.offset).map(|l| l as usize),
+            2 => read_u16_at(self.data, self.offset).map(|l| l as usize),
+            4 => read_u32_at(self.data, self.offset).map(|l| l as usize),
+            _ => Err(format!("NAL unit length size {} is not 1, 2 or 4", length_size))
+        };
+        let nal = length.and_then(|l| read_bytes_at(self.data, self.offset + length_size, l));
+
+        match nal {
+            Ok(nal) => {
+                self.offset += length_size + nal.len();
+                Some(Ok(nal))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(format!("NAL unit at {}: {}", self.offset, e)))
+            }
+        }
+    }
+}
+
+// Whether an avc sample starts a coded video sequence, i.e. has an IDR slice
+pub fn sample_has_idr(sample: &[u8], length_size: u8) -> Result<bool, String> {
+    for nal in NalUnits::new(sample, length_size) {
+        if nal_unit_type(nal?) == NAL_IDR_SLICE {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub fn sample_has_parameter_sets(sample: &[u8], length_size: u8) -> Result<bool, String> {
+    let mut sps = false;
+    let mut pps = false;
+    for nal in NalUnits::new(sample, length_size) {
+        match nal_unit_type(nal?) {
+            NAL_SPS => sps = true,
+            NAL_PPS => pps = true,
+            _       => {}
+        }
+    }
+    Ok(sps && pps)
+}
+
+// Colour description from the VUI, with the code points of ISO/IEC 23091-2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColourDescription {
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub full_range: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimingInfo {
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    // only signalled here by H.264
+    pub fixed_frame_rate: bool
+}
+
+// The video usability information of an H.264 or HEVC SPS. They share
+// everything up to the timing info.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vui {
+    // sample aspect ratio as width:height
+    pub sample_aspect_ratio: Option<(u16, u16)>,
+    pub video_format: Option<u8>,
+    pub colour: Option<ColourDescription>,
+    // HEVC only, left, right, top, bottom in pixels
+    pub default_display_window: Option<(u32, u32, u32, u32)>,
+    pub timing: Option<TimingInfo>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AvcSps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u8,
+    // 0 monochrome, 1 4:2:0, 2 4:2:2, 3 4:4:4
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane: bool,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub log2_max_frame_num: u8,
+    pub pic_order_cnt_type: u8,
+    pub max_num_ref_frames: u32,
+    pub frame_mbs_only: bool,
+    // in pixels before cropping
+    pub coded_width: u32,
+    pub coded_height: u32,
+    // left, right, top, bottom in pixels
+    pub crop: (u32, u32, u32, u32),
+    pub vui: Option<Vui>
+}
+
+// Profiles whose SPS has chroma format, bit depth and scaling matrices
+const HIGH_PROFILES: [u8; 13] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135];
+
+// Sample aspect ratios for aspect_ratio_idc 1 to 16, Table E-1
+const SAR_TABLE: [(u16, u16); 16] = [
+    (1, 1), (12, 11), (10, 11), (16, 11), (40, 33), (24, 11), (20, 11), (32, 11),
+    (80, 33), (18, 11), (15, 11), (64, 33), (160, 99), (4, 3), (3, 2), (2, 1)
+];
+
+impl AvcSps {
+    // nal is the whole NAL unit, header included
+    pub fn parse(nal: &[u8]) -> Result<AvcSps, String> {
+        if nal_unit_type(nal) != NAL_SPS {
+            return Err(format!("SPS: NAL unit type is {}", nal_unit_type(nal)));
+        }
+
+        let rbsp = remove_emulation_prevention(&nal[1..]);
+        let mut r = BitReader::new(&rbsp);
+
+        let profile_idc          = r.read_u8(8)?;
+        let constraint_flags     = r.read_u8(8)?;
+        let level_idc            = r.read_u8(8)?;
+        let seq_parameter_set_id = r.read_ue_max(31, "seq_parameter_set_id")? as u8;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+        if HIGH_PROFILES.contains(&profile_idc) {
+            chroma_format_idc = r.read_ue_max(3, "chroma_format_idc")? as u8;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = r.read_bit()?;
+            }
+            bit_depth_luma   = r.read_ue_max(6, "bit_depth_luma_minus8")? as u8 + 8;
+            bit_depth_chroma = r.read_ue_max(6, "bit_depth_chroma_minus8")? as u8 + 8;
+            r.skip_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+            if r.read_bit()? {
+                let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..lists {
+                    if r.read_bit()? {
+                        skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        let log2_max_frame_num = r.read_ue_max(12, "log2_max_frame_num_minus4")? as u8 + 4;
+        let pic_order_cnt_type = r.read_ue_max(2, "pic_order_cnt_type")? as u8;
+        match pic_order_cnt_type {
+            0 => { r.read_ue_max(12, "log2_max_pic_order_cnt_lsb_minus4")?; },
+            1 => {
+                r.skip_bits(1)?; // delta_pic_order_always_zero_flag
+                r.read_se()?;    // offset_for_non_ref_pic
+                r.read_se()?;    // offset_for_top_to_bottom_field
+                let cycle = r.read_ue_max(255, "num_ref_frames_in_pic_order_cnt_cycle")?;
+                for _ in 0..cycle {
+                    r.read_se()?;
+                }
+            },
+            _ => {}
+        }
+
+        let max_num_ref_frames = r.read_ue_max(16, "max_num_ref_frames")? as u32;
+        r.skip_bits(1)?; // gaps_in_frame_num_value_allowed_flag
+        let width_in_mbs = r.read_ue_max(1023, "pic_width_in_mbs_minus1")? as u32 + 1;
+        let height_in_map_units = r.read_ue_max(1023, "pic_height_in_map_units_minus1")? as u32 + 1;
+        let frame_mbs_only = r.read_bit()?;
+        if !frame_mbs_only {
+            r.skip_bits(1)?; // mb_adaptive_frame_field_flag
+        }
+        r.skip_bits(1)?; // direct_8x8_inference_flag
+
+        let coded_width = width_in_mbs * 16;
+        let coded_height = height_in_map_units * 16 * if frame_mbs_only { 1 } else { 2 };
+
+        let mut crop = (0, 0, 0, 0);
+        if r.read_bit()? {
+            // offsets count in chroma samples, and in field lines if interlaced
+            let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+            let (sub_width, sub_height) = match chroma_array_type {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1)
+            };
+            let unit_x = sub_width;
+            let unit_y = sub_height * if frame_mbs_only { 1 } else { 2 };
+
+            let left   = r.read_ue_max(coded_width as u64, "frame_crop_left_offset")? as u32;
+            let right  = r.read_ue_max(coded_width as u64, "frame_crop_right_offset")? as u32;
+            let top    = r.read_ue_max(coded_height as u64, "frame_crop_top_offset")? as u32;
+            let bottom = r.read_ue_max(coded_height as u64, "frame_crop_bottom_offset")? as u32;
+            crop = (left * unit_x, right * unit_x, top * unit_y, bottom * unit_y);
+
+            if crop.0 + crop.1 >= coded_width || crop.2 + crop.3 >= coded_height {
+                return Err(format!("SPS: cropping {:?} leaves nothing of {}x{}", crop, coded_width, coded_height));
+            }
+        }
+
+        let vui = if r.read_bit()? { Some(Vui::parse_avc(&mut r)?) } else { None };
+
+        Ok(AvcSps {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            seq_parameter_set_id,
+            chroma_format_idc,
+            separate_colour_plane,
+            bit_depth_luma,
+            bit_depth_chroma,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            max_num_ref_frames,
+            frame_mbs_only,
+            coded_width,
+            coded_height,
+            crop,
+            vui
+        })
+    }
+
+    pub fn cropped_width(&self) -> u32 { self.coded_width - self.crop.0 - self.crop.1 }
+
+    pub fn cropped_height(&self) -> u32 { self.coded_height - self.crop.2 - self.crop.3 }
+
+    // Frames per second from the VUI timing info. H.264 ticks are fields,
+    // so a frame lasts two of them.
+    pub fn frame_rate(&self) -> Option<f64> {
+        let timing = self.vui.as_ref()?.timing?;
+        if timing.num_units_in_tick == 0 || timing.time_scale == 0 {
+            return None;
+        }
+        Some((timing.time_scale as f64) / (2.0 * timing.num_units_in_tick as f64))
+    }
+
+    pub fn colour(&self) -> Option<ColourDescription> {
+        self.vui.as_ref()?.colour
+    }
+
+    pub fn chroma_format(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4"
+        }
+    }
+}
+
+impl Vui {
+    // Everything up to the timing info, the HRD and bitstream restrictions
+    // that follow aren't needed.
+    fn parse_avc(r: &mut BitReader) -> Result<Vui, String> {
+        let mut vui = Vui::parse_start(r)?;
+
+        if r.read_bit()? {
+            vui.timing = Some(TimingInfo {
+                num_units_in_tick: r.read_u32(32)?,
+                time_scale:        r.read_u32(32)?,
+                fixed_frame_rate:  r.read_bit()?
+            });
+        }
+
+        Ok(vui)
+    }
+
+    // The fields both codecs have, up to the chroma sample locations
+    pub fn parse_start(r: &mut BitReader) -> Result<Vui, String> {
+        let mut vui = Vui {
+            sample_aspect_ratio: None,
+            video_format: None,
+            colour: None,
+            default_display_window: None,
+            timing: None
+        };
+
+        if r.read_bit()? {
+            let aspect_ratio_idc = r.read_u8(8)?;
+            vui.sample_aspect_ratio = match aspect_ratio_idc {
+                255 => Some((r.read_u16(16)?, r.read_u16(16)?)),
+                1..=16 => Some(SAR_TABLE[aspect_ratio_idc as usize - 1]),
+                _ => None
+            };
+        }
+
+        if r.read_bit()? {
+            r.skip_bits(1)?; // overscan_appropriate_flag
+        }
+
+        if r.read_bit()? {
+            vui.video_format = Some(r.read_u8(3)?);
+            let full_range = r.read_bit()?;
+            // unspecified, as it is when there's no colour description
+            let mut colour = ColourDescription {
+                colour_primaries: 2,
+                transfer_characteristics: 2,
+                matrix_coefficients: 2,
+                full_range
+            };
+            if r.read_bit()? {
+                colour.colour_primaries         = r.read_u8(8)?;
+                colour.transfer_characteristics = r.read_u8(8)?;
+                colour.matrix_coefficients      = r.read_u8(8)?;
+            }
+            vui.colour = Some(colour);
+        }
+
+        if r.read_bit()? {
+            r.read_ue_max(5, "chroma_sample_loc_type_top_field")?;
+            r.read_ue_max(5, "chroma_sample_loc_type_bottom_field")?;
+        }
+
+        Ok(vui)
+    }
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Result<(), String> {
+    let mut last_scale = 8i64;
+    let mut next_scale = 8i64;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = r.read_se()?;
+            next_scale = (last_scale + delta + 256).rem_euclid(256);
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AvcPps {
+    pub pic_parameter_set_id: u8,
+    pub seq_parameter_set_id: u8,
+    // CABAC when set, CAVLC otherwise
+    pub entropy_coding_mode: bool,
+    pub num_slice_groups: u32,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub weighted_pred: bool,
+    pub weighted_bipred_idc: u8,
+    pub pic_init_qp: i32,
+    pub deblocking_filter_control_present: bool,
+    pub constrained_intra_pred: bool,
+    pub transform_8x8_mode: bool
+}
+
+impl AvcPps {
+    pub fn parse(nal: &[u8]) -> Result<AvcPps, String> {
+        if nal_unit_type(nal) != NAL_PPS {
+            return Err(format!("PPS: NAL unit type is {}", nal_unit_type(nal)));
+        }
+
+        let rbsp = remove_emulation_prevention(&nal[1..]);
+        let mut r = BitReader::new(&rbsp);
+
+        let pic_parameter_set_id = r.read_ue_max(255, "pic_parameter_set_id")? as u8;
+        let seq_parameter_set_id = r.read_ue_max(31, "seq_parameter_set_id")? as u8;
+        let entropy_coding_mode  = r.read_bit()?;
+        r.skip_bits(1)?; // bottom_field_pic_order_in_frame_present_flag
+
+        let num_slice_groups = r.read_ue_max(7, "num_slice_groups_minus1")? as u32 + 1;
+        if num_slice_groups > 1 {
+            // slice group maps only exist in Baseline and Extended
+            return Err("PPS: slice groups are not supported".to_string());
+        }
+
+        let num_ref_idx_l0_default_active = r.read_ue_max(31, "num_ref_idx_l0_default_active_minus1")? as u32 + 1;
+        let num_ref_idx_l1_default_active = r.read_ue_max(31, "num_ref_idx_l1_default_active_minus1")? as u32 + 1;
+        let weighted_pred       = r.read_bit()?;
+        let weighted_bipred_idc = r.read_u8(2)?;
+        let pic_init_qp_minus26 = r.read_se()?;
+        if !(-62..=25).contains(&pic_init_qp_minus26) {
+            return Err(format!("PPS: pic_init_qp_minus26 is {}", pic_init_qp_minus26));
+        }
+        let pic_init_qp         = 26 + pic_init_qp_minus26 as i32;
+        r.read_se()?; // pic_init_qs_minus26
+        r.read_se()?; // chroma_qp_index_offset
+        let deblocking_filter_control_present = r.read_bit()?;
+        let constrained_intra_pred            = r.read_bit()?;
+        r.skip_bits(1)?; // redundant_pic_cnt_present_flag
+
+        let transform_8x8_mode = r.more_rbsp_data() && r.read_bit()?;
+
+        Ok(AvcPps {
+            pic_parameter_set_id,
+            seq_parameter_set_id,
+            entropy_coding_mode,
+            num_slice_groups,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            weighted_pred,
+            weighted_bipred_idc,
+            pic_init_qp,
+            deblocking_filter_control_present,
+            constrained_intra_pred,
+            transform_8x8_mode
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_profile_1080p_sps() {
+        // x264's High 4.0 SPS for 1920x1080, coded as 1920x1088 and cropped
+        let nal = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0xc0, 0x44, 0x00,
+            0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xf0, 0x3c, 0x60, 0xc6, 0x58
+        ];
+        let sps = AvcSps::parse(&nal).unwrap();
+
+        assert_eq!((sps.profile_idc, sps.level_idc, sps.chroma_format_idc), (100, 40, 1));
+        assert_eq!((sps.coded_width, sps.coded_height), (1920, 1088));
+        assert_eq!((sps.cropped_width(), sps.cropped_height()), (1920, 1080));
+        assert_eq!(sps.vui.as_ref().and_then(|v| v.sample_aspect_ratio), Some((1, 1)));
+        assert_eq!(sps.frame_rate(), Some(30.0));
+        assert_eq!(sps.colour(), None);
+    }
+}
@@ -2,21 +2,81 @@ use std::env;
 
 extern crate trim;
 use trim::videotrim;
+use trim::writer::OutputOptions;
+
+const USAGE: &str = "Usage: video-trimmer [--interleave ms] video-file-in video-file-out start stop
+       video-trimmer [--interleave ms] --remux video-file-in video-file-out
+       video-trimmer --info video-file-in
+       video-trimmer --rotate degrees video-file-in video-file-out";
 
 fn main() {
-    let mut args = env::args().skip(1);
+    let mut options = OutputOptions::default();
+    let mut remux = false;
+    let mut info = false;
+    let mut rotate = None;
+    let mut args = vec![];
+
+    let mut all_args = env::args().skip(1);
+    while let Some(arg) = all_args.next() {
+        match arg.as_str() {
+            "--interleave" => {
+                let ms = all_args.next().expect(USAGE).parse::<f64>().expect("Interleave was not a number of ms");
+                options.interleave = Some(ms / 1000.0);
+            },
+            "--remux" => remux = true,
+            "--info"  => info = true,
+            "--rotate" => {
+                let degrees = all_args.next().expect(USAGE).parse::<u16>().expect("Rotation was not a number of degrees");
+                rotate = Some(degrees);
+            },
+            _         => args.push(arg)
+        }
+    }
+
+    if info {
+        if args.is_empty() {
+            panic!("{}", USAGE);
+        }
+
+        if let Err(e) = videotrim::describe_video(&args[0]) {
+            println!("Error while reading video: {}", e);
+        }
+        return;
+    }
+
+    if let Some(degrees) = rotate {
+        if args.len() < 2 {
+            panic!("{}", USAGE);
+        }
+
+        match videotrim::set_rotation(&args[0], &args[1], degrees) {
+            Ok(_)  => println!("Video rotated!"),
+            Err(e) => println!("Error while rotating video: {}", e)
+        };
+        return;
+    }
+
+    if remux {
+        if args.len() < 2 {
+            panic!("{}", USAGE);
+        }
+
+        match videotrim::remux_video(&args[0], &args[1], options) {
+            Ok(_)  => println!("Video remuxed!"),
+            Err(e) => println!("Error while remuxing video: {}", e)
+        };
+        return;
+    }
 
-    if args.len() < 3 {
-        panic!("Usage: video-trimmer video-file-in video-file-out start stop [moov position]");
+    if args.len() < 4 {
+        panic!("{}", USAGE);
     }
 
     // would expect since we check the length these args exist
-    let video_in = args.nth(0).unwrap();
-    let video_out = args.nth(0).unwrap();
-    let start = args.nth(0).unwrap().parse::<f32>().expect("Start was not an f32");
-    let stop = args.nth(0).unwrap().parse::<f32>().expect("Stop was not an f32");
+    let start = args[2].parse::<f32>().expect("Start was not an f32");
+    let stop = args[3].parse::<f32>().expect("Stop was not an f32");
 
-    match videotrim::trim_video(&video_in, &video_out, start, stop) {
+    match videotrim::trim_video_with_options(&args[0], &args[1], start, stop, options) {
         Ok(_)  => println!("Video trimmed!"),
         Err(e) => println!("Error while trimming video: {}", e)
     };
@@ -0,0 +1,225 @@
+/* ================================ Bit reader ================================ */
+
+// Reads big endian bit fields, as found in codec headers such as H.264 and
+// HEVC parameter sets, AV1 sequence headers and AudioSpecificConfig. Positions
+// are in bits from the start of the slice. Parameter sets need their
+// emulation prevention bytes removed first, see remove_emulation_prevention.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, position: 0 }
+    }
+
+    pub fn get_position(&self) -> usize { self.position }
+
+    pub fn bits_remaining(&self) -> usize { self.data.len() * 8 - self.position }
+
+    pub fn is_byte_aligned(&self) -> bool { self.position.is_multiple_of(8) }
+
+    // Skips to the start of the next byte, if not already at one
+    pub fn byte_align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Result<(), String> {
+        if count > self.bits_remaining() {
+            return Err(format!("skip_bits: need {} bits have {}", count, self.bits_remaining()));
+        }
+
+        self.position += count;
+        Ok(())
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, String> {
+        if self.bits_remaining() < 1 {
+            return Err("read_bit: no bits left".to_string());
+        }
+
+        let byte = self.data[self.position / 8];
+        let bit = (byte >> (7 - (self.position % 8))) & 1;
+        self.position += 1;
+
+        Ok(bit == 1)
+    }
+
+    // Reads up to 64 bits, most significant first
+    pub fn read_bits(&mut self, count: usize) -> Result<u64, String> {
+        if count > 64 {
+            return Err(format!("read_bits: can't read {} bits at once", count));
+        }
+        if count > self.bits_remaining() {
+            return Err(format!("read_bits: need {} bits have {}", count, self.bits_remaining()));
+        }
+
+        let mut r: u64 = 0;
+        let mut left = count;
+        while left > 0 {
+            let byte = self.data[self.position / 8] as u64;
+            let offset = self.position % 8;
+            let take = (8 - offset).min(left);
+            let bits = (byte >> (8 - offset - take)) & ((1 << take) - 1);
+
+            r = (r << take) | bits;
+            self.position += take;
+            left -= take;
+        }
+
+        Ok(r)
+    }
+
+    pub fn read_u8(&mut self, count: usize) -> Result<u8, String> {
+        if count > 8 {
+            return Err(format!("read_u8: {} bits won't fit", count));
+        }
+        Ok(self.read_bits(count)? as u8)
+    }
+
+    pub fn read_u16(&mut self, count: usize) -> Result<u16, String> {
+        if count > 16 {
+            return Err(format!("read_u16: {} bits won't fit", count));
+        }
+        Ok(self.read_bits(count)? as u16)
+    }
+
+    pub fn read_u32(&mut self, count: usize) -> Result<u32, String> {
+        if count > 32 {
+            return Err(format!("read_u32: {} bits won't fit", count));
+        }
+        Ok(self.read_bits(count)? as u32)
+    }
+
+    // Unsigned Exp-Golomb, ue(v)
+    pub fn read_ue(&mut self) -> Result<u64, String> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 63 {
+                return Err("read_ue: more than 63 leading zero bits".to_string());
+            }
+        }
+
+        if leading_zeros == 0 {
+            return Ok(0);
+        }
+
+        let suffix = self.read_bits(leading_zeros)?;
+        Ok(((1u64 << leading_zeros) - 1) + suffix)
+    }
+
+    // Signed Exp-Golomb, se(v): 0, 1, -1, 2, -2, ...
+    pub fn read_se(&mut self) -> Result<i64, String> {
+        let code = self.read_ue()?;
+        let magnitude = code.div_ceil(2) as i64;
+
+        if code % 2 == 1 { Ok(magnitude) } else { Ok(-magnitude) }
+    }
+
+    // ue(v) that must not exceed max, as most syntax elements are bounded
+    pub fn read_ue_max(&mut self, max: u64, what: &str) -> Result<u64, String> {
+        let value = self.read_ue()?;
+        if value > max {
+            return Err(format!("{} is {} which is more than {}", what, value, max));
+        }
+        Ok(value)
+    }
+
+    // Whether there's anything other than rbsp_trailing_bits left
+    pub fn more_rbsp_data(&self) -> bool {
+        let mut last = self.data.len();
+        while last > 0 && self.data[last - 1] == 0 {
+            last -= 1;
+        }
+        if last == 0 {
+            return false;
+        }
+
+        // position of the stop bit, the last 1 in the data
+        let stop_bit = last * 8 - 1 - (self.data[last - 1].trailing_zeros() as usize);
+        self.position < stop_bit
+    }
+}
+
+// Turns a NAL unit payload (EBSP) into its raw bytes (RBSP) by dropping the
+// 0x03 in every 0x00 0x00 0x03 sequence.
+pub fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &b in data {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        r.push(b);
+    }
+
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 010 011 00100 00101 00110 00111 0001000, the codes for 0 to 7
+    const CODES: [u8; 5] = [0xa6, 0x42, 0x98, 0xe2, 0x00];
+
+    #[test]
+    fn unsigned_exp_golomb() {
+        let mut r = BitReader::new(&CODES);
+        for expected in 0..8 {
+            assert_eq!(r.read_ue().unwrap(), expected);
+        }
+        assert_eq!(r.get_position(), 34);
+
+        // 8 leading zeros then 1 and 00000000
+        let mut r = BitReader::new(&[0x00, 0x80, 0x00]);
+        assert_eq!(r.read_ue().unwrap(), 255);
+        assert_eq!(r.get_position(), 17);
+    }
+
+    #[test]
+    fn unsigned_exp_golomb_runs_out() {
+        assert!(BitReader::new(&[0x00]).read_ue().is_err());
+        assert!(BitReader::new(&[0x01]).read_ue().is_err());
+    }
+
+    #[test]
+    fn signed_exp_golomb() {
+        let mut r = BitReader::new(&CODES);
+        for &expected in &[0, 1, -1, 2, -2, 3, -3, 4] {
+            assert_eq!(r.read_se().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn more_rbsp_data_stops_at_the_stop_bit() {
+        // ue 1 then the stop bit
+        let data = [0x50];
+        let mut r = BitReader::new(&data);
+        assert!(r.more_rbsp_data());
+        assert_eq!(r.read_ue().unwrap(), 1);
+        assert!(!r.more_rbsp_data());
+
+        // trailing zero bytes after the stop bit don't count
+        let mut r = BitReader::new(&[0xc0, 0x00, 0x00]);
+        assert!(r.more_rbsp_data());
+        r.read_bit().unwrap();
+        assert!(!r.more_rbsp_data());
+
+        assert!(!BitReader::new(&[0x00, 0x00]).more_rbsp_data());
+    }
+
+    #[test]
+    fn emulation_prevention_is_removed() {
+        assert_eq!(remove_emulation_prevention(&[0x00, 0x00, 0x03, 0x01]), [0x00, 0x00, 0x01]);
+        assert_eq!(remove_emulation_prevention(&[0x00, 0x00, 0x03, 0x00, 0x00, 0x03]), [0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(remove_emulation_prevention(&[0x00, 0x00, 0x03, 0x03]), [0x00, 0x00, 0x03]);
+        assert_eq!(remove_emulation_prevention(&[0x00, 0x03, 0x00, 0x03]), [0x00, 0x03, 0x00, 0x03]);
+    }
+}
@@ -0,0 +1,270 @@
+use av1::Av1Config;
+use dovi::*;
+use esds::*;
+use hevc::HevcDecoderConfig;
+use stsd::*;
+use vp9::VpCodecConfig;
+
+/* ================================ Codec strings ================================ */
+
+// The RFC 6381 codecs parameter for a sample entry, as used in MIME types
+// and HLS/DASH playlists, e.g. "avc1.640028" or "mp4a.40.2". Entries whose
+// codec takes no parameters give their type, in the case the registrations
+// use.
+pub fn codec_string(entry: &SampleEntry) -> Result<String, String> {
+    if DOLBY_VISION_ENTRIES.contains(&entry.typ.as_str()) {
+        if let Some(dovi) = entry.dolby_vision_config()? {
+            return Ok(format!("{}.{:02}.{:02}", entry.typ, dovi.profile, dovi.level));
+        }
+    }
+    if let Some(avcc) = entry.avc_config()? {
+        return Ok(format!("{}.{:02X}{:02X}{:02X}", entry.typ, avcc.profile_indication,
+                          avcc.profile_compatibility, avcc.level_indication));
+    }
+    if let Some(hvcc) = entry.hevc_config()? {
+        return Ok(format!("{}.{}", entry.typ, hevc_parameters(&hvcc)));
+    }
+    if let Some(av1c) = entry.av1_config()? {
+        return Ok(format!("{}.{}", entry.typ, av1_parameters(&av1c)?));
+    }
+    if let Some(vpcc) = entry.vp_config()? {
+        return Ok(format!("{}.{}", entry.typ, vp_parameters(&vpcc)));
+    }
+    if let Some(esds) = entry.esds()? {
+        return mpeg4_codec_string(entry, &esds);
+    }
+
+    Ok(match entry.typ.as_str() {
+        "Opus" => "opus".to_string(),
+        "fLaC" => "flac".to_string(),
+        ".mp3" => "mp4a.6B".to_string(),
+        typ    => typ.trim_end().to_string()
+    })
+}
+
+// The Dolby Vision codec string of an entry whose own type is another
+// codec's, as HLS gives in SUPPLEMENTAL-CODECS, e.g. "dvh1.08.06" for an
+// hvc1 entry with a dvvC
+pub fn supplemental_codec_string(entry: &SampleEntry) -> Result<Option<String>, String> {
+    if DOLBY_VISION_ENTRIES.contains(&entry.typ.as_str()) {
+        return Ok(None);
+    }
+    let dovi = match entry.dolby_vision_config()? {
+        Some(dovi) => dovi,
+        None       => return Ok(None)
+    };
+
+    let typ = match entry.typ.as_str() {
+        "avc1" => "dva1",
+        "avc3" => "dvav",
+        "hvc1" => "dvh1",
+        "hev1" => "dvhe",
+        "av01" => "dav1",
+        _      => return Ok(None)
+    };
+    Ok(Some(format!("{}.{:02}.{:02}", typ, dovi.profile, dovi.level)))
+}
+
+// Comma separated, for a codecs parameter covering several tracks
+pub fn codecs_parameter(codecs: &[String]) -> String {
+    let mut unique: Vec<&str> = vec![];
+    for codec in codecs {
+        if !unique.contains(&codec.as_str()) {
+            unique.push(codec);
+        }
+    }
+    unique.join(",")
+}
+
+// ISO/IEC 14496-15 annex E: profile space and profile, the compatibility
+// flags bit reversed, tier and level, then the constraint bytes without
+// the trailing zero ones. e.g. "2.4.L153.B0"
+fn hevc_parameters(hvcc: &HevcDecoderConfig) -> String {
+    let space = ["", "A", "B", "C"][hvcc.general_profile_space as usize & 3];
+    let tier = if hvcc.general_tier_flag { "H" } else { "L" };
+
+    let mut parameters = format!("{}{}.{:X}.{}{}", space, hvcc.general_profile_idc,
+                                 hvcc.general_profile_compatibility_flags.reverse_bits(),
+                                 tier, hvcc.general_level_idc);
+
+    let constraints: Vec<u8> = (0..6).map(|i| (hvcc.general_constraint_indicator_flags >> (40 - 8 * i)) as u8).collect();
+    let used = constraints.iter().rposition(|&b| b != 0).map(|i| i + 1).unwrap_or(0);
+    for byte in &constraints[..used] {
+        parameters.push_str(&format!(".{:02X}", byte));
+    }
+
+    parameters
+}
+
+// The AV1 codec ISO media file format binding: profile, level and tier,
+// bit depth, and when they aren't the defaults the monochrome flag, chroma
+// subsampling, colour description and range. e.g. "0.08M.10"
+fn av1_parameters(av1c: &Av1Config) -> Result<String, String> {
+    let tier = if av1c.seq_tier_0 { "H" } else { "M" };
+    let mut parameters = format!("{}.{:02}{}.{:02}", av1c.seq_profile, av1c.seq_level_idx_0, tier, av1c.bit_depth());
+
+    let header = match av1c.sequence_header()? {
+        Some(header) => header,
+        None         => return Ok(parameters)
+    };
+    let subsampling = format!("{}{}{}", header.subsampling_x as u8, header.subsampling_y as u8,
+                              if header.subsampling_x && header.subsampling_y { av1c.chroma_sample_position } else { 0 });
+    let colours = [header.colour_primaries, header.transfer_characteristics, header.matrix_coefficients];
+
+    if header.monochrome || subsampling != "110" || !default_colours(&colours) || header.full_range {
+        parameters.push_str(&format!(".{}.{}.{:02}.{:02}.{:02}.{}", header.monochrome as u8, subsampling,
+                                     colours[0], colours[1], colours[2], header.full_range as u8));
+    }
+    Ok(parameters)
+}
+
+// VP codec ISO media file format binding: profile, level and bit depth, and
+// when they aren't the defaults the chroma subsampling, colour description
+// and range. e.g. "00.41.08"
+fn vp_parameters(vpcc: &VpCodecConfig) -> String {
+    let mut parameters = format!("{:02}.{:02}.{:02}", vpcc.profile, vpcc.level, vpcc.bit_depth);
+
+    let colours = [vpcc.colour_primaries, vpcc.transfer_characteristics, vpcc.matrix_coefficients];
+    if vpcc.chroma_subsampling != 1 || !default_colours(&colours) || vpcc.full_range {
+        parameters.push_str(&format!(".{:02}.{:02}.{:02}.{:02}.{:02}", vpcc.chroma_subsampling,
+                                     colours[0], colours[1], colours[2], vpcc.full_range as u8));
+    }
+    parameters
+}
+
+// BT.709 primaries, transfer and matrix, which the short forms stand for
+fn default_colours(colours: &[u8]) -> bool {
+    colours.iter().all(|&c| c == 1)
+}
+
+// mp4a and mp4v: the object type indication in hex, then for MPEG-4 audio
+// the audio object type and for MPEG-4 visual the profile and level, both
+// in decimal. HE-AAC is given as its SBR or PS object type.
+fn mpeg4_codec_string(entry: &SampleEntry, esds: &EsDescriptor) -> Result<String, String> {
+    let config = match esds.decoder_config {
+        Some(ref config) => config,
+        None             => return Ok(entry.typ.clone())
+    };
+    let mut codec = format!("{}.{:02X}", entry.typ, config.object_type_indication);
+
+    if let Some(asc) = esds.audio_specific_config()? {
+        let object_type = match (asc.audio_object_type, asc.sbr_present, asc.ps_present) {
+            (AOT_AAC_LC, Some(true), Some(true)) => AOT_PS,
+            (AOT_AAC_LC, Some(true), _)          => AOT_SBR,
+            (object_type, _, _)                  => object_type
+        };
+        codec.push_str(&format!(".{}", object_type));
+    } else if config.object_type_indication == OBJECT_TYPE_MPEG4_VISUAL {
+        // the profile_and_level_indication after a visual object sequence start code
+        let info = &config.decoder_specific_info;
+        if info.len() > 4 && info[..4] == [0, 0, 1, 0xb0] {
+            codec.push_str(&format!(".{}", info[4]));
+        }
+    }
+
+    Ok(codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ParseLimits;
+    use reader::AtomReader;
+
+    fn atom(typ: &str, payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(typ.as_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    // A sample entry of typ with zeroed fields apart from the data reference
+    // index, followed by child
+    fn entry(typ: &str, fields: usize, child: &[u8]) -> SampleEntry {
+        let mut payload = vec![0u8; fields];
+        payload[7] = 1;
+        payload.extend_from_slice(child);
+
+        let data = atom(typ, &payload);
+        let reader = AtomReader::new(&data);
+        SampleEntry::parse(&reader.atom_at(0).unwrap(), 0, &ParseLimits::default()).unwrap()
+    }
+
+    fn visual(typ: &str, config: &str, payload: &[u8]) -> SampleEntry {
+        entry(typ, 78, &atom(config, payload))
+    }
+
+    #[test]
+    fn avc() {
+        let entry = visual("avc1", "avcC", &[1, 0x64, 0, 0x28, 0xff, 0xe0, 0]);
+        assert_eq!(codec_string(&entry).unwrap(), "avc1.640028");
+    }
+
+    #[test]
+    fn hevc() {
+        let hvcc = [
+            1, 0x02, 0x20, 0, 0, 0, 0xb0, 0, 0, 0, 0, 0, 153,
+            0xf0, 0, 0xfc, 0xfd, 0xf8, 0xf8, 0, 0, 0x0f, 0
+        ];
+        let entry = visual("hvc1", "hvcC", &hvcc);
+        assert_eq!(codec_string(&entry).unwrap(), "hvc1.2.4.L153.B0");
+    }
+
+    #[test]
+    fn aac() {
+        // ES_Descriptor holding a DecoderConfigDescriptor for MPEG-4 audio
+        // with an AAC LC, 44.1kHz stereo AudioSpecificConfig
+        let esds = [
+            0, 0, 0, 0,
+            0x03, 25, 0, 1, 0,
+            0x04, 17, 0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x05, 2, 0x12, 0x10,
+            0x06, 1, 0x02
+        ];
+        let entry = entry("mp4a", 28, &atom("esds", &esds));
+        assert_eq!(codec_string(&entry).unwrap(), "mp4a.40.2");
+    }
+
+    #[test]
+    fn av1() {
+        let entry = visual("av01", "av1C", &[0x81, 0x08, 0x4c, 0]);
+        assert_eq!(codec_string(&entry).unwrap(), "av01.0.08M.10");
+    }
+
+    #[test]
+    fn av1_unspecified_colours() {
+        // 1920x1080 Main profile 10 bit sequence header with colour
+        // primaries, transfer and matrix all 2 (unspecified)
+        let obu = [
+            0x0a, 0x0e, 0x00, 0x00, 0x00, 0x42, 0xab, 0xbf, 0xc3, 0x73,
+            0xff, 0xe7, 0x40, 0x80, 0x80, 0x81
+        ];
+        let mut av1c = vec![0x81, 0x08, 0x4c, 0];
+        av1c.extend_from_slice(&obu);
+
+        let header = Av1Config::parse(&av1c).unwrap().sequence_header().unwrap().unwrap();
+        assert_eq!((header.max_frame_width, header.max_frame_height), (1920, 1080));
+
+        let entry = visual("av01", "av1C", &av1c);
+        assert_eq!(codec_string(&entry).unwrap(), "av01.0.08M.10.0.110.02.02.02.0");
+    }
+
+    #[test]
+    fn vp9() {
+        let entry = visual("vp09", "vpcC", &[1, 0, 0, 0, 0, 41, 0x82, 1, 1, 1, 0, 0]);
+        assert_eq!(codec_string(&entry).unwrap(), "vp09.00.41.08");
+    }
+
+    #[test]
+    fn vp9_non_default_subsampling() {
+        // 4:2:0 with vertical chroma siting, the default is 4:2:0 colocated
+        let entry = visual("vp09", "vpcC", &[1, 0, 0, 0, 0, 41, 0x80, 1, 1, 1, 0, 0]);
+        assert_eq!(codec_string(&entry).unwrap(), "vp09.00.41.08.00.01.01.01.00");
+    }
+
+    #[test]
+    fn vp9_unspecified_colours() {
+        let entry = visual("vp09", "vpcC", &[1, 0, 0, 0, 0, 41, 0x82, 2, 2, 2, 0, 0]);
+        assert_eq!(codec_string(&entry).unwrap(), "vp09.00.41.08.01.02.02.02.00");
+    }
+}
@@ -0,0 +1,64 @@
+use bitreader::*;
+
+/* ================================ Dolby Vision ================================ */
+
+// The boxes a Dolby Vision configuration can be in: dvcC for profiles up to
+// 7, dvvC for 8 to 10 and dvwC for the ones after
+pub const DOVI_CONFIG_BOXES: [&str; 3] = ["dvcC", "dvvC", "dvwC"];
+
+// Dolby Vision's own sample entry types, for streams only a Dolby Vision
+// decoder can play
+pub const DOLBY_VISION_ENTRIES: [&str; 5] = ["dvh1", "dvhe", "dvav", "dva1", "dav1"];
+
+// DOVIDecoderConfigurationRecord
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DoviDecoderConfig {
+    pub version_major: u8,
+    pub version_minor: u8,
+    pub profile: u8,
+    pub level: u8,
+    // which of the reference processing unit, enhancement layer and base
+    // layer the stream carries
+    pub rpu_present: bool,
+    pub el_present: bool,
+    pub bl_present: bool,
+    // what the base layer can be played as without Dolby Vision: 0 none,
+    // 1 HDR10, 2 SDR, 4 HLG...
+    pub bl_signal_compatibility_id: u8
+}
+
+impl DoviDecoderConfig {
+    pub fn parse(data: &[u8]) -> Result<DoviDecoderConfig, String> {
+        let mut r = BitReader::new(data);
+
+        Ok(DoviDecoderConfig {
+            version_major:              r.read_u8(8)?,
+            version_minor:              r.read_u8(8)?,
+            profile:                    r.read_u8(7)?,
+            level:                      r.read_u8(6)?,
+            rpu_present:                r.read_bit()?,
+            el_present:                 r.read_bit()?,
+            bl_present:                 r.read_bit()?,
+            bl_signal_compatibility_id: r.read_u8(4)?
+        })
+    }
+
+    // e.g. "8.1" for profile 8 with an HDR10 compatible base layer
+    pub fn profile_name(&self) -> String {
+        match self.bl_signal_compatibility_id {
+            0  => format!("{}", self.profile),
+            id => format!("{}.{}", self.profile, id)
+        }
+    }
+
+    pub fn compatibility(&self) -> &'static str {
+        match self.bl_signal_compatibility_id {
+            0 => "none",
+            1 => "HDR10",
+            2 => "SDR",
+            4 => "HLG",
+            6 => "UHD Blu-ray",
+            _ => "reserved"
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use bitreader::*;
+use reader::*;
+
+/* ======================= MPEG-4 elementary stream descriptors ======================= */
+
+pub const ES_DESCRIPTOR_TAG: u8 = 0x03;
+pub const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+pub const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+pub const SL_CONFIG_DESCRIPTOR_TAG: u8 = 0x06;
+
+// objectTypeIndication values for the codecs we care about
+pub const OBJECT_TYPE_MPEG4_VISUAL: u8 = 0x20;
+pub const OBJECT_TYPE_MPEG4_AUDIO: u8 = 0x40;
+pub const OBJECT_TYPE_MPEG2_AAC_LC: u8 = 0x67;
+pub const OBJECT_TYPE_MP3: u8 = 0x6b;
+
+// The ES_Descriptor in esds, as used by mp4a and mp4v entries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EsDescriptor {
+    pub es_id: u16,
+    pub depends_on_es_id: Option<u16>,
+    pub url: Option<String>,
+    pub ocr_es_id: Option<u16>,
+    pub stream_priority: u8,
+    pub decoder_config: Option<DecoderConfigDescriptor>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecoderConfigDescriptor {
+    pub object_type_indication: u8,
+    // 4 for visual, 5 for audio
+    pub stream_type: u8,
+    pub up_stream: bool,
+    pub buffer_size_db: u32,
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+    // the DecoderSpecificInfo, an AudioSpecificConfig for MPEG-4 audio
+    pub decoder_specific_info: Vec<u8>
+}
+
+impl EsDescriptor {
+    // data is the payload of esds, version and flags included
+    pub fn parse(data: &[u8]) -> Result<EsDescriptor, String> {
+        let version = read_u8_at(data, 0)?;
+        if version != 0 {
+            return Err(format!("esds: unknown version {}", version));
+        }
+
+        let (tag, es, _) = read_descriptor(data, 4)?;
+        if tag != ES_DESCRIPTOR_TAG {
+            return Err(format!("esds: expected an ES_Descriptor, found tag {:#x}", tag));
+        }
+
+        let flags = read_u8_at(es, 2)?;
+        let mut descriptor = EsDescriptor {
+            es_id: read_u16_at(es, 0)?,
+            depends_on_es_id: None,
+            url: None,
+            ocr_es_id: None,
+            stream_priority: flags & 0x1f,
+            decoder_config: None
+        };
+
+        let mut offset = 3;
+        if flags & 0x80 != 0 {
+            descriptor.depends_on_es_id = Some(read_u16_at(es, offset)?);
+            offset += 2;
+        }
+        if flags & 0x40 != 0 {
+            let length = read_u8_at(es, offset)? as usize;
+            descriptor.url = Some(String::from_utf8_lossy(read_bytes_at(es, offset + 1, length)?).to_string());
+            offset += 1 + length;
+        }
+        if flags & 0x20 != 0 {
+            descriptor.ocr_es_id = Some(read_u16_at(es, offset)?);
+            offset += 2;
+        }
+
+        while offset < es.len() {
+            let (tag, body, next) = read_descriptor(es, offset)?;
+            if tag == DECODER_CONFIG_DESCRIPTOR_TAG {
+                descriptor.decoder_config = Some(DecoderConfigDescriptor::parse(body)?);
+            }
+            offset = next;
+        }
+
+        Ok(descriptor)
+    }
+
+    pub fn audio_specific_config(&self) -> Result<Option<AudioSpecificConfig>, String> {
+        match self.decoder_config {
+            Some(ref config) if config.object_type_indication == OBJECT_TYPE_MPEG4_AUDIO => {
+                Ok(Some(AudioSpecificConfig::parse(&config.decoder_specific_info)?))
+            },
+            _ => Ok(None)
+        }
+    }
+}
+
+impl DecoderConfigDescriptor {
+    fn parse(data: &[u8]) -> Result<DecoderConfigDescriptor, String> {
+        let stream = read_u8_at(data, 1)?;
+        let mut config = DecoderConfigDescriptor {
+            object_type_indication: read_u8_at(data, 0)?,
+            stream_type:            stream >> 2,
+            up_stream:              stream & 0x2 != 0,
+            buffer_size_db:         read_u24_at(data, 2)?,
+            max_bitrate:            read_u32_at(data, 5)?,
+            avg_bitrate:            read_u32_at(data, 9)?,
+            decoder_specific_info:  vec![]
+        };
+
+        let mut offset = 13;
+        while offset < data.len() {
+            let (tag, body, next) = read_descriptor(data, offset)?;
+            if tag == DECODER_SPECIFIC_INFO_TAG {
+                config.decoder_specific_info = body.to_vec();
+            }
+            offset = next;
+        }
+
+        Ok(config)
+    }
+}
+
+// Reads the descriptor at offset, returning its tag, its body and where the
+// next one starts. The size takes 1 to 4 bytes of 7 bits each.
+pub fn read_descriptor(data: &[u8], offset: usize) -> Result<(u8, &[u8], usize), String> {
+    let tag = read_u8_at(data, offset)?;
+    let mut size = 0usize;
+    let mut position = offset + 1;
+    for i in 0..4 {
+        let byte = read_u8_at(data, position)?;
+        size = (size << 7) | ((byte & 0x7f) as usize);
+        position += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if i == 3 {
+            return Err(format!("descriptor {:#x}: size longer than 4 bytes", tag));
+        }
+    }
+
+    let body = read_bytes_at(data, position, size)?;
+    Ok((tag, body, position + size))
+}
+
+/* ============================== AudioSpecificConfig ============================== */
+
+pub const AOT_AAC_MAIN: u8 = 1;
+pub const AOT_AAC_LC: u8 = 2;
+pub const AOT_SBR: u8 = 5;
+pub const AOT_PS: u8 = 29;
+pub const AOT_ER_AAC_LD: u8 = 23;
+pub const AOT_ER_AAC_ELD: u8 = 39;
+pub const AOT_USAC: u8 = 42;
+
+const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350
+];
+
+// The channel counts of channelConfiguration 1 to 14
+const CHANNELS: [u8; 14] = [1, 2, 3, 4, 5, 6, 8, 0, 0, 0, 7, 8, 24, 8];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    // the core object type, 2 for AAC LC even when SBR is signalled
+    pub audio_object_type: u8,
+    pub sampling_frequency: u32,
+    // 0 means the channels are in a program config element
+    pub channel_configuration: u8,
+    pub channel_count: u8,
+    // 960 instead of 1024 (or 480 instead of 512) samples per frame
+    pub frame_length_flag: bool,
+    // Some(true) when SBR (HE-AAC) is signalled, Some(false) when it is
+    // explicitly absent, None when not signalled at all
+    pub sbr_present: Option<bool>,
+    pub ps_present: Option<bool>,
+    pub extension_sampling_frequency: Option<u32>
+}
+
+impl AudioSpecificConfig {
+    pub fn parse(data: &[u8]) -> Result<AudioSpecificConfig, String> {
+        let mut r = BitReader::new(data);
+
+        let mut audio_object_type = read_audio_object_type(&mut r)?;
+        let sampling_frequency = read_sampling_frequency(&mut r)?;
+        let channel_configuration = r.read_u8(4)?;
+
+        let mut config = AudioSpecificConfig {
+            audio_object_type,
+            sampling_frequency,
+            channel_configuration,
+            channel_count: match channel_configuration {
+                1..=14 => CHANNELS[channel_configuration as usize - 1],
+                _      => 0
+            },
+            frame_length_flag: false,
+            sbr_present: None,
+            ps_present: None,
+            extension_sampling_frequency: None
+        };
+
+        // explicit hierarchical signalling of HE-AAC
+        if audio_object_type == AOT_SBR || audio_object_type == AOT_PS {
+            config.sbr_present = Some(true);
+            config.ps_present = Some(audio_object_type == AOT_PS);
+            config.extension_sampling_frequency = Some(read_sampling_frequency(&mut r)?);
+            audio_object_type = read_audio_object_type(&mut r)?;
+            config.audio_object_type = audio_object_type;
+            if audio_object_type == 22 {
+                r.skip_bits(4)?; // extensionChannelConfiguration
+            }
+        }
+
+        match audio_object_type {
+            1 | 2 | 3 | 4 | 6 | 7 | 17 | 19 | 20 | 21 | 22 | 23 => {
+                read_ga_specific_config(&mut r, &mut config)?;
+            },
+            _ => return Ok(config)
+        }
+
+        if [17, 19, 20, 21, 22, 23, 24, 25, 26, 27, 39].contains(&audio_object_type) {
+            let ep_config = r.read_u8(2)?;
+            if ep_config == 2 || ep_config == 3 {
+                // ErrorProtectionSpecificConfig, nothing useful after it
+                return Ok(config);
+            }
+        }
+
+        // backwards compatible signalling of SBR and PS after the core config
+        if config.sbr_present.is_none() && r.bits_remaining() >= 16 && r.read_u16(11)? == 0x2b7 {
+            let extension_type = read_audio_object_type(&mut r)?;
+            if extension_type == AOT_SBR {
+                let sbr = r.read_bit()?;
+                config.sbr_present = Some(sbr);
+                if sbr {
+                    config.extension_sampling_frequency = Some(read_sampling_frequency(&mut r)?);
+                    if r.bits_remaining() >= 12 && r.read_u16(11)? == 0x548 {
+                        config.ps_present = Some(r.read_bit()?);
+                    }
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    // PCM samples per channel in one access unit, at the core sampling rate
+    pub fn frame_length(&self) -> u32 {
+        match (self.audio_object_type, self.frame_length_flag) {
+            (AOT_ER_AAC_LD, true) | (AOT_ER_AAC_ELD, true) => 480,
+            (AOT_ER_AAC_LD, false) | (AOT_ER_AAC_ELD, false) => 512,
+            (_, true) => 960,
+            (_, false) => 1024
+        }
+    }
+
+    // The sampling rate a decoder outputs, twice the core rate with SBR
+    pub fn output_sampling_frequency(&self) -> u32 {
+        match (self.sbr_present, self.extension_sampling_frequency) {
+            (Some(true), Some(frequency)) => frequency,
+            _ => self.sampling_frequency
+        }
+    }
+
+    // PCM samples per channel a decoder outputs for one access unit
+    pub fn output_frame_length(&self) -> u32 {
+        if self.output_sampling_frequency() > self.sampling_frequency {
+            self.frame_length() * 2
+        } else {
+            self.frame_length()
+        }
+    }
+
+    // The codec as commonly named. Implicit SBR (never signalled) reads as LC.
+    pub fn profile_name(&self) -> &'static str {
+        match (self.audio_object_type, self.sbr_present, self.ps_present) {
+            (AOT_AAC_LC, Some(true), Some(true)) => "HE-AACv2",
+            (AOT_AAC_LC, Some(true), _)          => "HE-AAC",
+            (AOT_AAC_LC, _, _)                   => "AAC-LC",
+            (AOT_AAC_MAIN, _, _)                 => "AAC Main",
+            (AOT_ER_AAC_LD, _, _)                => "AAC-LD",
+            (AOT_ER_AAC_ELD, _, _)               => "AAC-ELD",
+            (AOT_USAC, _, _)                     => "USAC",
+            _                                    => "AAC"
+        }
+    }
+}
+
+fn read_audio_object_type(r: &mut BitReader) -> Result<u8, String> {
+    let object_type = r.read_u8(5)?;
+    if object_type == 31 {
+        return Ok(32 + r.read_u8(6)?);
+    }
+    Ok(object_type)
+}
+
+fn read_sampling_frequency(r: &mut BitReader) -> Result<u32, String> {
+    let index = r.read_u8(4)?;
+    match index {
+        0xf => r.read_u32(24),
+        0..=12 => Ok(SAMPLING_FREQUENCIES[index as usize]),
+        _ => Err(format!("AudioSpecificConfig: reserved sampling frequency index {}", index))
+    }
+}
+
+fn read_ga_specific_config(r: &mut BitReader, config: &mut AudioSpecificConfig) -> Result<(), String> {
+    config.frame_length_flag = r.read_bit()?;
+    if r.read_bit()? {
+        r.skip_bits(14)?; // coreCoderDelay
+    }
+    let extension_flag = r.read_bit()?;
+
+    if config.channel_configuration == 0 {
+        config.channel_count = read_program_config_element(r)?;
+    }
+    if config.audio_object_type == 6 || config.audio_object_type == 20 {
+        r.skip_bits(3)?; // layerNr
+    }
+    if extension_flag {
+        if config.audio_object_type == 22 {
+            r.skip_bits(5 + 11)?; // numOfSubFrame, layer_length
+        }
+        if [17, 19, 20, 23].contains(&config.audio_object_type) {
+            r.skip_bits(3)?; // the resilience flags
+        }
+        r.skip_bits(1)?; // extensionFlag3
+    }
+    Ok(())
+}
+
+// Returns the number of channels the program_config_element() describes
+fn read_program_config_element(r: &mut BitReader) -> Result<u8, String> {
+    r.skip_bits(4 + 2 + 4)?; // element_instance_tag, object_type, sampling_frequency_index
+    let front = r.read_u8(4)?;
+    let side  = r.read_u8(4)?;
+    let back  = r.read_u8(4)?;
+    let lfe   = r.read_u8(2)?;
+    let assoc = r.read_u8(3)?;
+    let cc    = r.read_u8(4)?;
+
+    if r.read_bit()? {
+        r.skip_bits(4)?; // mono_mixdown_element_number
+    }
+    if r.read_bit()? {
+        r.skip_bits(4)?; // stereo_mixdown_element_number
+    }
+    if r.read_bit()? {
+        r.skip_bits(3)?; // matrix_mixdown_idx, pseudo_surround_enable
+    }
+
+    let mut channels = lfe;
+    for _ in 0..front + side + back {
+        let is_cpe = r.read_bit()?;
+        r.skip_bits(4)?; // element tag
+        channels += if is_cpe { 2 } else { 1 };
+    }
+    r.skip_bits(4 * (lfe + assoc) as usize + 5 * cc as usize)?;
+
+    r.byte_align();
+    let comment_bytes = r.read_u8(8)?;
+    r.skip_bits(8 * comment_bytes as usize)?;
+
+    Ok(channels)
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+/* ============================ Fixed point numbers ============================ */
+
+// Fixed point fields are kept as their raw bits so that reading and writing an
+// atom back out is bit exact. Conversion to floating point is only for display
+// and arithmetic by callers.
+macro_rules! fixed_point {
+    ( $name:ident, $repr:ty, $bits:ty, $frac:expr ) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name($repr);
+
+        impl $name {
+            pub const FRACTIONAL_BITS: u32 = $frac;
+
+            pub fn from_bits(bits: $bits) -> $name { $name(bits as $repr) }
+
+            pub fn to_bits(self) -> $bits { self.0 as $bits }
+
+            pub fn from_int(integer: $repr) -> $name { $name(integer << $frac) }
+
+            // Rounds to the nearest representable value and saturates at the
+            // ends of the range.
+            pub fn from_f64(value: f64) -> $name {
+                let scaled = (value * ((1u64 << $frac) as f64)).round();
+                if scaled >= <$repr>::MAX as f64 {
+                    $name(<$repr>::MAX)
+                } else if scaled <= <$repr>::MIN as f64 {
+                    $name(<$repr>::MIN)
+                } else {
+                    $name(scaled as $repr)
+                }
+            }
+
+            pub fn to_f64(self) -> f64 { (self.0 as f64) / ((1u64 << $frac) as f64) }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.to_f64())
+            }
+        }
+    };
+}
+
+// Signed 16.16, used for rates and the a, b, c, d, x and y matrix entries.
+fixed_point!(Fixed16_16, i32, u32, 16);
+// Unsigned 16.16, used for track width and height.
+fixed_point!(UFixed16_16, u32, u32, 16);
+// Signed 8.8, used for volume and balance.
+fixed_point!(Fixed8_8, i16, u16, 8);
+// Signed 2.30, used for the u, v and w matrix entries.
+fixed_point!(Fixed2_30, i32, u32, 30);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_round_trips() {
+        let one = Fixed16_16::from_bits(0x0001_0000);
+        assert_eq!(one.to_f64(), 1.0);
+        assert_eq!(Fixed16_16::from_f64(one.to_f64()).to_bits(), 0x0001_0000);
+        assert_eq!(Fixed16_16::from_int(1), one);
+
+        assert_eq!(UFixed16_16::from_f64(1.0).to_bits(), 0x0001_0000);
+        assert_eq!(Fixed8_8::from_f64(1.0).to_bits(), 0x0100);
+    }
+
+    #[test]
+    fn negative_round_trips() {
+        let minus_one = Fixed16_16::from_bits(0xffff_0000);
+        assert_eq!(minus_one.to_f64(), -1.0);
+        assert_eq!(Fixed16_16::from_f64(-1.0).to_bits(), 0xffff_0000);
+        assert_eq!(Fixed16_16::from_int(-1), minus_one);
+
+        // -1.5 and the smallest step below 0
+        assert_eq!(Fixed16_16::from_bits(0xfffe_8000).to_f64(), -1.5);
+        assert_eq!(Fixed16_16::from_f64(-1.5).to_bits(), 0xfffe_8000);
+        assert_eq!(Fixed16_16::from_bits(0xffff_ffff).to_f64(), -1.0 / 65536.0);
+
+        assert_eq!(Fixed8_8::from_bits(0xff00).to_f64(), -1.0);
+        assert_eq!(Fixed8_8::from_f64(-0.5).to_bits(), 0xff80);
+    }
+
+    #[test]
+    fn unusual_bits_round_trip() {
+        for &bits in &[0u32, 1, 0x7fff_ffff, 0x8000_0000, 0x1234_5678, 0xdead_beef] {
+            assert_eq!(Fixed16_16::from_bits(bits).to_bits(), bits);
+            assert_eq!(Fixed16_16::from_f64(Fixed16_16::from_bits(bits).to_f64()).to_bits(), bits);
+            assert_eq!(Fixed2_30::from_f64(Fixed2_30::from_bits(bits).to_f64()).to_bits(), bits);
+        }
+    }
+
+    #[test]
+    fn out_of_range_saturates() {
+        assert_eq!(Fixed16_16::from_f64(1e9).to_bits(), 0x7fff_ffff);
+        assert_eq!(Fixed16_16::from_f64(-1e9).to_bits(), 0x8000_0000);
+        assert_eq!(UFixed16_16::from_f64(-1.0).to_bits(), 0);
+    }
+}
@@ -0,0 +1,88 @@
+use reader::*;
+
+/* ==================================== FLAC ==================================== */
+
+pub const STREAMINFO: u8 = 0;
+
+// FLACSpecificBox, dfLa: a full box holding the FLAC metadata blocks, of
+// which only STREAMINFO is required
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlacSpecificBox {
+    pub stream_info: StreamInfo,
+    // type and data of the other metadata blocks
+    pub blocks: Vec<(u8, Vec<u8>)>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    pub min_frame_size: u32,
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    // 0 when unknown
+    pub total_samples: u64,
+    pub md5: Vec<u8>
+}
+
+impl FlacSpecificBox {
+    // data is the payload of dfLa, version and flags included
+    pub fn parse(data: &[u8]) -> Result<FlacSpecificBox, String> {
+        let version = read_u8_at(data, 0)?;
+        if version != 0 {
+            return Err(format!("dfLa: unknown version {}", version));
+        }
+
+        let mut stream_info = None;
+        let mut blocks = vec![];
+        let mut offset = 4;
+        loop {
+            let header = read_u32_at(data, offset)?;
+            let last = header & 0x8000_0000 != 0;
+            let typ = ((header >> 24) & 0x7f) as u8;
+            let body = read_bytes_at(data, offset + 4, (header & 0xff_ffff) as usize)?;
+
+            if typ == STREAMINFO {
+                stream_info = Some(StreamInfo::parse(body)?);
+            } else {
+                blocks.push((typ, body.to_vec()));
+            }
+
+            offset += 4 + body.len();
+            if last || offset >= data.len() {
+                break;
+            }
+        }
+
+        match stream_info {
+            Some(stream_info) => Ok(FlacSpecificBox { stream_info, blocks }),
+            None              => Err("dfLa: no STREAMINFO block".to_string())
+        }
+    }
+}
+
+impl StreamInfo {
+    pub fn parse(data: &[u8]) -> Result<StreamInfo, String> {
+        // 20 bits of rate, 3 of channels - 1, 5 of bits per sample - 1, 36 of total samples
+        let packed = read_u64_at(data, 10)?;
+
+        Ok(StreamInfo {
+            min_block_size:  read_u16_at(data, 0)?,
+            max_block_size:  read_u16_at(data, 2)?,
+            min_frame_size:  read_u24_at(data, 4)?,
+            max_frame_size:  read_u24_at(data, 7)?,
+            sample_rate:     (packed >> 44) as u32,
+            channels:        ((packed >> 41) & 0x7) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1f) as u8 + 1,
+            total_samples:   packed & 0xf_ffff_ffff,
+            md5:             read_bytes_at(data, 18, 16)?.to_vec()
+        })
+    }
+
+    // Samples per channel in every frame, when the stream uses a fixed size
+    pub fn fixed_block_size(&self) -> Option<u16> {
+        if self.min_block_size == self.max_block_size { Some(self.max_block_size) } else { None }
+    }
+}
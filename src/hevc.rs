@@ -0,0 +1,609 @@
+use avc::{NalUnits, Vui, ColourDescription, TimingInfo};
+use bitreader::*;
+use reader::*;
+
+/* =============================== H.265 / HEVC =============================== */
+
+// HEVCDecoderConfigurationRecord, the payload of hvcC. hvc1 entries carry
+// all their parameter sets here, hev1 entries may also send them in band.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcDecoderConfig {
+    pub configuration_version: u8,
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    // 48 bits
+    pub general_constraint_indicator_flags: u64,
+    pub general_level_idc: u8,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    // frames per 256 seconds, 0 when unspecified
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    // bytes in the length before each NAL unit of a sample: 1, 2 or 4
+    pub length_size: u8,
+    pub arrays: Vec<HevcNalArray>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcNalArray {
+    // set when every NAL unit of this type is here and none are in band
+    pub array_completeness: bool,
+    pub nal_unit_type: u8,
+    pub nal_units: Vec<Vec<u8>>
+}
+
+impl HevcDecoderConfig {
+    pub fn parse(data: &[u8]) -> Result<HevcDecoderConfig, String> {
+        let configuration_version = read_u8_at(data, 0)?;
+        if configuration_version != 1 {
+            return Err(format!("hvcC: unknown configuration version {}", configuration_version));
+        }
+
+        let profile = read_u8_at(data, 1)?;
+        let constraints = ((read_u16_at(data, 6)? as u64) << 32) | (read_u32_at(data, 8)? as u64);
+        let frame_rate_and_length = read_u8_at(data, 21)?;
+        let length_size = (frame_rate_and_length & 0x3) + 1;
+        if length_size == 3 {
+            return Err("hvcC: NAL unit length size can't be 3".to_string());
+        }
+
+        let mut config = HevcDecoderConfig {
+            configuration_version,
+            general_profile_space:               profile >> 6,
+            general_tier_flag:                   profile & 0x20 != 0,
+            general_profile_idc:                 profile & 0x1f,
+            general_profile_compatibility_flags: read_u32_at(data, 2)?,
+            general_constraint_indicator_flags:  constraints,
+            general_level_idc:                   read_u8_at(data, 12)?,
+            min_spatial_segmentation_idc:        read_u16_at(data, 13)? & 0xfff,
+            parallelism_type:                    read_u8_at(data, 15)? & 0x3,
+            chroma_format_idc:                   read_u8_at(data, 16)? & 0x3,
+            bit_depth_luma:                      (read_u8_at(data, 17)? & 0x7) + 8,
+            bit_depth_chroma:                    (read_u8_at(data, 18)? & 0x7) + 8,
+            avg_frame_rate:                      read_u16_at(data, 19)?,
+            constant_frame_rate:                 frame_rate_and_length >> 6,
+            num_temporal_layers:                 (frame_rate_and_length >> 3) & 0x7,
+            temporal_id_nested:                  frame_rate_and_length & 0x4 != 0,
+            length_size,
+            arrays: vec![]
+        };
+
+        let array_count = read_u8_at(data, 22)?;
+        let mut offset = 23;
+        for _ in 0..array_count {
+            let header = read_u8_at(data, offset)?;
+            let count = read_u16_at(data, offset + 1)? as usize;
+            offset += 3;
+
+            let mut nal_units = Vec::with_capacity(count.min(32));
+            for _ in 0..count {
+                let length = read_u16_at(data, offset)? as usize;
+                nal_units.push(read_bytes_at(data, offset + 2, length)?.to_vec());
+                offset += 2 + length;
+            }
+
+            config.arrays.push(HevcNalArray {
+                array_completeness: header & 0x80 != 0,
+                nal_unit_type: header & 0x3f,
+                nal_units
+            });
+        }
+
+        Ok(config)
+    }
+
+    pub fn nal_units(&self, nal_unit_type: u8) -> impl Iterator<Item = &Vec<u8>> {
+        self.arrays.iter()
+            .filter(move |a| a.nal_unit_type == nal_unit_type)
+            .flat_map(|a| a.nal_units.iter())
+    }
+
+    pub fn first_vps(&self) -> Result<Option<HevcVps>, String> {
+        match self.nal_units(NAL_VPS).next() {
+            Some(nal) => Ok(Some(HevcVps::parse(nal)?)),
+            None      => Ok(None)
+        }
+    }
+
+    pub fn first_sps(&self) -> Result<Option<HevcSps>, String> {
+        match self.nal_units(NAL_SPS).next() {
+            Some(nal) => Ok(Some(HevcSps::parse(nal)?)),
+            None      => Ok(None)
+        }
+    }
+
+    pub fn first_pps(&self) -> Result<Option<HevcPps>, String> {
+        match self.nal_units(NAL_PPS).next() {
+            Some(nal) => Ok(Some(HevcPps::parse(nal)?)),
+            None      => Ok(None)
+        }
+    }
+}
+
+// IRAP pictures are 16 to 23: BLA, IDR and CRA
+pub const NAL_BLA_W_LP: u8 = 16;
+pub const NAL_IDR_W_RADL: u8 = 19;
+pub const NAL_IDR_N_LP: u8 = 20;
+pub const NAL_CRA: u8 = 21;
+pub const NAL_VPS: u8 = 32;
+pub const NAL_SPS: u8 = 33;
+pub const NAL_PPS: u8 = 34;
+pub const NAL_AUD: u8 = 35;
+pub const NAL_PREFIX_SEI: u8 = 39;
+
+// HEVC NAL units have a two byte header, with the type in the first one
+pub fn nal_unit_type(nal: &[u8]) -> u8 {
+    nal.first().map(|b| (b >> 1) & 0x3f).unwrap_or(0)
+}
+
+pub fn is_irap(nal_unit_type: u8) -> bool {
+    (16..=23).contains(&nal_unit_type)
+}
+
+// The type of the first IRAP picture in a sample, if it has one
+pub fn sample_irap_type(sample: &[u8], length_size: u8) -> Result<Option<u8>, String> {
+    for nal in NalUnits::new(sample, length_size) {
+        let typ = nal_unit_type(nal?);
+        if is_irap(typ) {
+            return Ok(Some(typ));
+        }
+    }
+    Ok(None)
+}
+
+pub fn sample_has_parameter_sets(sample: &[u8], length_size: u8) -> Result<bool, String> {
+    let mut vps = false;
+    let mut sps = false;
+    let mut pps = false;
+    for nal in NalUnits::new(sample, length_size) {
+        match nal_unit_type(nal?) {
+            NAL_VPS => vps = true,
+            NAL_SPS => sps = true,
+            NAL_PPS => pps = true,
+            _       => {}
+        }
+    }
+    Ok(vps && sps && pps)
+}
+
+// Strips the two byte NAL unit header and the emulation prevention bytes
+fn rbsp(nal: &[u8], expected_type: u8, what: &str) -> Result<Vec<u8>, String> {
+    if nal.len() < 2 || nal_unit_type(nal) != expected_type {
+        return Err(format!("{}: NAL unit type is {}", what, nal_unit_type(nal)));
+    }
+    Ok(remove_emulation_prevention(&nal[2..]))
+}
+
+// general_profile_space to general_level_idc of profile_tier_level()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfileTierLevel {
+    pub profile_space: u8,
+    pub tier_flag: bool,
+    pub profile_idc: u8,
+    pub profile_compatibility_flags: u32,
+    pub progressive_source: bool,
+    pub interlaced_source: bool,
+    pub level_idc: u8
+}
+
+impl ProfileTierLevel {
+    // Reads the general part and skips the sub layer ones
+    fn parse(r: &mut BitReader, max_sub_layers_minus1: u8) -> Result<ProfileTierLevel, String> {
+        let profile_space               = r.read_u8(2)?;
+        let tier_flag                   = r.read_bit()?;
+        let profile_idc                 = r.read_u8(5)?;
+        let profile_compatibility_flags = r.read_u32(32)?;
+        let progressive_source          = r.read_bit()?;
+        let interlaced_source           = r.read_bit()?;
+        r.skip_bits(46)?; // non_packed, frame_only and 44 bits of constraint flags
+        let level_idc                   = r.read_u8(8)?;
+
+        let mut present = vec![];
+        for _ in 0..max_sub_layers_minus1 {
+            present.push((r.read_bit()?, r.read_bit()?));
+        }
+        if max_sub_layers_minus1 > 0 {
+            r.skip_bits(2 * (8 - max_sub_layers_minus1 as usize))?;
+        }
+        for (profile_present, level_present) in present {
+            if profile_present {
+                r.skip_bits(88)?;
+            }
+            if level_present {
+                r.skip_bits(8)?;
+            }
+        }
+
+        Ok(ProfileTierLevel {
+            profile_space,
+            tier_flag,
+            profile_idc,
+            profile_compatibility_flags,
+            progressive_source,
+            interlaced_source,
+            level_idc
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcVps {
+    pub video_parameter_set_id: u8,
+    pub max_layers: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: ProfileTierLevel
+}
+
+impl HevcVps {
+    pub fn parse(nal: &[u8]) -> Result<HevcVps, String> {
+        let rbsp = rbsp(nal, NAL_VPS, "VPS")?;
+        let mut r = BitReader::new(&rbsp);
+
+        let video_parameter_set_id = r.read_u8(4)?;
+        r.skip_bits(2)?; // vps_base_layer_internal_flag, vps_base_layer_available_flag
+        let max_layers            = r.read_u8(6)? + 1;
+        let max_sub_layers_minus1 = r.read_u8(3)?;
+        let temporal_id_nesting   = r.read_bit()?;
+        r.skip_bits(16)?; // vps_reserved_0xffff_16bits
+
+        Ok(HevcVps {
+            video_parameter_set_id,
+            max_layers,
+            max_sub_layers: max_sub_layers_minus1 + 1,
+            temporal_id_nesting,
+            profile_tier_level: ProfileTierLevel::parse(&mut r, max_sub_layers_minus1)?
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcSps {
+    pub video_parameter_set_id: u8,
+    pub max_sub_layers: u8,
+    pub profile_tier_level: ProfileTierLevel,
+    pub seq_parameter_set_id: u8,
+    // 0 monochrome, 1 4:2:0, 2 4:2:2, 3 4:4:4
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane: bool,
+    // in luma samples before the conformance window
+    pub width: u32,
+    pub height: u32,
+    // left, right, top, bottom in pixels
+    pub conformance_window: (u32, u32, u32, u32),
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    pub log2_max_pic_order_cnt_lsb: u8,
+    pub vui: Option<Vui>
+}
+
+impl HevcSps {
+    // nal is the whole NAL unit, header included
+    pub fn parse(nal: &[u8]) -> Result<HevcSps, String> {
+        let rbsp = rbsp(nal, NAL_SPS, "SPS")?;
+        let mut r = BitReader::new(&rbsp);
+
+        let video_parameter_set_id = r.read_u8(4)?;
+        let max_sub_layers_minus1  = r.read_u8(3)?;
+        if max_sub_layers_minus1 > 6 {
+            return Err("SPS: sps_max_sub_layers_minus1 is 7".to_string());
+        }
+        r.skip_bits(1)?; // sps_temporal_id_nesting_flag
+        let profile_tier_level = ProfileTierLevel::parse(&mut r, max_sub_layers_minus1)?;
+
+        let seq_parameter_set_id = r.read_ue_max(15, "sps_seq_parameter_set_id")? as u8;
+        let chroma_format_idc = r.read_ue_max(3, "chroma_format_idc")? as u8;
+        let separate_colour_plane = chroma_format_idc == 3 && r.read_bit()?;
+        let width  = r.read_ue_max(16888, "pic_width_in_luma_samples")? as u32;
+        let height = r.read_ue_max(16888, "pic_height_in_luma_samples")? as u32;
+
+        let mut conformance_window = (0, 0, 0, 0);
+        if r.read_bit()? {
+            // offsets count in chroma samples
+            let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+            let (sub_width, sub_height) = match chroma_array_type {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1)
+            };
+
+            let left   = r.read_ue_max(width as u64, "conf_win_left_offset")? as u32;
+            let right  = r.read_ue_max(width as u64, "conf_win_right_offset")? as u32;
+            let top    = r.read_ue_max(height as u64, "conf_win_top_offset")? as u32;
+            let bottom = r.read_ue_max(height as u64, "conf_win_bottom_offset")? as u32;
+            conformance_window = (left * sub_width, right * sub_width, top * sub_height, bottom * sub_height);
+
+            if conformance_window.0 + conformance_window.1 >= width || conformance_window.2 + conformance_window.3 >= height {
+                return Err(format!("SPS: conformance window {:?} leaves nothing of {}x{}", conformance_window, width, height));
+            }
+        }
+
+        let bit_depth_luma   = r.read_ue_max(8, "bit_depth_luma_minus8")? as u8 + 8;
+        let bit_depth_chroma = r.read_ue_max(8, "bit_depth_chroma_minus8")? as u8 + 8;
+        let log2_max_pic_order_cnt_lsb = r.read_ue_max(12, "log2_max_pic_order_cnt_lsb_minus4")? as u8 + 4;
+
+        let sub_layer_ordering_info_present = r.read_bit()?;
+        let first_sub_layer = if sub_layer_ordering_info_present { 0 } else { max_sub_layers_minus1 };
+        for _ in first_sub_layer..=max_sub_layers_minus1 {
+            r.read_ue()?; // sps_max_dec_pic_buffering_minus1
+            r.read_ue()?; // sps_max_num_reorder_pics
+            r.read_ue()?; // sps_max_latency_increase_plus1
+        }
+
+        r.read_ue()?; // log2_min_luma_coding_block_size_minus3
+        r.read_ue()?; // log2_diff_max_min_luma_coding_block_size
+        r.read_ue()?; // log2_min_luma_transform_block_size_minus2
+        r.read_ue()?; // log2_diff_max_min_luma_transform_block_size
+        r.read_ue()?; // max_transform_hierarchy_depth_inter
+        r.read_ue()?; // max_transform_hierarchy_depth_intra
+
+        if r.read_bit()? && r.read_bit()? {
+            // scaling_list_enabled_flag and sps_scaling_list_data_present_flag
+            skip_scaling_list_data(&mut r)?;
+        }
+        r.skip_bits(2)?; // amp_enabled_flag, sample_adaptive_offset_enabled_flag
+
+        if r.read_bit()? {
+            // pcm sample bit depths
+            r.skip_bits(8)?;
+            r.read_ue()?; // log2_min_pcm_luma_coding_block_size_minus3
+            r.read_ue()?; // log2_diff_max_min_pcm_luma_coding_block_size
+            r.skip_bits(1)?; // pcm_loop_filter_disabled_flag
+        }
+
+        let num_short_term_ref_pic_sets = r.read_ue_max(64, "num_short_term_ref_pic_sets")? as usize;
+        let mut num_delta_pocs = Vec::with_capacity(num_short_term_ref_pic_sets);
+        for i in 0..num_short_term_ref_pic_sets {
+            let count = skip_st_ref_pic_set(&mut r, i, &num_delta_pocs)?;
+            num_delta_pocs.push(count);
+        }
+
+        if r.read_bit()? {
+            let count = r.read_ue_max(32, "num_long_term_ref_pics_sps")?;
+            for _ in 0..count {
+                // lt_ref_pic_poc_lsb_sps and used_by_curr_pic_lt_sps_flag
+                r.skip_bits(log2_max_pic_order_cnt_lsb as usize + 1)?;
+            }
+        }
+        r.skip_bits(2)?; // sps_temporal_mvp_enabled_flag, strong_intra_smoothing_enabled_flag
+
+        let vui = if r.read_bit()? { Some(parse_vui(&mut r, chroma_format_idc)?) } else { None };
+
+        Ok(HevcSps {
+            video_parameter_set_id,
+            max_sub_layers: max_sub_layers_minus1 + 1,
+            profile_tier_level,
+            seq_parameter_set_id,
+            chroma_format_idc,
+            separate_colour_plane,
+            width,
+            height,
+            conformance_window,
+            bit_depth_luma,
+            bit_depth_chroma,
+            log2_max_pic_order_cnt_lsb,
+            vui
+        })
+    }
+
+    pub fn cropped_width(&self) -> u32 { self.width - self.conformance_window.0 - self.conformance_window.1 }
+
+    pub fn cropped_height(&self) -> u32 { self.height - self.conformance_window.2 - self.conformance_window.3 }
+
+    // Frames per second from the VUI timing info, one tick per picture
+    pub fn frame_rate(&self) -> Option<f64> {
+        let timing = self.vui.as_ref()?.timing?;
+        if timing.num_units_in_tick == 0 || timing.time_scale == 0 {
+            return None;
+        }
+        Some((timing.time_scale as f64) / (timing.num_units_in_tick as f64))
+    }
+
+    pub fn colour(&self) -> Option<ColourDescription> {
+        self.vui.as_ref()?.colour
+    }
+
+    pub fn chroma_format(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4"
+        }
+    }
+}
+
+// The HEVC VUI up to its timing info
+fn parse_vui(r: &mut BitReader, chroma_format_idc: u8) -> Result<Vui, String> {
+    let mut vui = Vui::parse_start(r)?;
+
+    r.skip_bits(3)?; // neutral_chroma_indication_flag, field_seq_flag, frame_field_info_present_flag
+
+    if r.read_bit()? {
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1)
+        };
+        let left   = r.read_ue_max(16888, "def_disp_win_left_offset")? as u32;
+        let right  = r.read_ue_max(16888, "def_disp_win_right_offset")? as u32;
+        let top    = r.read_ue_max(16888, "def_disp_win_top_offset")? as u32;
+        let bottom = r.read_ue_max(16888, "def_disp_win_bottom_offset")? as u32;
+        vui.default_display_window = Some((left * sub_width, right * sub_width, top * sub_height, bottom * sub_height));
+    }
+
+    if r.read_bit()? {
+        vui.timing = Some(TimingInfo {
+            num_units_in_tick: r.read_u32(32)?,
+            time_scale:        r.read_u32(32)?,
+            fixed_frame_rate:  false
+        });
+    }
+
+    Ok(vui)
+}
+
+fn skip_scaling_list_data(r: &mut BitReader) -> Result<(), String> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0..6).step_by(step) {
+            if !r.read_bit()? {
+                r.read_ue()?; // scaling_list_pred_matrix_id_delta
+                continue;
+            }
+
+            let coef_num = 64.min(1 << (4 + (size_id << 1)));
+            if size_id > 1 {
+                r.read_se()?; // scaling_list_dc_coef_minus8
+            }
+            for _ in 0..coef_num {
+                r.read_se()?; // scaling_list_delta_coef
+            }
+        }
+    }
+    Ok(())
+}
+
+// Skips st_ref_pic_set(index) of an SPS and returns its NumDeltaPocs, which
+// later sets predicted from this one need.
+fn skip_st_ref_pic_set(r: &mut BitReader, index: usize, num_delta_pocs: &[u32]) -> Result<u32, String> {
+    if index != 0 && r.read_bit()? {
+        // inter_ref_pic_set_prediction_flag, predicted from the set before
+        r.skip_bits(1)?; // delta_rps_sign
+        r.read_ue()?;    // abs_delta_rps_minus1
+
+        let mut count = 0;
+        for _ in 0..=num_delta_pocs[index - 1] {
+            let used_by_curr_pic = r.read_bit()?;
+            let use_delta = used_by_curr_pic || r.read_bit()?;
+            if use_delta {
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+
+    let num_negative_pics = r.read_ue_max(16, "num_negative_pics")? as u32;
+    let num_positive_pics = r.read_ue_max(16, "num_positive_pics")? as u32;
+    for _ in 0..num_negative_pics + num_positive_pics {
+        r.read_ue()?;    // delta_poc_minus1
+        r.skip_bits(1)?; // used_by_curr_pic_flag
+    }
+    Ok(num_negative_pics + num_positive_pics)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcPps {
+    pub pic_parameter_set_id: u8,
+    pub seq_parameter_set_id: u8,
+    pub dependent_slice_segments_enabled: bool,
+    pub output_flag_present: bool,
+    pub num_extra_slice_header_bits: u8,
+    pub sign_data_hiding_enabled: bool,
+    pub cabac_init_present: bool,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub init_qp: i32,
+    pub constrained_intra_pred: bool,
+    pub transform_skip_enabled: bool,
+    pub cu_qp_delta_enabled: bool,
+    pub weighted_pred: bool,
+    pub weighted_bipred: bool,
+    pub transquant_bypass_enabled: bool,
+    pub tiles_enabled: bool,
+    pub entropy_coding_sync_enabled: bool
+}
+
+impl HevcPps {
+    // The fields up to entropy_coding_sync_enabled_flag
+    pub fn parse(nal: &[u8]) -> Result<HevcPps, String> {
+        let rbsp = rbsp(nal, NAL_PPS, "PPS")?;
+        let mut r = BitReader::new(&rbsp);
+
+        let pic_parameter_set_id             = r.read_ue_max(63, "pps_pic_parameter_set_id")? as u8;
+        let seq_parameter_set_id             = r.read_ue_max(15, "pps_seq_parameter_set_id")? as u8;
+        let dependent_slice_segments_enabled = r.read_bit()?;
+        let output_flag_present              = r.read_bit()?;
+        let num_extra_slice_header_bits      = r.read_u8(3)?;
+        let sign_data_hiding_enabled         = r.read_bit()?;
+        let cabac_init_present               = r.read_bit()?;
+        let num_ref_idx_l0_default_active    = r.read_ue_max(14, "num_ref_idx_l0_default_active_minus1")? as u32 + 1;
+        let num_ref_idx_l1_default_active    = r.read_ue_max(14, "num_ref_idx_l1_default_active_minus1")? as u32 + 1;
+
+        let init_qp_minus26 = r.read_se()?;
+        if !(-62..=25).contains(&init_qp_minus26) {
+            return Err(format!("PPS: init_qp_minus26 is {}", init_qp_minus26));
+        }
+
+        let constrained_intra_pred = r.read_bit()?;
+        let transform_skip_enabled = r.read_bit()?;
+        let cu_qp_delta_enabled    = r.read_bit()?;
+        if cu_qp_delta_enabled {
+            r.read_ue()?; // diff_cu_qp_delta_depth
+        }
+        r.read_se()?;    // pps_cb_qp_offset
+        r.read_se()?;    // pps_cr_qp_offset
+        r.skip_bits(1)?; // pps_slice_chroma_qp_offsets_present_flag
+
+        Ok(HevcPps {
+            pic_parameter_set_id,
+            seq_parameter_set_id,
+            dependent_slice_segments_enabled,
+            output_flag_present,
+            num_extra_slice_header_bits,
+            sign_data_hiding_enabled,
+            cabac_init_present,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            init_qp: 26 + init_qp_minus26 as i32,
+            constrained_intra_pred,
+            transform_skip_enabled,
+            cu_qp_delta_enabled,
+            weighted_pred:               r.read_bit()?,
+            weighted_bipred:             r.read_bit()?,
+            transquant_bypass_enabled:   r.read_bit()?,
+            tiles_enabled:               r.read_bit()?,
+            entropy_coding_sync_enabled: r.read_bit()?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn main10_sps_with_vui() {
+        // Main 10 level 4 1920x1080 at 24000/1001 with BT.2020 PQ colour,
+        // laid out as x265 writes it
+        let nal = [
+            0x42, 0x01, 0x01, 0x02, 0x20, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x78, 0xa0, 0x03, 0xc0, 0x80, 0x10, 0xe4, 0xd9, 0x65, 0x79, 0x24,
+            0xca, 0xf0, 0x16, 0xa1, 0x22, 0x01, 0x36, 0xc2, 0x00, 0x00, 0x07, 0xd2, 0x00, 0x00,
+            0xbb, 0x80, 0x10
+        ];
+        let sps = HevcSps::parse(&nal).unwrap();
+
+        let ptl = sps.profile_tier_level;
+        assert_eq!((ptl.profile_idc, ptl.tier_flag, ptl.level_idc), (2, false, 120));
+        assert_eq!(ptl.profile_compatibility_flags, 0x2000_0000);
+        assert!(ptl.progressive_source && !ptl.interlaced_source);
+
+        assert_eq!((sps.bit_depth_luma, sps.bit_depth_chroma), (10, 10));
+        assert_eq!((sps.cropped_width(), sps.cropped_height()), (1920, 1080));
+        assert_eq!(sps.frame_rate(), Some(24000.0 / 1001.0));
+        assert_eq!(sps.colour(), Some(ColourDescription {
+            colour_primaries: 9,
+            transfer_characteristics: 16,
+            matrix_coefficients: 9,
+            full_range: false
+        }));
+    }
+}
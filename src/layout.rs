@@ -0,0 +1,255 @@
+use std::fmt;
+
+/* ============================ Top level box walker ============================ */
+
+// Box types we expect to see at the top level of a file. Only used to find our
+// footing again after a corrupt header; well formed files are walked purely by
+// the declared sizes and may contain anything.
+const KNOWN_TOP_LEVEL: &[&str] = &[
+    "ftyp", "styp", "pdin", "moov", "moof", "mfra", "mdat", "free", "skip",
+    "wide", "meta", "uuid", "sidx", "ssix", "prft", "emsg", "pnot", "meco"
+];
+
+pub struct TopLevelAtom {
+    pub location: usize,
+    pub size: u64,
+    pub header_size: usize,
+    pub typ: String
+}
+
+impl TopLevelAtom {
+    pub fn end(&self) -> u64 { (self.location as u64).saturating_add(self.size) }
+
+    pub fn data_location(&self) -> usize { self.location + self.header_size }
+}
+
+impl fmt::Display for TopLevelAtom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} @ {} with size {}", self.typ, self.location, self.size)
+    }
+}
+
+pub enum LayoutIssue {
+    // Bytes between start and end don't belong to any box we could read.
+    Gap { start: usize, end: usize },
+    // The box at location claims to run to end, past the limit of the bytes
+    // that are actually there (the end of the file).
+    Truncated { location: usize, end: u64, limit: usize },
+    // The box at location claims to run to end, but another box starts at
+    // next inside it.
+    Overlap { location: usize, end: u64, next: usize }
+}
+
+impl fmt::Display for LayoutIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayoutIssue::Gap { start, end } =>
+                write!(f, "{} unaccounted bytes from {} to {}", end - start, start, end),
+            LayoutIssue::Truncated { location, end, limit } =>
+                write!(f, "box @ {} ends at {} past the end of the file at {}", location, end, limit),
+            LayoutIssue::Overlap { location, end, next } =>
+                write!(f, "box @ {} ends at {} which overlaps the box @ {}", location, end, next)
+        }
+    }
+}
+
+pub struct FileLayout {
+    pub atoms: Vec<TopLevelAtom>,
+    pub issues: Vec<LayoutIssue>
+}
+
+impl FileLayout {
+    pub fn walk(data: &[u8]) -> FileLayout {
+        let mut atoms = vec![];
+        let mut issues = vec![];
+        let mut position = 0;
+
+        while position < data.len() {
+            match read_header(data, position) {
+                Some(atom) => {
+                    if atom.end() <= data.len() as u64 {
+                        position = atom.end() as usize;
+                        atoms.push(atom);
+                        continue;
+                    }
+
+                    // Either the size is wrong and more boxes follow inside
+                    // this one, or the file was cut short
+                    let next = resync(data, atom.data_location());
+                    if next < data.len() {
+                        issues.push(LayoutIssue::Overlap { location: atom.location, end: atom.end(), next });
+                    } else {
+                        issues.push(LayoutIssue::Truncated { location: atom.location, end: atom.end(), limit: data.len() });
+                    }
+                    position = next;
+                    atoms.push(atom);
+                },
+                None => {
+                    // Garbage right after a box can mean that box claimed
+                    // too much, so look for the next box inside it first
+                    let overlapped = atoms.last()
+                        .filter(|a| a.end() == position as u64)
+                        .map(|a| (a.location, a.end(), resync(data, a.data_location())))
+                        .filter(|&(_, _, next)| next < position);
+                    if let Some((location, end, next)) = overlapped {
+                        issues.push(LayoutIssue::Overlap { location, end, next });
+                        position = next;
+                        continue;
+                    }
+
+                    let next = resync(data, position + 1);
+                    issues.push(LayoutIssue::Gap { start: position, end: next });
+                    position = next;
+                }
+            }
+        }
+
+        FileLayout { atoms, issues }
+    }
+
+    pub fn find(&self, typ: &str) -> Option<&TopLevelAtom> {
+        self.atoms.iter().find(|a| a.typ == typ)
+    }
+
+    pub fn find_all<'a>(&'a self, typ: &'a str) -> impl Iterator<Item = &'a TopLevelAtom> + 'a {
+        self.atoms.iter().filter(move |a| a.typ == typ)
+    }
+}
+
+fn read_be(data: &[u8]) -> u64 {
+    data.iter().fold(0, |r, &b| (r << 8) | (b as u64))
+}
+
+fn read_header(data: &[u8], position: usize) -> Option<TopLevelAtom> {
+    if data.len() - position < 8 {
+        return None;
+    }
+
+    let typ_bytes = &data[position + 4 .. position + 8];
+    if !typ_bytes.iter().all(|&b| (0x20..0x7f).contains(&b)) {
+        return None;
+    }
+    let typ = String::from_utf8(typ_bytes.to_vec()).ok()?;
+
+    let mut header_size = 8;
+    let size = match read_be(&data[position .. position + 4]) {
+        // size 0 means the box runs to the end of the file
+        0 => (data.len() - position) as u64,
+        // size 1 means a 64 bit size follows the type
+        1 => {
+            if data.len() - position < 16 {
+                return None;
+            }
+            header_size = 16;
+            read_be(&data[position + 8 .. position + 16])
+        },
+        s => s
+    };
+
+    if typ == "uuid" {
+        header_size += 16;
+    }
+
+    if size < header_size as u64 {
+        return None;
+    }
+
+    Some(TopLevelAtom { location: position, size, header_size, typ })
+}
+
+// Scan forward for the next position holding a plausible top level box. Only
+// used after a corrupt header, so the false positives a byte scan can produce
+// are limited to files that are already broken.
+fn resync(data: &[u8], from: usize) -> usize {
+    for position in from .. data.len() {
+        if let Some(atom) = read_header(data, position) {
+            if KNOWN_TOP_LEVEL.contains(&atom.typ.as_str()) && atom.end() <= data.len() as u64 {
+                return position;
+            }
+        }
+    }
+
+    data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(typ: &str, size: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = size.to_be_bytes().to_vec();
+        data.extend_from_slice(typ.as_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn types(layout: &FileLayout) -> Vec<&str> {
+        layout.atoms.iter().map(|a| a.typ.as_str()).collect()
+    }
+
+    #[test]
+    fn walks_by_declared_size() {
+        let mut data = atom("ftyp", 16, b"isom\0\0\0\0");
+        data.extend(atom("mdat", 12, b"moov"));
+        data.extend(atom("moov", 8, b""));
+
+        let layout = FileLayout::walk(&data);
+        assert_eq!(types(&layout), ["ftyp", "mdat", "moov"]);
+        assert!(layout.issues.is_empty());
+        assert_eq!(layout.find("moov").map(|a| a.location), Some(28));
+    }
+
+    #[test]
+    fn oversized_box_does_not_hide_the_rest() {
+        let mut data = atom("ftyp", 0xffff_fff0, b"isom\0\0\0\0");
+        data.extend(atom("free", 12, b"\0\0\0\0"));
+        data.extend(atom("moov", 8, b""));
+
+        let layout = FileLayout::walk(&data);
+        assert_eq!(types(&layout), ["ftyp", "free", "moov"]);
+        match layout.issues[..] {
+            [LayoutIssue::Overlap { location: 0, next: 16, .. }] => (),
+            _ => panic!("expected an overlap with the free box")
+        }
+    }
+
+    #[test]
+    fn box_claiming_too_much_overlaps_the_next() {
+        let mut data = atom("free", 20, b"");
+        data.extend(atom("moov", 16, b"\0\0\0\0\0\0\0\0"));
+
+        let layout = FileLayout::walk(&data);
+        assert_eq!(types(&layout), ["free", "moov"]);
+        match layout.issues[..] {
+            [LayoutIssue::Overlap { location: 0, end: 20, next: 8 }] => (),
+            _ => panic!("expected an overlap with the moov box")
+        }
+    }
+
+    #[test]
+    fn cut_short_box_is_truncated() {
+        let mut data = atom("ftyp", 16, b"isom\0\0\0\0");
+        data.extend(atom("mdat", 100, b"\0\0\0\0"));
+
+        let layout = FileLayout::walk(&data);
+        assert_eq!(types(&layout), ["ftyp", "mdat"]);
+        match layout.issues[..] {
+            [LayoutIssue::Truncated { location: 16, end: 116, limit: 28 }] => (),
+            _ => panic!("expected a truncated mdat")
+        }
+    }
+
+    #[test]
+    fn garbage_between_boxes_is_a_gap() {
+        let mut data = atom("free", 8, b"");
+        data.extend_from_slice(&[0xff; 6]);
+        data.extend(atom("moov", 8, b""));
+
+        let layout = FileLayout::walk(&data);
+        assert_eq!(types(&layout), ["free", "moov"]);
+        match layout.issues[..] {
+            [LayoutIssue::Gap { start: 8, end: 14 }] => (),
+            _ => panic!("expected a gap")
+        }
+    }
+}
@@ -1,3 +1,25 @@
 pub mod videotrim;
+pub mod ac3;
+pub mod alac;
 pub mod atoms;
+pub mod av1;
+pub mod avc;
+pub mod bitreader;
+pub mod codecs;
+pub mod dovi;
+pub mod esds;
+pub mod fixed;
+pub mod flac;
+pub mod hevc;
+pub mod layout;
+pub mod matrix;
+pub mod opus;
 pub mod parser;
+pub mod pcm;
+pub mod reader;
+pub mod samples;
+pub mod stbl;
+pub mod stsd;
+pub mod visual;
+pub mod vp9;
+pub mod writer;
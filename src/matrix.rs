@@ -0,0 +1,208 @@
+use fixed::*;
+use std::fmt;
+
+/* ============================ Transformation matrix ============================ */
+
+// The 3x3 matrix stored in mvhd and tkhd, in file order:
+//   | a  b  u |
+//   | c  d  v |
+//   | x  y  w |
+// a, b, c, d, x and y are 16.16 while u, v and w are 2.30.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Matrix {
+    pub a: Fixed16_16,
+    pub b: Fixed16_16,
+    pub u: Fixed2_30,
+    pub c: Fixed16_16,
+    pub d: Fixed16_16,
+    pub v: Fixed2_30,
+    pub x: Fixed16_16,
+    pub y: Fixed16_16,
+    pub w: Fixed2_30
+}
+
+impl Matrix {
+    pub fn identity() -> Matrix {
+        Matrix {
+            a: Fixed16_16::from_int(1), b: Fixed16_16::from_int(0), u: Fixed2_30::from_int(0),
+            c: Fixed16_16::from_int(0), d: Fixed16_16::from_int(1), v: Fixed2_30::from_int(0),
+            x: Fixed16_16::from_int(0), y: Fixed16_16::from_int(0), w: Fixed2_30::from_int(1)
+        }
+    }
+
+    pub fn from_bits(bits: &[u32; 9]) -> Matrix {
+        Matrix {
+            a: Fixed16_16::from_bits(bits[0]),
+            b: Fixed16_16::from_bits(bits[1]),
+            u: Fixed2_30::from_bits(bits[2]),
+            c: Fixed16_16::from_bits(bits[3]),
+            d: Fixed16_16::from_bits(bits[4]),
+            v: Fixed2_30::from_bits(bits[5]),
+            x: Fixed16_16::from_bits(bits[6]),
+            y: Fixed16_16::from_bits(bits[7]),
+            w: Fixed2_30::from_bits(bits[8])
+        }
+    }
+
+    pub fn to_bits(&self) -> [u32; 9] {
+        [
+            self.a.to_bits(), self.b.to_bits(), self.u.to_bits(),
+            self.c.to_bits(), self.d.to_bits(), self.v.to_bits(),
+            self.x.to_bits(), self.y.to_bits(), self.w.to_bits()
+        ]
+    }
+
+    pub fn to_f64(&self) -> [[f64; 3]; 3] {
+        [
+            [self.a.to_f64(), self.b.to_f64(), self.u.to_f64()],
+            [self.c.to_f64(), self.d.to_f64(), self.v.to_f64()],
+            [self.x.to_f64(), self.y.to_f64(), self.w.to_f64()]
+        ]
+    }
+}
+
+// How a matrix turns the picture for display: clockwise rotation in degrees,
+// after mirroring left to right when mirrored. A vertical flip is a mirror
+// turned by 180.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: u16,
+    pub mirrored: bool
+}
+
+impl Orientation {
+    pub fn new(rotation: u16, mirrored: bool) -> Result<Orientation, String> {
+        if !rotation.is_multiple_of(90) || rotation >= 360 {
+            return Err(format!("Can't rotate by {} degrees, only 0, 90, 180 or 270", rotation));
+        }
+        Ok(Orientation { rotation, mirrored })
+    }
+
+    pub fn horizontal_flip(&self) -> bool { self.mirrored && self.rotation == 0 }
+
+    pub fn vertical_flip(&self) -> bool { self.mirrored && self.rotation == 180 }
+
+    // The linear part of the matrix, a b c d. Points are row vectors, so x
+    // goes to (a x + c y, b x + d y), with y pointing down.
+    fn linear(&self) -> [i32; 4] {
+        let rotation = match self.rotation {
+            90  => [0, 1, -1, 0],
+            180 => [-1, 0, 0, -1],
+            270 => [0, -1, 1, 0],
+            _   => [1, 0, 0, 1]
+        };
+        if self.mirrored {
+            // mirroring first negates the row x comes from
+            [-rotation[0], -rotation[1], rotation[2], rotation[3]]
+        } else {
+            rotation
+        }
+    }
+}
+
+impl Matrix {
+    // The rotation and mirroring, None when the matrix turns by some other
+    // angle, shears or has a perspective part. Scaling is allowed.
+    pub fn orientation(&self) -> Option<Orientation> {
+        if self.u.to_bits() != 0 || self.v.to_bits() != 0 {
+            return None;
+        }
+        let sign = |f: Fixed16_16| (f.to_bits() as i32).signum();
+        let linear = [sign(self.a), sign(self.b), sign(self.c), sign(self.d)];
+
+        [false, true].iter()
+            .flat_map(|&mirrored| [0, 90, 180, 270].iter().map(move |&rotation| Orientation { rotation, mirrored }))
+            .find(|o| o.linear() == linear)
+    }
+
+    // Where a width x height picture ends up: the size of its bounding box
+    // once transformed, so 90 and 270 swap the dimensions
+    pub fn display_size(&self, width: f64, height: f64) -> (f64, f64) {
+        let m = self.to_f64();
+        (m[0][0].abs() * width + m[1][0].abs() * height,
+         m[0][1].abs() * width + m[1][1].abs() * height)
+    }
+
+    // A matrix showing a width x height picture with orientation, moved
+    // back so the picture starts at the origin, as phones write them
+    pub fn for_orientation(orientation: Orientation, width: UFixed16_16, height: UFixed16_16) -> Matrix {
+        let [a, b, c, d] = orientation.linear();
+        let (w, h) = (width.to_bits() as i64, height.to_bits() as i64);
+
+        // the corner the transform takes furthest into negative x and y
+        let corners = [(0, 0), (w, 0), (0, h), (w, h)];
+        let x = corners.iter().map(|&(px, py)| (a as i64) * px + (c as i64) * py).min().unwrap_or(0);
+        let y = corners.iter().map(|&(px, py)| (b as i64) * px + (d as i64) * py).min().unwrap_or(0);
+
+        Matrix {
+            a: Fixed16_16::from_int(a), b: Fixed16_16::from_int(b), u: Fixed2_30::from_int(0),
+            c: Fixed16_16::from_int(c), d: Fixed16_16::from_int(d), v: Fixed2_30::from_int(0),
+            x: Fixed16_16::from_bits((-x) as u32), y: Fixed16_16::from_bits((-y) as u32), w: Fixed2_30::from_int(1)
+        }
+    }
+}
+
+impl Default for Matrix {
+    fn default() -> Matrix { Matrix::identity() }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{} {} {} | {} {} {} | {} {} {}]",
+               self.a, self.b, self.u,
+               self.c, self.d, self.v,
+               self.x, self.y, self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As written by most muxers
+    const IDENTITY: [u32; 9] = [
+        0x0001_0000, 0, 0,
+        0, 0x0001_0000, 0,
+        0, 0, 0x4000_0000
+    ];
+
+    #[test]
+    fn identity_round_trips() {
+        let matrix = Matrix::from_bits(&IDENTITY);
+        assert_eq!(matrix, Matrix::identity());
+        assert_eq!(matrix.to_bits(), IDENTITY);
+        assert_eq!(matrix.to_f64(), [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn w_is_2_30() {
+        let matrix = Matrix::from_bits(&IDENTITY);
+        assert_eq!(matrix.w.to_bits(), 0x4000_0000);
+        assert_eq!(matrix.w.to_f64(), 1.0);
+        assert_eq!(Fixed2_30::from_f64(1.0).to_bits(), 0x4000_0000);
+        assert_eq!(Fixed2_30::from_f64(-1.0).to_bits(), 0xc000_0000);
+        assert_eq!(Fixed2_30::from_bits(0xc000_0000).to_f64(), -1.0);
+    }
+
+    #[test]
+    fn negative_entries_round_trip() {
+        // 90 degree rotation of a 1920 wide picture: b = 1, c = -1, x = 1920
+        let bits = [
+            0, 0x0001_0000, 0,
+            0xffff_0000, 0, 0,
+            0x0780_0000, 0, 0x4000_0000
+        ];
+        let matrix = Matrix::from_bits(&bits);
+        assert_eq!(matrix.c.to_f64(), -1.0);
+        assert_eq!(matrix.x.to_f64(), 1920.0);
+        assert_eq!(matrix.to_bits(), bits);
+
+        let values = matrix.to_f64();
+        let rebuilt = Matrix {
+            a: Fixed16_16::from_f64(values[0][0]), b: Fixed16_16::from_f64(values[0][1]), u: Fixed2_30::from_f64(values[0][2]),
+            c: Fixed16_16::from_f64(values[1][0]), d: Fixed16_16::from_f64(values[1][1]), v: Fixed2_30::from_f64(values[1][2]),
+            x: Fixed16_16::from_f64(values[2][0]), y: Fixed16_16::from_f64(values[2][1]), w: Fixed2_30::from_f64(values[2][2])
+        };
+        assert_eq!(rebuilt, matrix);
+    }
+}
@@ -0,0 +1,61 @@
+use reader::*;
+
+/* ==================================== Opus ==================================== */
+
+// Opus always decodes at 48 kHz, whatever the input rate was
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+// How much audio to decode before a seek point for the output to converge,
+// in 48 kHz samples (80 ms, as RFC 7845 recommends)
+pub const OPUS_PRE_ROLL: u32 = 3840;
+
+// OpusSpecificBox, dOps. Unlike the Ogg header it is big endian.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusSpecificBox {
+    pub version: u8,
+    pub output_channel_count: u8,
+    // 48 kHz samples to drop from the start of the decoded stream
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+    // Q7.8 in dB
+    pub output_gain: i16,
+    pub channel_mapping_family: u8,
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>
+}
+
+impl OpusSpecificBox {
+    pub fn parse(data: &[u8]) -> Result<OpusSpecificBox, String> {
+        let version = read_u8_at(data, 0)?;
+        if version != 0 {
+            return Err(format!("dOps: unknown version {}", version));
+        }
+
+        let output_channel_count = read_u8_at(data, 1)?;
+        let mut dops = OpusSpecificBox {
+            version,
+            output_channel_count,
+            pre_skip:               read_u16_at(data, 2)?,
+            input_sample_rate:      read_u32_at(data, 4)?,
+            output_gain:            read_u16_at(data, 8)? as i16,
+            channel_mapping_family: read_u8_at(data, 10)?,
+            // family 0 is one stream, coupled when stereo
+            stream_count:           1,
+            coupled_count:          if output_channel_count > 1 { 1 } else { 0 },
+            channel_mapping:        vec![]
+        };
+
+        if dops.channel_mapping_family != 0 {
+            dops.stream_count    = read_u8_at(data, 11)?;
+            dops.coupled_count   = read_u8_at(data, 12)?;
+            dops.channel_mapping = read_bytes_at(data, 13, output_channel_count as usize)?.to_vec();
+
+            if dops.coupled_count > dops.stream_count {
+                return Err(format!("dOps: {} coupled streams of {}", dops.coupled_count, dops.stream_count));
+            }
+        }
+
+        Ok(dops)
+    }
+}
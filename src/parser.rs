@@ -1,3 +1,6 @@
+use fixed::*;
+use matrix::*;
+use reader::*;
 use std::io::prelude::*;
 use std::fs::File;
 
@@ -9,10 +12,48 @@ macro_rules! check_length {
     };
 }
 
+// Limits applied while parsing, so that a hostile file turns into an error
+// rather than a panic, a hang or an enormous allocation.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseLimits {
+    pub max_depth: usize,
+    pub max_atom_count: usize,
+    pub max_table_entries: usize,
+    // When false a child overrunning its parent ends the list of children
+    // instead of failing the parse.
+    pub child_must_fit_in_parent: bool
+}
+
+impl ParseLimits {
+    pub fn unlimited() -> ParseLimits {
+        ParseLimits {
+            max_depth: usize::MAX,
+            max_atom_count: usize::MAX,
+            max_table_entries: usize::MAX,
+            child_must_fit_in_parent: false
+        }
+    }
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_depth: 32,
+            max_atom_count: 100_000,
+            max_table_entries: 50_000_000,
+            child_must_fit_in_parent: true
+        }
+    }
+}
+
 pub struct MParser {
     position: usize,
     pub data: Vec<u8>,
-    size: usize
+    size: usize,
+    // reads may not go past limit, which views narrow to the current atom
+    limit: usize,
+    limits: ParseLimits,
+    atom_count: usize
 }
 
 impl MParser {
@@ -22,10 +63,46 @@ impl MParser {
 
     pub fn get_size(&self) -> usize { self.size }
 
-    pub fn get_remaining_bytes(&self) -> usize { self.data.len() - self.position }
+    pub fn get_limit(&self) -> usize { self.limit }
 
-    pub fn get_byte(&self, offset: usize) -> u8 {
-        self.data[self.position + offset]
+    pub fn get_remaining_bytes(&self) -> usize { self.limit.saturating_sub(self.position) }
+
+    pub fn get_byte(&self, offset: usize) -> Result<u8, String> {
+        if offset >= self.get_remaining_bytes() {
+            return Err(format!("get_byte: offset {} from {} is past {}", offset, self.position, self.limit));
+        }
+
+        Ok(self.data[self.position + offset])
+    }
+
+    pub fn get_limits(&self) -> &ParseLimits { &self.limits }
+
+    pub fn set_limits(&mut self, limits: ParseLimits) { self.limits = limits; }
+
+    // Counts an atom against max_atom_count
+    pub fn count_atom(&mut self) -> Result<(), String> {
+        self.atom_count += 1;
+        if self.atom_count > self.limits.max_atom_count {
+            return Err(format!("More than {} atoms", self.limits.max_atom_count));
+        }
+
+        Ok(())
+    }
+
+    // Checks a table's entry count against max_table_entries and against the
+    // bytes actually left, before anything gets allocated for it.
+    pub fn check_table_entries(&self, count: u64, entry_size: usize, table: &str) -> Result<(), String> {
+        if count > self.limits.max_table_entries as u64 {
+            return Err(format!("{} has {} entries, limit is {}", table, count, self.limits.max_table_entries));
+        }
+
+        let needed = count.saturating_mul(entry_size as u64);
+        if needed > self.get_remaining_bytes() as u64 {
+            return Err(format!("{} has {} entries needing {} bytes but only {} remain",
+                               table, count, needed, self.get_remaining_bytes()));
+        }
+
+        Ok(())
     }
 
     pub fn new(filename: &str) -> Result<MParser, String> {
@@ -40,16 +117,27 @@ impl MParser {
             Err(e) => return Err(format!("Couldn't read data in video: {}", e))
         };
 
+        Ok(MParser::from_bytes(data))
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> MParser {
         let size = data.len();
 
-        Ok(MParser{ position: 0, data: data, size: size })
+        MParser{ position: 0, data, size, limit: size, limits: ParseLimits::default(), atom_count: 0 }
     }
 
     pub fn move_cursor(&mut self, delta: isize) -> Result<(), String> {
-        let new_position = ((self.position as isize) + delta) as usize;
+        let new_position = if delta < 0 {
+            match self.position.checked_sub(delta.unsigned_abs()) {
+                Some(p) => p,
+                None    => return Err(format!("Moving parser position {} by {} is before the start", self.position, delta))
+            }
+        } else {
+            self.position.saturating_add(delta as usize)
+        };
 
-        if new_position > self.size {
-            return Err(format!("New parser position {} > parser size {}", new_position, self.size));
+        if new_position > self.limit {
+            return Err(format!("New parser position {} > parser limit {}", new_position, self.limit));
         }
 
         self.position = new_position;
@@ -64,19 +152,28 @@ impl MParser {
 
             let mut r : u32 = 0;
 
-            r = r | ((data[3] as u32) << 0);
-            r = r | ((data[2] as u32) << 8);
-            r = r | ((data[1] as u32) << 16);
-            r = r | ((data[0] as u32) << 24);
+            r |= data[3] as u32;
+            r |= (data[2] as u32) << 8;
+            r |= (data[1] as u32) << 16;
+            r |= (data[0] as u32) << 24;
 
             r
         };
 
-        try!(self.move_cursor(4));
+        self.move_cursor(4)?;
 
         Ok(r)
     }
 
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        check_length!(8, self, "read_u64");
+
+        let high = self.read_u32()? as u64;
+        let low  = self.read_u32()? as u64;
+
+        Ok((high << 32) | low)
+    }
+
     pub fn read_u16(&mut self) -> Result<u16, String> {
         check_length!(2, self, "read_u16");
 
@@ -85,13 +182,13 @@ impl MParser {
 
             let mut r : u16 = 0;
 
-            r = r | ((data[1] as u16) << 0);
-            r = r | ((data[0] as u16) << 8);
+            r |= data[1] as u16;
+            r |= (data[0] as u16) << 8;
 
             r
         };
 
-        try!(self.move_cursor(2));
+        self.move_cursor(2)?;
 
         Ok(r)
     }
@@ -104,14 +201,14 @@ impl MParser {
 
             let mut r : u32 = 0;
 
-            r = r | ((data[2] as u32) << 0);
-            r = r | ((data[1] as u32) << 8);
-            r = r | ((data[0] as u32) << 16);
+            r |= data[2] as u32;
+            r |= (data[1] as u32) << 8;
+            r |= (data[0] as u32) << 16;
 
             r
         };
 
-        try!(self.move_cursor(3));
+        self.move_cursor(3)?;
 
         Ok(r)
     }
@@ -119,39 +216,62 @@ impl MParser {
     pub fn read_u8(&mut self) -> Result<u8, String> {
         check_length!(1, self, "read_u8");
 
-        try!(self.move_cursor(1));
+        let r = self.data[self.position];
+
+        self.move_cursor(1)?;
 
-        Ok(self.data[self.position])
+        Ok(r)
     }
 
     // read_u32 moves cursor
-    pub fn read_fixed32(&mut self) -> Result<f32, String> {
-        let integer = try!(self.read_u32());
-        let float = (integer as f32) / ((!0 as u32) as f32);
+    pub fn read_fixed16_16(&mut self) -> Result<Fixed16_16, String> {
+        Ok(Fixed16_16::from_bits(self.read_u32()?))
+    }
 
-        Ok(float)
+    // read_u32 moves cursor
+    pub fn read_ufixed16_16(&mut self) -> Result<UFixed16_16, String> {
+        Ok(UFixed16_16::from_bits(self.read_u32()?))
     }
 
     // read_u16 moves cursor
-    pub fn read_fixed16(&mut self) -> Result<f32, String> {
-        let integer = try!(self.read_u16());
-        let float = (integer as f32) / ((!0 as u16) as f32);
+    pub fn read_fixed8_8(&mut self) -> Result<Fixed8_8, String> {
+        Ok(Fixed8_8::from_bits(self.read_u16()?))
+    }
 
-        Ok(float)
+    // read_u32 moves cursor
+    pub fn read_fixed2_30(&mut self) -> Result<Fixed2_30, String> {
+        Ok(Fixed2_30::from_bits(self.read_u32()?))
+    }
+
+    pub fn read_matrix(&mut self) -> Result<Matrix, String> {
+        check_length!(36, self, "read_matrix");
+
+        let mut bits = [0u32; 9];
+        for b in bits.iter_mut() {
+            *b = self.read_u32()?;
+        }
+
+        Ok(Matrix::from_bits(&bits))
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>, String> {
+        check_length!(count, self, "read_bytes");
+
+        let r = self.data[self.position .. self.position + count].to_vec();
+
+        self.move_cursor(count as isize)?;
+
+        Ok(r)
     }
 
     pub fn read_string(&mut self) -> Result<String, String> {
         check_length!(4, self, "read_string()");
 
-        let mut bytes = Vec::new();
-        bytes.push(self.get_byte(0));
-        bytes.push(self.get_byte(1));
-        bytes.push(self.get_byte(2));
-        bytes.push(self.get_byte(3));
+        let bytes = vec![self.get_byte(0)?, self.get_byte(1)?, self.get_byte(2)?, self.get_byte(3)?];
 
         match String::from_utf8(bytes) {
             Ok(s)  => {
-                try!(self.move_cursor(4));
+                self.move_cursor(4)?;
 
                 Ok(s)
             },
@@ -159,22 +279,33 @@ impl MParser {
         }
     }
 
-    pub fn get_view_at(&mut self, position: usize) -> MParserView {
-        MParserView::new(position, self)
+    // An immutable reader over the whole file, independent of the cursor
+    pub fn reader(&self) -> AtomReader<'_> {
+        AtomReader::new(&self.data)
+    }
+
+    pub fn get_view_at(&mut self, position: usize) -> MParserView<'_> {
+        let limit = self.limit;
+        MParserView::new(position, limit, self)
     }
 }
 
 pub struct MParserView<'a> {
     initial_position: usize,
+    // the limit of whoever made this view, put back when it's dropped
+    parent_limit: usize,
     parser: &'a mut MParser
 }
 
 impl<'a> MParserView<'a> {
-    fn new(position: usize, parser: &mut MParser) -> MParserView {
+    fn new(position: usize, limit: usize, parser: &mut MParser) -> MParserView<'_> {
+        let parent_limit = parser.limit;
         parser.set_position(position);
+        parser.limit = limit.min(parent_limit);
         MParserView {
             initial_position: position,
-            parser: parser
+            parent_limit,
+            parser
         }
     }
 }
@@ -182,27 +313,48 @@ impl<'a> MParserView<'a> {
 impl<'a> Drop for MParserView<'a> {
     fn drop(&mut self) {
         self.parser.set_position(self.initial_position);
+        self.parser.limit = self.parent_limit;
     }
 }
 
 impl<'a> MParserView<'a> {
     pub fn reset(&mut self) { self.parser.set_position(self.initial_position); }
 
-    pub fn get_view_at(&mut self, position: usize) -> MParserView { MParserView::new(position, self.parser) }
+    pub fn get_view_at(&mut self, position: usize) -> MParserView<'_> {
+        let limit = self.parser.limit;
+        MParserView::new(position, limit, self.parser)
+    }
+
+    // A view that can't read past end, which is also clamped to this view's limit
+    pub fn get_bounded_view(&mut self, position: usize, end: usize) -> MParserView<'_> {
+        MParserView::new(position, end, self.parser)
+    }
+
+    pub fn get_limit(&self) -> usize { self.parser.get_limit() }
+    pub fn get_limits(&self) -> &ParseLimits { self.parser.get_limits() }
+    pub fn count_atom(&mut self) -> Result<(), String> { self.parser.count_atom() }
+    pub fn check_table_entries(&self, count: u64, entry_size: usize, table: &str) -> Result<(), String> {
+        self.parser.check_table_entries(count, entry_size, table)
+    }
 
     pub fn move_cursor(&mut self, delta: isize) -> Result<(), String> { self.parser.move_cursor(delta) }
     pub fn get_position(&self) -> usize { self.parser.get_position() }
     pub fn set_position(&mut self, new_position: usize) { self.parser.set_position(new_position); }
 
-    pub fn get_byte(&self, offset: usize) -> u8 { self.parser.get_byte(offset) }
+    pub fn get_byte(&self, offset: usize) -> Result<u8, String> { self.parser.get_byte(offset) }
     pub fn get_remaining_bytes(&self) -> usize { self.parser.get_remaining_bytes() }
 
+    pub fn read_u64(&mut self) -> Result<u64, String> { self.parser.read_u64() }
     pub fn read_u32(&mut self) -> Result<u32, String> { self.parser.read_u32() }
     pub fn read_u16(&mut self) -> Result<u16, String> { self.parser.read_u16() }
     pub fn read_u8(&mut self)  -> Result<u8, String>  { self.parser.read_u8() }
-    pub fn read_fixed32(&mut self) -> Result<f32, String> { self.parser.read_fixed32() }
-    pub fn read_fixed16(&mut self) -> Result<f32, String> { self.parser.read_fixed16() }
+    pub fn read_fixed16_16(&mut self) -> Result<Fixed16_16, String> { self.parser.read_fixed16_16() }
+    pub fn read_ufixed16_16(&mut self) -> Result<UFixed16_16, String> { self.parser.read_ufixed16_16() }
+    pub fn read_fixed8_8(&mut self) -> Result<Fixed8_8, String> { self.parser.read_fixed8_8() }
+    pub fn read_fixed2_30(&mut self) -> Result<Fixed2_30, String> { self.parser.read_fixed2_30() }
+    pub fn read_matrix(&mut self) -> Result<Matrix, String> { self.parser.read_matrix() }
     pub fn read_flags(&mut self) -> Result<u32, String> { self.parser.read_flags() }
+    pub fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>, String> { self.parser.read_bytes(count) }
     pub fn read_string(&mut self) -> Result<String, String> { self.parser.read_string() }
 }
 
@@ -215,15 +367,11 @@ impl ParserAction<String> for TypeParserAction {
     fn try_parse(parser: &mut MParserView) -> Result<String, String> {
         check_length!(4, parser, "TypeParserAction");
 
-        let mut bytes = Vec::new();
-        bytes.push(parser.get_byte(0));
-        bytes.push(parser.get_byte(1));
-        bytes.push(parser.get_byte(2));
-        bytes.push(parser.get_byte(3));
+        let bytes = vec![parser.get_byte(0)?, parser.get_byte(1)?, parser.get_byte(2)?, parser.get_byte(3)?];
 
         match String::from_utf8(bytes) {
             Ok(s)  => {
-                try!(parser.move_cursor(4));
+                parser.move_cursor(4)?;
 
                 Ok(s)
             },
@@ -0,0 +1,90 @@
+use reader::*;
+use stsd::*;
+
+/* ===================================== PCM ===================================== */
+
+// Flags of an lpcm entry, as in Core Audio's AudioStreamBasicDescription
+pub const LPCM_FLAG_FLOAT: u32 = 0x1;
+pub const LPCM_FLAG_BIG_ENDIAN: u32 = 0x2;
+pub const LPCM_FLAG_SIGNED: u32 = 0x4;
+
+// How the frames of an uncompressed audio track are laid out. A frame holds
+// one sample of each channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    pub float: bool,
+    pub big_endian: bool,
+    pub signed: bool,
+    pub bytes_per_frame: u32
+}
+
+impl PcmFormat {
+    // From an lpcm, sowt, twos, in24, in32, fl32, fl64, ipcm or fpcm
+    // entry, None for anything compressed
+    pub fn from_entry(entry: &SampleEntry) -> Result<Option<PcmFormat>, String> {
+        let audio = match entry.audio() {
+            Some(a) => a,
+            None    => return Ok(None)
+        };
+        let channels = audio.channel_count;
+
+        // QuickTime formats are big endian unless the wave box has an enda
+        // saying otherwise, which in24 and in32 sometimes do
+        let big_endian = match entry.child_or_in_wave("enda") {
+            Some(enda) => read_u16_at(enda, 0)? == 0,
+            None       => true
+        };
+
+        let (bits_per_sample, float, big_endian, signed) = match entry.typ.as_str() {
+            "sowt" => (16, false, false, true),
+            "twos" => (if audio.sample_size == 8 { 8 } else { 16 }, false, true, true),
+            "in24" => (24, false, big_endian, true),
+            "in32" => (32, false, big_endian, true),
+            "fl32" => (32, true, big_endian, true),
+            "fl64" => (64, true, big_endian, true),
+            "lpcm" => {
+                let flags = audio.format_specific_flags;
+                (audio.sample_size, flags & LPCM_FLAG_FLOAT != 0, flags & LPCM_FLAG_BIG_ENDIAN != 0,
+                 flags & LPCM_FLAG_SIGNED != 0 || flags & LPCM_FLAG_FLOAT != 0)
+            },
+            "ipcm" | "fpcm" => {
+                // pcmC: version and flags, format_flags, PCM_sample_size
+                let pcmc = match entry.child("pcmC") {
+                    Some(c) => &c.payload,
+                    None    => return Err(format!("{} entry has no pcmC", entry.typ))
+                };
+                let little_endian = read_u8_at(pcmc, 4)? & 0x1 != 0;
+                (read_u8_at(pcmc, 5)? as u32, entry.typ == "fpcm", !little_endian, true)
+            },
+            _ => return Ok(None)
+        };
+
+        if bits_per_sample == 0 || !bits_per_sample.is_multiple_of(8) || bits_per_sample > 64 {
+            return Err(format!("{} entry with {} bits per sample", entry.typ, bits_per_sample));
+        }
+        if channels == 0 {
+            return Err(format!("{} entry with no channels", entry.typ));
+        }
+
+        // the entry's own frame size when it has one, QuickTime version 1
+        // and 2 sound descriptions do
+        let bytes_per_frame = match audio.version {
+            1 if audio.bytes_per_frame != 0 => audio.bytes_per_frame,
+            2 if audio.const_bytes_per_audio_packet != 0 && audio.const_lpcm_frames_per_audio_packet == 1 => {
+                audio.const_bytes_per_audio_packet
+            },
+            _ => channels.saturating_mul(bits_per_sample / 8)
+        };
+
+        Ok(Some(PcmFormat { channels, bits_per_sample, float, big_endian, signed, bytes_per_frame }))
+    }
+
+    // e.g. "s16le" or "f32be", in the style of ffmpeg's sample formats
+    pub fn name(&self) -> String {
+        let kind = if self.float { "f" } else if self.signed { "s" } else { "u" };
+        let endian = if self.bits_per_sample == 8 { "" } else if self.big_endian { "be" } else { "le" };
+        format!("{}{}{}", kind, self.bits_per_sample, endian)
+    }
+}
@@ -0,0 +1,334 @@
+use fixed::*;
+use matrix::*;
+use parser::ParseLimits;
+use std::fmt;
+use std::str;
+
+/* ============================ Zero copy atom reader ============================ */
+
+// Unlike MParser this has no cursor: everything is read at an explicit offset
+// from an immutable slice, and children are handed out as borrowed sub-slices
+// of their parent. An AtomRef is Copy, Send and Sync, so atoms can be held in
+// structs, looked at side by side and shared with worker threads.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCC(pub [u8; 4]);
+
+impl FourCC {
+    pub fn as_str(&self) -> Option<&str> { str::from_utf8(&self.0).ok() }
+}
+
+impl<'a> PartialEq<&'a str> for FourCC {
+    fn eq(&self, other: &&'a str) -> bool { &self.0[..] == other.as_bytes() }
+}
+
+impl fmt::Display for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in &self.0 {
+            if (0x20..0x7f).contains(&b) {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}'", self)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AtomRef<'a> {
+    // offset of the first header byte within the source the reader was made from
+    pub location: usize,
+    pub typ: FourCC,
+    pub header_size: usize,
+    // the whole atom, header included
+    pub data: &'a [u8]
+}
+
+impl<'a> AtomRef<'a> {
+    pub fn size(&self) -> usize { self.data.len() }
+
+    pub fn payload(&self) -> &'a [u8] { &self.data[self.header_size..] }
+
+    pub fn payload_location(&self) -> usize { self.location + self.header_size }
+
+    // Children start at offset bytes into the payload, which is 0 for plain
+    // containers and non zero for things like stsd that have fields first.
+    pub fn children_at(&self, offset: usize) -> Result<AtomReader<'a>, String> {
+        let payload = self.payload();
+        if offset > payload.len() {
+            return Err(format!("{} @ {}: children at {} but payload is {} bytes",
+                               self.typ, self.location, offset, payload.len()));
+        }
+
+        Ok(AtomReader::with_location(&payload[offset..], self.payload_location() + offset))
+    }
+
+    pub fn children(&self) -> AtomReader<'a> {
+        AtomReader::with_location(self.payload(), self.payload_location())
+    }
+
+    // Full box helpers, relative to the payload
+    pub fn version(&self) -> Result<u8, String> { read_u8_at(self.payload(), 0) }
+    pub fn flags(&self) -> Result<u32, String> { read_u24_at(self.payload(), 1) }
+
+    pub fn read_u8(&self, offset: usize) -> Result<u8, String> { read_u8_at(self.payload(), offset) }
+    pub fn read_u16(&self, offset: usize) -> Result<u16, String> { read_u16_at(self.payload(), offset) }
+    pub fn read_u24(&self, offset: usize) -> Result<u32, String> { read_u24_at(self.payload(), offset) }
+    pub fn read_u32(&self, offset: usize) -> Result<u32, String> { read_u32_at(self.payload(), offset) }
+    pub fn read_u64(&self, offset: usize) -> Result<u64, String> { read_u64_at(self.payload(), offset) }
+    pub fn read_bytes(&self, offset: usize, length: usize) -> Result<&'a [u8], String> {
+        read_bytes_at(self.payload(), offset, length)
+    }
+    pub fn read_fixed16_16(&self, offset: usize) -> Result<Fixed16_16, String> {
+        Ok(Fixed16_16::from_bits(self.read_u32(offset)?))
+    }
+    pub fn read_fixed8_8(&self, offset: usize) -> Result<Fixed8_8, String> {
+        Ok(Fixed8_8::from_bits(self.read_u16(offset)?))
+    }
+    pub fn read_matrix(&self, offset: usize) -> Result<Matrix, String> {
+        let mut bits = [0u32; 9];
+        for (i, b) in bits.iter_mut().enumerate() {
+            *b = self.read_u32(offset.saturating_add(i * 4))?;
+        }
+        Ok(Matrix::from_bits(&bits))
+    }
+}
+
+impl<'a> fmt::Display for AtomRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} @ {} with size {}", self.typ, self.location, self.size())
+    }
+}
+
+// Iterates the atoms laid out back to back in a slice.
+#[derive(Clone, Copy)]
+pub struct AtomReader<'a> {
+    data: &'a [u8],
+    location: usize
+}
+
+impl<'a> AtomReader<'a> {
+    pub fn new(data: &'a [u8]) -> AtomReader<'a> { AtomReader { data, location: 0 } }
+
+    // location is where data starts in the original source, so the atoms
+    // handed out report absolute offsets.
+    pub fn with_location(data: &'a [u8], location: usize) -> AtomReader<'a> {
+        AtomReader { data, location }
+    }
+
+    pub fn data(&self) -> &'a [u8] { self.data }
+
+    pub fn location(&self) -> usize { self.location }
+
+    pub fn atom_at(&self, offset: usize) -> Result<AtomRef<'a>, String> {
+        let data = &self.data[offset.min(self.data.len())..];
+        let location = self.location + offset;
+
+        if data.len() < 8 {
+            return Err(format!("atom header @ {} needs 8 bytes, have {}", location, data.len()));
+        }
+
+        let typ = FourCC([data[4], data[5], data[6], data[7]]);
+        let mut header_size = 8;
+        let size = match read_u32_at(data, 0)? {
+            0 => data.len() as u64,
+            1 => {
+                header_size = 16;
+                read_u64_at(data, 8)?
+            },
+            s => s as u64
+        };
+
+        if typ == "uuid" {
+            header_size += 16;
+        }
+
+        if size < header_size as u64 {
+            return Err(format!("{} @ {} has size {} smaller than its header", typ, location, size));
+        }
+        if size > data.len() as u64 {
+            return Err(format!("{} @ {} has size {} but only {} bytes remain", typ, location, size, data.len()));
+        }
+
+        Ok(AtomRef { location, typ, header_size, data: &data[..size as usize] })
+    }
+
+    pub fn iter(&self) -> AtomIter<'a> { AtomIter { reader: *self, offset: 0, failed: false } }
+
+    pub fn find(&self, typ: &str) -> Option<AtomRef<'a>> {
+        self.iter().filter_map(|a| a.ok()).find(|a| a.typ == typ)
+    }
+
+    pub fn find_all(&self, typ: &'a str) -> impl Iterator<Item = AtomRef<'a>> + 'a {
+        self.iter().filter_map(|a| a.ok()).filter(move |a| a.typ == typ)
+    }
+}
+
+impl<'a> IntoIterator for AtomReader<'a> {
+    type Item = Result<AtomRef<'a>, String>;
+    type IntoIter = AtomIter<'a>;
+
+    fn into_iter(self) -> AtomIter<'a> { self.iter() }
+}
+
+pub struct AtomIter<'a> {
+    reader: AtomReader<'a>,
+    offset: usize,
+    failed: bool
+}
+
+impl<'a> Iterator for AtomIter<'a> {
+    type Item = Result<AtomRef<'a>, String>;
+
+    fn next(&mut self) -> Option<Result<AtomRef<'a>, String>> {
+        if self.failed || self.offset >= self.reader.data.len() {
+            return None;
+        }
+
+        match self.reader.atom_at(self.offset) {
+            Ok(atom) => {
+                self.offset += atom.size();
+                Some(Ok(atom))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/* ================================= Atom tree ================================= */
+
+// Where the children of a container start within its payload, or None when
+// the atom is a leaf (or a container we don't descend into).
+pub fn children_offset(typ: FourCC) -> Option<usize> {
+    match &typ.0 {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"edts" | b"dinf" |
+        b"mvex" | b"moof" | b"traf" | b"mfra" | b"udta" | b"tref" | b"sinf" |
+        b"schi" | b"gmhd" | b"tapt" | b"clip" | b"matt" | b"imap" => Some(0),
+        b"stsd" | b"dref" => Some(8),
+        _ => None
+    }
+}
+
+// A parsed tree of borrowed atoms. Holds nothing but references into the
+// source, so it is cheap to build and can be shared between threads.
+pub struct AtomNode<'a> {
+    pub atom: AtomRef<'a>,
+    pub children: Vec<AtomNode<'a>>
+}
+
+impl<'a> AtomNode<'a> {
+    pub fn parse(atom: AtomRef<'a>) -> Result<AtomNode<'a>, String> {
+        AtomNode::parse_with_limits(atom, &ParseLimits::default())
+    }
+
+    pub fn parse_with_limits(atom: AtomRef<'a>, limits: &ParseLimits) -> Result<AtomNode<'a>, String> {
+        let mut atom_count = 0;
+        AtomNode::parse_limited(atom, limits, 0, &mut atom_count)
+    }
+
+    fn parse_limited(atom: AtomRef<'a>, limits: &ParseLimits, depth: usize, atom_count: &mut usize)
+                     -> Result<AtomNode<'a>, String> {
+        if depth > limits.max_depth {
+            return Err(format!("Atoms nested deeper than {}", limits.max_depth));
+        }
+
+        *atom_count += 1;
+        if *atom_count > limits.max_atom_count {
+            return Err(format!("More than {} atoms", limits.max_atom_count));
+        }
+
+        let mut children = vec![];
+
+        if let Some(offset) = children_offset(atom.typ) {
+            for child in atom.children_at(offset)? {
+                let child = match child {
+                    Ok(c)  => c,
+                    Err(e) => if limits.child_must_fit_in_parent { return Err(e) } else { break }
+                };
+                children.push(AtomNode::parse_limited(child, limits, depth + 1, atom_count)?);
+            }
+        }
+
+        Ok(AtomNode { atom, children })
+    }
+
+    // Parses every atom in data (e.g. a whole file) into a list of trees.
+    pub fn parse_all(reader: AtomReader<'a>) -> Result<Vec<AtomNode<'a>>, String> {
+        AtomNode::parse_all_with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn parse_all_with_limits(reader: AtomReader<'a>, limits: &ParseLimits) -> Result<Vec<AtomNode<'a>>, String> {
+        let mut nodes = vec![];
+        let mut atom_count = 0;
+        for atom in reader {
+            nodes.push(AtomNode::parse_limited(atom?, limits, 0, &mut atom_count)?);
+        }
+        Ok(nodes)
+    }
+
+    pub fn child(&self, typ: &str) -> Option<&AtomNode<'a>> {
+        self.children.iter().find(|c| c.atom.typ == typ)
+    }
+
+    pub fn children_of_type<'b>(&'b self, typ: &'b str) -> impl Iterator<Item = &'b AtomNode<'a>> + 'b {
+        self.children.iter().filter(move |c| c.atom.typ == typ)
+    }
+
+    // Follows a path of types such as ["mdia", "minf", "stbl"], taking the
+    // first match at each level.
+    pub fn descend(&self, path: &[&str]) -> Option<&AtomNode<'a>> {
+        let mut node = self;
+        for typ in path {
+            node = node.child(typ)?;
+        }
+        Some(node)
+    }
+}
+
+/* ================================ Byte helpers ================================ */
+
+fn out_of_range(what: &str, offset: usize, length: usize, have: usize) -> String {
+    format!("{} at {} needs {} bytes, have {}", what, offset, length, have.saturating_sub(offset))
+}
+
+pub fn read_bytes_at(data: &[u8], offset: usize, length: usize) -> Result<&[u8], String> {
+    match offset.checked_add(length) {
+        Some(end) if end <= data.len() => Ok(&data[offset..end]),
+        _ => Err(out_of_range("read_bytes", offset, length, data.len()))
+    }
+}
+
+pub fn read_u8_at(data: &[u8], offset: usize) -> Result<u8, String> {
+    Ok(read_bytes_at(data, offset, 1)?[0])
+}
+
+pub fn read_u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    let b = read_bytes_at(data, offset, 2)?;
+    Ok(((b[0] as u16) << 8) | (b[1] as u16))
+}
+
+pub fn read_u24_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    let b = read_bytes_at(data, offset, 3)?;
+    Ok(((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32))
+}
+
+pub fn read_u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    let b = read_bytes_at(data, offset, 4)?;
+    Ok(((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8) | (b[3] as u32))
+}
+
+pub fn read_u64_at(data: &[u8], offset: usize) -> Result<u64, String> {
+    let high = read_u32_at(data, offset)? as u64;
+    let low  = read_u32_at(data, offset.saturating_add(4))? as u64;
+    Ok((high << 32) | low)
+}
@@ -0,0 +1,657 @@
+use stbl::*;
+
+/* ================================ Sample index ================================ */
+
+// Everything about one sample, gathered from the separate stbl tables. Times
+// are in media time scale units, index is 0 based.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub index: u32,
+    pub dts: u64,
+    pub pts: i64,
+    pub duration: u32,
+    pub offset: u64,
+    pub size: u32,
+    // 1 based, as in the file
+    pub chunk: u32,
+    pub is_sync: bool,
+    // listed in stps, e.g. an open GOP I frame
+    pub is_partial_sync: bool,
+    // from sdtp when there is one
+    pub dependency: Option<SampleDependency>,
+    pub sample_description_index: u32
+}
+
+impl Sample {
+    pub fn end(&self) -> u64 { self.offset + (self.size as u64) }
+
+    // Somewhere decoding can start
+    pub fn is_random_access_point(&self) -> bool { self.is_sync || self.is_partial_sync }
+}
+
+// stts, ctts and stsc as runs, each knowing the first sample it covers so a
+// sample's run can be found with a binary search
+#[derive(Clone, Copy, Debug)]
+struct TimeRun {
+    first_sample: u64,
+    first_dts: u64,
+    count: u32,
+    delta: u32
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OffsetRun {
+    first_sample: u64,
+    count: u32,
+    offset: i64
+}
+
+// chunks first_chunk..first_chunk + chunk_count (1 based), each holding
+// samples_per_chunk samples
+#[derive(Clone, Copy, Debug)]
+struct ChunkRun {
+    first_sample: u64,
+    first_chunk: u32,
+    chunk_count: u32,
+    samples_per_chunk: u32,
+    sample_description_index: u32,
+    // bytes in a sample when stsz's size is really a frame count
+    frame_size: Option<u32>
+}
+
+impl ChunkRun {
+    fn sample_count(&self) -> u64 { (self.chunk_count as u64) * (self.samples_per_chunk as u64) }
+}
+
+// Index of the run covering sample, the last one starting at or before it
+fn run_for<T, F: Fn(&T) -> u64>(runs: &[T], first_sample: F, sample: u64) -> Option<usize> {
+    let after = runs.partition_point(|r| first_sample(r) <= sample);
+    after.checked_sub(1)
+}
+
+// Where a walk through the samples is, so that stepping to the next sample
+// doesn't have to look its runs up again
+#[derive(Clone, Copy, Debug, Default)]
+struct Cursor {
+    next: usize,
+    time_run: usize,
+    offset_run: usize,
+    chunk_run: usize,
+    // file offset the next sample starts at when it's in the same chunk
+    offset: u64
+}
+
+// The one place the stbl tables are put together. Analysis and trimming both
+// go through this rather than decoding the tables themselves. The tables stay
+// in their run length form, per sample values are worked out when asked for.
+pub struct SampleIndex<'a> {
+    stbl: &'a SampleTableAtom,
+    time_scale: u32,
+    sample_count: usize,
+    time_runs: Vec<TimeRun>,
+    offset_runs: Vec<OffsetRun>,
+    // least and greatest composition offset of any sample
+    offset_range: (i64, i64),
+    chunk_runs: Vec<ChunkRun>,
+    chunk_offsets: &'a [u64],
+    sizes: &'a SampleSizeAtom
+}
+
+impl<'a> SampleIndex<'a> {
+    pub fn new(stbl: &'a SampleTableAtom, time_scale: u32) -> Result<SampleIndex<'a>, String> {
+        if time_scale == 0 {
+            return Err("Media time scale is 0".to_string());
+        }
+
+        let (stts, stsc, stco, stsz) = match (&stbl.stts, &stbl.stsc, &stbl.stco, &stbl.stsz) {
+            (Some(stts), Some(stsc), Some(stco), Some(stsz)) => (stts, stsc, stco, stsz),
+            _ => return Err("stbl is missing one of stts, stsc, stco/co64 or stsz/stz2".to_string())
+        };
+        let sample_count = stsz.sample_count as usize;
+
+        let mut time_runs = Vec::with_capacity(stts.entries.len());
+        let (mut first_sample, mut first_dts) = (0u64, 0u64);
+        for entry in stts.entries.iter().filter(|e| e.sample_count > 0) {
+            time_runs.push(TimeRun { first_sample, first_dts, count: entry.sample_count, delta: entry.sample_delta });
+            first_sample += entry.sample_count as u64;
+            first_dts = first_dts.saturating_add((entry.sample_count as u64) * (entry.sample_delta as u64));
+        }
+        if first_sample != sample_count as u64 {
+            return Err(format!("stts describes {} samples but stsz has {}", first_sample, sample_count));
+        }
+
+        let mut offset_runs = vec![];
+        if let Some(ref ctts) = stbl.ctts {
+            let mut first_sample = 0u64;
+            for entry in ctts.entries.iter().filter(|e| e.sample_count > 0) {
+                offset_runs.push(OffsetRun { first_sample, count: entry.sample_count, offset: entry.sample_offset });
+                first_sample += entry.sample_count as u64;
+            }
+        }
+        let mut offset_range = offset_runs.iter().fold(None, |range: Option<(i64, i64)>, r| match range {
+            Some((least, greatest)) => Some((least.min(r.offset), greatest.max(r.offset))),
+            None                    => Some((r.offset, r.offset))
+        }).unwrap_or((0, 0));
+        // samples past the end of ctts have no offset
+        if offset_runs.last().is_none_or(|r| r.first_sample + (r.count as u64) < sample_count as u64) {
+            offset_range = (offset_range.0.min(0), offset_range.1.max(0));
+        }
+
+        // QuickTime PCM tracks give stsz a size of 1 and count frames in
+        // stsc, the bytes in a frame come from the sample entry
+        let frame_size = |description: u32| -> Option<u32> {
+            if stsz.sample_size != 1 {
+                return None;
+            }
+            let entry = stbl.stsd.as_ref()?.entry(description)?;
+            entry.pcm_format().ok()?.map(|f| f.bytes_per_frame).filter(|&size| size > 1)
+        };
+
+        let mut chunk_runs = Vec::with_capacity(stsc.entries.len());
+        let mut first_sample = 0u64;
+        for (i, entry) in stsc.entries.iter().enumerate() {
+            // an entry covers chunks up to the next entry's first chunk
+            let last_chunk = match stsc.entries.get(i + 1) {
+                Some(next) => next.first_chunk,
+                None       => stco.offsets.len() as u32 + 1
+            };
+            if last_chunk > stco.offsets.len() as u32 + 1 {
+                return Err(format!("stsc refers to chunk {} but there are only {}", last_chunk - 1, stco.offsets.len()));
+            }
+
+            let run = ChunkRun {
+                first_sample,
+                first_chunk: entry.first_chunk,
+                chunk_count: last_chunk.saturating_sub(entry.first_chunk),
+                samples_per_chunk: entry.samples_per_chunk,
+                sample_description_index: entry.sample_description_index,
+                frame_size: frame_size(entry.sample_description_index)
+            };
+            if run.sample_count() > 0 {
+                first_sample += run.sample_count();
+                chunk_runs.push(run);
+            }
+        }
+        if first_sample < sample_count as u64 {
+            return Err(format!("stsc and stco describe {} samples but stsz has {}", first_sample, sample_count));
+        }
+
+        Ok(SampleIndex {
+            stbl, time_scale, sample_count, time_runs, offset_runs, offset_range, chunk_runs,
+            chunk_offsets: &stco.offsets,
+            sizes: stsz
+        })
+    }
+
+    pub fn len(&self) -> usize { self.sample_count }
+
+    pub fn is_empty(&self) -> bool { self.sample_count == 0 }
+
+    pub fn time_scale(&self) -> u32 { self.time_scale }
+
+    // Whether stsz has the QuickTime PCM size of 1 standing for a frame,
+    // which a rewritten stsz should keep
+    pub fn sizes_count_frames(&self) -> bool {
+        self.chunk_runs.iter().any(|r| r.frame_size.is_some())
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.time_runs.last().map(|r| r.first_dts + (r.count as u64) * (r.delta as u64)).unwrap_or(0)
+    }
+
+    // The least and greatest composition offset, so that every sample's pts
+    // is between its dts plus the one and the other
+    pub fn composition_offset_range(&self) -> (i64, i64) { self.offset_range }
+
+    // Where the last presented sample ends, the greatest pts + duration
+    pub fn presentation_end(&self) -> i64 {
+        let last = match self.len().checked_sub(1).and_then(|i| self.get(i)) {
+            Some(s) => s,
+            None    => return 0
+        };
+
+        // a sample ends by the next one's dts plus the greatest offset, so
+        // only those decoded near the end can beat the last one
+        let (_, greatest) = self.offset_range;
+        let end = last.pts + (last.duration as i64);
+        let from = self.decode_index(end - greatest);
+        self.iter_from(from).map(|s| s.pts + (s.duration as i64)).max().unwrap_or(end)
+    }
+
+    pub fn get(&self, index: usize) -> Option<Sample> {
+        if index >= self.sample_count {
+            return None;
+        }
+        let mut cursor = self.cursor_at(index)?;
+        self.advance(&mut cursor)
+    }
+
+    // A cursor for walking from sample index onwards
+    fn cursor_at(&self, index: usize) -> Option<Cursor> {
+        let sample = index as u64;
+        let time_run = run_for(&self.time_runs, |r| r.first_sample, sample).unwrap_or(0);
+        let offset_run = run_for(&self.offset_runs, |r| r.first_sample, sample).unwrap_or(0);
+        let chunk_run = run_for(&self.chunk_runs, |r| r.first_sample, sample)?;
+
+        // add up the sizes of the samples before this one in its chunk
+        let run = &self.chunk_runs[chunk_run];
+        let in_chunk = (sample - run.first_sample) % (run.samples_per_chunk as u64);
+        let chunk = (run.first_chunk as u64) + (sample - run.first_sample) / (run.samples_per_chunk as u64);
+        let mut offset = self.chunk_offsets.get((chunk as usize).wrapping_sub(1)).cloned().unwrap_or(0);
+        if let Some(frame_size) = run.frame_size {
+            offset = offset.saturating_add(in_chunk * (frame_size as u64));
+        } else if self.sizes.sample_size != 0 {
+            offset = offset.saturating_add(in_chunk * (self.sizes.sample_size as u64));
+        } else {
+            for i in (index - in_chunk as usize)..index {
+                offset = offset.saturating_add(self.sizes.size_of(i) as u64);
+            }
+        }
+
+        Some(Cursor { next: index, time_run, offset_run, chunk_run, offset })
+    }
+
+    // The sample at the cursor, moving it on to the next one
+    fn advance(&self, cursor: &mut Cursor) -> Option<Sample> {
+        let index = cursor.next;
+        if index >= self.sample_count {
+            return None;
+        }
+        let sample = index as u64;
+
+        while cursor.time_run + 1 < self.time_runs.len() && self.time_runs[cursor.time_run + 1].first_sample <= sample {
+            cursor.time_run += 1;
+        }
+        let time = self.time_runs.get(cursor.time_run)?;
+        let dts = time.first_dts + (sample - time.first_sample) * (time.delta as u64);
+
+        while cursor.offset_run + 1 < self.offset_runs.len() && self.offset_runs[cursor.offset_run + 1].first_sample <= sample {
+            cursor.offset_run += 1;
+        }
+        // samples past the end of ctts have no offset
+        let composition_offset = match self.offset_runs.get(cursor.offset_run) {
+            Some(r) if sample >= r.first_sample && sample < r.first_sample + (r.count as u64) => r.offset,
+            _ => 0
+        };
+
+        while cursor.chunk_run + 1 < self.chunk_runs.len() && self.chunk_runs[cursor.chunk_run + 1].first_sample <= sample {
+            cursor.chunk_run += 1;
+        }
+        let run = self.chunk_runs.get(cursor.chunk_run)?;
+        let in_run = sample - run.first_sample;
+        let chunk = run.first_chunk + (in_run / (run.samples_per_chunk as u64)) as u32;
+        let offset = if in_run.is_multiple_of(run.samples_per_chunk as u64) {
+            *self.chunk_offsets.get((chunk as usize).wrapping_sub(1))?
+        } else {
+            cursor.offset
+        };
+        let size = run.frame_size.unwrap_or_else(|| self.sizes.size_of(index));
+
+        cursor.next += 1;
+        cursor.offset = offset.saturating_add(size as u64);
+
+        Some(Sample {
+            index: index as u32,
+            dts,
+            pts: (dts as i64) + composition_offset,
+            duration: time.delta,
+            offset,
+            size,
+            chunk,
+            is_sync: self.is_sync(index),
+            is_partial_sync: self.is_partial_sync(index),
+            dependency: self.dependency(index),
+            sample_description_index: run.sample_description_index
+        })
+    }
+
+    // Errors if any sample lies outside a file of file_size bytes
+    pub fn check_locations(&self, file_size: u64) -> Result<(), String> {
+        let mut outside = self.iter().filter(|s| s.end() > file_size);
+
+        match outside.next() {
+            Some(first) => Err(format!("{} samples point outside the file, the first is sample {} @ {} with size {}",
+                                       outside.count() + 1, first.index, first.offset, first.size)),
+            None        => Ok(())
+        }
+    }
+
+    pub fn sync_samples(&self) -> SyncSamples<'a> { self.stbl.sync_samples() }
+
+    pub fn is_sync(&self, index: usize) -> bool { self.sync_samples().is_sync(index) }
+
+    pub fn is_partial_sync(&self, index: usize) -> bool {
+        match self.stbl.stps {
+            Some(ref stps) => stps.contains(index),
+            None           => false
+        }
+    }
+
+    pub fn dependency(&self, index: usize) -> Option<SampleDependency> {
+        self.stbl.sdtp.as_ref()?.entries.get(index).cloned()
+    }
+
+    // The sgpd entry (1 based) sample index is mapped to by the sbgp of
+    // grouping_type and grouping_type_parameter, 0 when it's in no group
+    pub fn group_description_index(&self, index: usize, grouping_type: &str, grouping_type_parameter: Option<u32>) -> u32 {
+        self.stbl.group_description_index(index, grouping_type, grouping_type_parameter)
+    }
+
+    pub fn group_entry(&self, index: usize, grouping_type: &str, grouping_type_parameter: Option<u32>) -> Option<&'a SampleGroupEntry> {
+        let description = self.group_description_index(index, grouping_type, grouping_type_parameter);
+        self.stbl.sample_group_description(grouping_type)?.entry(description)
+    }
+
+    // Grouping types and parameters with an sbgp, then types that only have
+    // an sgpd, in table order without repeats
+    pub fn grouping_types(&self) -> Vec<(&'a str, Option<u32>)> {
+        let mut types: Vec<(&'a str, Option<u32>)> = vec![];
+        for sbgp in &self.stbl.sbgp {
+            let key = (sbgp.grouping_type.as_str(), sbgp.grouping_type_parameter);
+            if !types.contains(&key) {
+                types.push(key);
+            }
+        }
+        for sgpd in &self.stbl.sgpd {
+            if !types.iter().any(|&(typ, _)| typ == sgpd.grouping_type) {
+                types.push((sgpd.grouping_type.as_str(), None));
+            }
+        }
+        types
+    }
+
+    // From the roll group, how many samples before this one (negative) need
+    // decoding first, e.g. AAC pre-roll
+    pub fn roll_distance(&self, index: usize) -> Option<i16> {
+        match self.group_entry(index, "roll", None) {
+            Some(&SampleGroupEntry::Roll { roll_distance }) => Some(roll_distance),
+            _ => None
+        }
+    }
+
+    // Where to start decoding so that the sample presented at time comes out
+    // right: the latest sync sample, in decode order, that is presented no
+    // later than time. Samples between it and time get decoded but should be
+    // hidden with an edit list.
+    pub fn cut_point_for_presentation_time(&self, time: i64) -> Option<usize> {
+        self.cut_point_where(time, |_| true)
+    }
+
+    // As cut_point_for_presentation_time, but skipping sync samples accept
+    // turns down, for codecs whose stss can't be trusted
+    pub fn cut_point_where<F: Fn(&Sample) -> bool>(&self, time: i64, accept: F) -> Option<usize> {
+        let target = self.sample_at_presentation_time(time)?;
+        let sync = self.sync_samples();
+
+        let mut candidate = sync.at_or_before(target);
+        while let Some(index) = candidate {
+            if self.get(index).is_some_and(|s| s.pts <= time && accept(&s)) {
+                return Some(index);
+            }
+            candidate = if index == 0 { None } else { sync.at_or_before(index - 1) };
+        }
+
+        // nothing presented early enough, so start from the first sync sample
+        (0..self.len()).find(|&i| sync.is_sync(i) && self.get(i).is_some_and(|s| accept(&s)))
+    }
+
+    pub fn iter(&self) -> SampleIter<'_, 'a> { self.iter_from(0) }
+
+    // Samples from index onwards, in decode order
+    pub fn iter_from(&self, index: usize) -> SampleIter<'_, 'a> {
+        let cursor = self.cursor_at(index).unwrap_or(Cursor { next: self.sample_count, ..Cursor::default() });
+        SampleIter { index: self, cursor }
+    }
+
+    // The sample whose decode interval [dts, dts + duration) covers time.
+    // Times past the end give the last sample.
+    pub fn sample_at_decode_time(&self, time: u64) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // last run starting at or before time, then the sample within it
+        let run = &self.time_runs[run_for(&self.time_runs, |r| r.first_dts, time).unwrap_or(0)];
+        let in_run = match run.delta {
+            0     => (run.count as u64) - 1,
+            delta => (time.saturating_sub(run.first_dts) / (delta as u64)).min((run.count as u64) - 1)
+        };
+
+        Some((run.first_sample + in_run) as usize)
+    }
+
+    // The sample being presented at time, i.e. whose [pts, pts + duration)
+    // covers it. Presentation order isn't decode order once there are
+    // composition offsets, so this scans the samples whose offsets could
+    // put them there. Before the first presented sample gives the first
+    // presented one, after the last gives the last.
+    pub fn sample_at_presentation_time(&self, time: i64) -> Option<usize> {
+        // without ctts that's decode order, no need to scan e.g. a PCM
+        // track's million samples
+        if self.offset_runs.is_empty() {
+            return self.sample_at_decode_time(time.max(0) as u64);
+        }
+
+        // The sample decoded at time - greatest is presented by time, and
+        // anything decoded more than greatest before its pts is presented
+        // earlier still. Nothing decoded after time - least is presented
+        // by time.
+        let (least, greatest) = self.offset_range;
+        let known = self.get(self.decode_index(time - greatest))?;
+        let (from, to) = if known.pts <= time {
+            (self.decode_index(known.pts - greatest), self.decode_index(time - least))
+        } else {
+            // possibly before anything is presented, which is known.pts
+            // at the latest
+            (0, self.decode_index(known.pts.max(time) - least))
+        };
+
+        // latest sample starting before time, in case of a gap
+        let mut best: Option<(usize, i64)> = None;
+        let mut earliest: Option<(usize, i64)> = None;
+
+        for s in self.iter_from(from).take(to + 1 - from) {
+            let index = s.index as usize;
+            if s.pts <= time {
+                if s.pts + (s.duration as i64) > time {
+                    return Some(index);
+                }
+                if best.is_none_or(|(_, pts)| s.pts > pts) {
+                    best = Some((index, s.pts));
+                }
+            }
+            if earliest.is_none_or(|(_, pts)| s.pts < pts) {
+                earliest = Some((index, s.pts));
+            }
+        }
+
+        best.or(earliest).map(|(index, _)| index)
+    }
+
+    // sample_at_decode_time for a time that can be negative, which gives the
+    // first sample, or 0 for an empty track
+    fn decode_index(&self, time: i64) -> usize {
+        self.sample_at_decode_time(time.max(0) as u64).unwrap_or(0)
+    }
+
+    pub fn seconds_to_media_time(&self, seconds: f64) -> i64 {
+        (seconds * (self.time_scale as f64)).round() as i64
+    }
+
+    pub fn media_time_to_seconds(&self, time: i64) -> f64 {
+        (time as f64) / (self.time_scale as f64)
+    }
+}
+
+#[derive(Clone)]
+pub struct SampleIter<'i, 'a: 'i> {
+    index: &'i SampleIndex<'a>,
+    cursor: Cursor
+}
+
+impl<'i, 'a> Iterator for SampleIter<'i, 'a> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> { self.index.advance(&mut self.cursor) }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.index.len().saturating_sub(self.cursor.next);
+        (left, Some(left))
+    }
+}
+
+// Owns its index, as returned by TrakAtom::samples
+pub struct Samples<'a> {
+    index: SampleIndex<'a>,
+    cursor: Cursor
+}
+
+impl<'a> Samples<'a> {
+    pub fn new(index: SampleIndex<'a>) -> Samples<'a> {
+        let cursor = index.cursor_at(0).unwrap_or_default();
+        Samples { index, cursor }
+    }
+
+    pub fn index(&self) -> &SampleIndex<'a> { &self.index }
+}
+
+impl<'a> Iterator for Samples<'a> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> { self.index.advance(&mut self.cursor) }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.index.len().saturating_sub(self.cursor.next);
+        (left, Some(left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atoms::AtomParser;
+    use parser::MParser;
+
+    fn atom(typ: &str, payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(typ.as_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    // version, no flags, then the fields as 32 bit values
+    fn table(typ: &str, version: u8, fields: &[i64]) -> Vec<u8> {
+        let mut payload = vec![version, 0, 0, 0];
+        for &field in fields {
+            payload.extend_from_slice(&(field as u32).to_be_bytes());
+        }
+        atom(typ, &payload)
+    }
+
+    fn stbl(children: &[Vec<u8>]) -> SampleTableAtom {
+        let mut parser = MParser::from_bytes(atom("stbl", &children.concat()));
+        let mut stbl = SampleTableAtom::new();
+        stbl.parse(&mut parser.get_view_at(0), 0).unwrap();
+        stbl
+    }
+
+    // Two chunks of two samples then one of three, at 64 bit offsets with
+    // 4 bit sizes 1 to 7
+    fn located() -> SampleTableAtom {
+        let mut co64 = vec![0, 0, 0, 0, 0, 0, 0, 3];
+        for offset in [0x1_0000_0000u64, 0x1_0000_0100, 0x2_0000_0000] {
+            co64.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        stbl(&[
+            table("stts", 0, &[1, 7, 10]),
+            table("stsc", 0, &[2, 1, 2, 1, 3, 3, 1]),
+            atom("co64", &co64),
+            atom("stz2", &[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 7, 0x12, 0x34, 0x56, 0x70])
+        ])
+    }
+
+    // I P B B P B with version 1 offsets putting them at 0 30 10 20 50 40
+    fn reordered() -> SampleTableAtom {
+        stbl(&[
+            table("stts", 0, &[2, 2, 10, 4, 10]),
+            table("ctts", 1, &[5, 1, 0, 1, 20, 2, -10, 1, 10, 1, -10]),
+            table("cslg", 0, &[10, -10, 20, 0, 60]),
+            table("stsc", 0, &[1, 1, 6, 1]),
+            table("stco", 0, &[1, 1000]),
+            table("stsz", 0, &[10, 6])
+        ])
+    }
+
+    #[test]
+    fn sample_locations() {
+        let stbl = located();
+        let index = SampleIndex::new(&stbl, 1000).unwrap();
+
+        let located = index.iter().map(|s| (s.offset, s.size, s.chunk)).collect::<Vec<_>>();
+        assert_eq!(located, [
+            (0x1_0000_0000, 1, 1), (0x1_0000_0001, 2, 1),
+            (0x1_0000_0100, 3, 2), (0x1_0000_0103, 4, 2),
+            (0x2_0000_0000, 5, 3), (0x2_0000_0005, 6, 3), (0x2_0000_000b, 7, 3)
+        ]);
+
+        // starting part way through a chunk gives the same samples
+        for first in 0..index.len() {
+            assert!(index.iter_from(first).eq(index.iter().skip(first)));
+            assert_eq!(index.get(first), index.iter().nth(first));
+        }
+        assert_eq!(index.get(7), None);
+        assert_eq!(index.iter_from(7).next(), None);
+
+        assert!(index.check_locations(0x2_0000_0012).is_ok());
+        assert!(index.check_locations(0x2_0000_0011).is_err());
+    }
+
+    #[test]
+    fn sample_timing() {
+        let stbl = reordered();
+        let cslg = stbl.cslg.as_ref().unwrap();
+        assert_eq!((cslg.least_decode_to_display_delta, cslg.greatest_decode_to_display_delta), (-10, 20));
+
+        let index = SampleIndex::new(&stbl, 10).unwrap();
+        let times = index.iter().map(|s| (s.dts, s.pts, s.duration)).collect::<Vec<_>>();
+        assert_eq!(times, [(0, 0, 10), (10, 30, 10), (20, 10, 10), (30, 20, 10), (40, 50, 10), (50, 40, 10)]);
+        assert_eq!(index.duration(), 60);
+        assert_eq!(index.composition_offset_range(), (-10, 20));
+        assert_eq!(index.presentation_end(), 60);
+
+        let by_decode_time = [0, 15, 59, 1000].iter().map(|&t| index.sample_at_decode_time(t)).collect::<Vec<_>>();
+        assert_eq!(by_decode_time, [Some(0), Some(1), Some(5), Some(5)]);
+
+        // before the start gives the first presented, after the end the last
+        let by_presentation_time = [-5, 0, 10, 25, 30, 45, 55, 100].iter()
+            .map(|&t| index.sample_at_presentation_time(t))
+            .collect::<Vec<_>>();
+        assert_eq!(by_presentation_time, [Some(0), Some(0), Some(2), Some(3), Some(1), Some(5), Some(4), Some(4)]);
+    }
+
+    #[test]
+    fn quicktime_pcm_frames() {
+        // sowt, 2 channels of 16 bits, so 4 bytes a frame
+        let mut sowt = vec![0u8; 28];
+        sowt[7] = 1;
+        sowt[17] = 2;
+        sowt[19] = 16;
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(atom("sowt", &sowt));
+
+        let stbl = stbl(&[
+            atom("stsd", &stsd),
+            table("stts", 0, &[1, 1000, 1]),
+            table("stsc", 0, &[1, 1, 250, 1]),
+            table("stco", 0, &[4, 100, 1100, 2100, 3100]),
+            table("stsz", 0, &[1, 1000])
+        ]);
+        let index = SampleIndex::new(&stbl, 48000).unwrap();
+
+        assert!(index.sizes_count_frames());
+        let frames = index.iter_from(249).take(3).map(|s| (s.offset, s.size, s.chunk)).collect::<Vec<_>>();
+        assert_eq!(frames, [(1096, 4, 1), (1100, 4, 2), (1104, 4, 2)]);
+        assert_eq!(index.get(999).map(|s| s.end()), Some(4100));
+    }
+}
@@ -0,0 +1,1281 @@
+use atoms::*;
+use parser::*;
+use reader::*;
+use stsd::*;
+
+/* ================================ Sample tables ================================ */
+
+pub struct SampleTableAtom {
+    pub location: usize,
+    pub size: u32,
+    pub stsd: Option<SampleDescriptionAtom>,
+    pub stts: Option<TimeToSampleAtom>,
+    pub ctts: Option<CompositionOffsetAtom>,
+    pub cslg: Option<CompositionShiftAtom>,
+    pub stsc: Option<SampleToChunkAtom>,
+    // stco or co64
+    pub stco: Option<ChunkOffsetAtom>,
+    // stsz or stz2
+    pub stsz: Option<SampleSizeAtom>,
+    pub stss: Option<SyncSampleAtom>,
+    pub stps: Option<SyncSampleAtom>,
+    pub sdtp: Option<SampleDependencyAtom>,
+    // one of each per grouping type
+    pub sgpd: Vec<SampleGroupDescriptionAtom>,
+    pub sbgp: Vec<SampleToGroupAtom>,
+    pub subs: Vec<SubSampleInformationAtom>,
+    // paired by aux_info_type
+    pub saiz: Vec<SampleAuxInfoSizesAtom>,
+    pub saio: Vec<SampleAuxInfoOffsetsAtom>
+}
+
+impl SampleTableAtom {
+    pub fn new() -> SampleTableAtom {
+        SampleTableAtom {
+            location: 0,
+            size: 0,
+            stsd: None,
+            stts: None,
+            ctts: None,
+            cslg: None,
+            stsc: None,
+            stco: None,
+            stsz: None,
+            stss: None,
+            stps: None,
+            sdtp: None,
+            sgpd: vec![],
+            sbgp: vec![],
+            subs: vec![],
+            saiz: vec![],
+            saio: vec![]
+        }
+    }
+
+    // sgpd has no grouping_type_parameter, every sbgp of the type shares it
+    pub fn sample_group_description(&self, grouping_type: &str) -> Option<&SampleGroupDescriptionAtom> {
+        self.sgpd.iter().find(|g| g.grouping_type == grouping_type)
+    }
+
+    // A track can have an sbgp per grouping_type_parameter, so both have to
+    // match. None is a version 0 sbgp.
+    pub fn sample_to_group(&self, grouping_type: &str, grouping_type_parameter: Option<u32>) -> Option<&SampleToGroupAtom> {
+        self.sbgp.iter().find(|g| g.grouping_type == grouping_type && g.grouping_type_parameter == grouping_type_parameter)
+    }
+
+    // The sgpd entry, 1 based, that sample index (0 based) belongs to in the
+    // sbgp of grouping_type and grouping_type_parameter, or 0 if it isn't in
+    // a group there. Only samples that sbgp doesn't map at all fall back to
+    // the version 2 sgpd default, an explicit 0 stays 0.
+    pub fn group_description_index(&self, index: usize, grouping_type: &str, grouping_type_parameter: Option<u32>) -> u32 {
+        let mapped = self.sample_to_group(grouping_type, grouping_type_parameter)
+            .and_then(|g| g.group_of(index));
+
+        match mapped {
+            Some(group) => group,
+            None        => self.sample_group_description(grouping_type)
+                .map(|g| g.default_sample_description_index)
+                .unwrap_or(0)
+        }
+    }
+
+    pub fn sync_samples(&self) -> SyncSamples<'_> {
+        match self.stss {
+            Some(ref stss) => SyncSamples::Listed(stss),
+            None           => SyncSamples::All
+        }
+    }
+}
+
+impl Default for SampleTableAtom {
+    fn default() -> SampleTableAtom { SampleTableAtom::new() }
+}
+
+impl AtomParser for SampleTableAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+
+        Ok(())
+    }
+
+    fn parse_child(&mut self, atom: &str, parser: &mut MParserView, depth: usize) -> Result<(), String> {
+        match atom {
+            "stsd" => {
+                let mut stsd = SampleDescriptionAtom::new();
+                stsd.parse(parser, depth)?;
+                self.stsd = Some(stsd);
+            },
+            "stts" => {
+                let mut stts = TimeToSampleAtom::new();
+                stts.parse(parser, depth)?;
+                self.stts = Some(stts);
+            },
+            "ctts" => {
+                let mut ctts = CompositionOffsetAtom::new();
+                ctts.parse(parser, depth)?;
+                self.ctts = Some(ctts);
+            },
+            "cslg" => {
+                let mut cslg = CompositionShiftAtom::new();
+                cslg.parse(parser, depth)?;
+                self.cslg = Some(cslg);
+            },
+            "stsc" => {
+                let mut stsc = SampleToChunkAtom::new();
+                stsc.parse(parser, depth)?;
+                self.stsc = Some(stsc);
+            },
+            "stco" | "co64" => {
+                let mut stco = ChunkOffsetAtom::new();
+                stco.parse(parser, depth)?;
+                self.stco = Some(stco);
+            },
+            "stsz" | "stz2" => {
+                let mut stsz = SampleSizeAtom::new();
+                stsz.parse(parser, depth)?;
+                self.stsz = Some(stsz);
+            },
+            "stss" => {
+                let mut stss = SyncSampleAtom::new();
+                stss.parse(parser, depth)?;
+                self.stss = Some(stss);
+            },
+            "stps" => {
+                let mut stps = SyncSampleAtom::new();
+                stps.parse(parser, depth)?;
+                self.stps = Some(stps);
+            },
+            "sdtp" => {
+                let mut sdtp = SampleDependencyAtom::new();
+                sdtp.parse(parser, depth)?;
+                self.sdtp = Some(sdtp);
+            },
+            "sgpd" => {
+                let mut sgpd = SampleGroupDescriptionAtom::new();
+                sgpd.parse(parser, depth)?;
+                self.sgpd.push(sgpd);
+            },
+            "sbgp" => {
+                let mut sbgp = SampleToGroupAtom::new();
+                sbgp.parse(parser, depth)?;
+                self.sbgp.push(sbgp);
+            },
+            "subs" => {
+                let mut subs = SubSampleInformationAtom::new();
+                subs.parse(parser, depth)?;
+                self.subs.push(subs);
+            },
+            "saiz" => {
+                let mut saiz = SampleAuxInfoSizesAtom::new();
+                saiz.parse(parser, depth)?;
+                self.saiz.push(saiz);
+            },
+            "saio" => {
+                let mut saio = SampleAuxInfoOffsetsAtom::new();
+                saio.parse(parser, depth)?;
+                self.saio.push(saio);
+            },
+            // padb, stsh and stdp aren't needed for trimming
+            _      => {}
+        };
+
+        Ok(())
+    }
+
+    fn get_children(&self, parser: &mut MParserView) -> Result<Vec<usize>, String> {
+        let atoms = vec![
+            "stsd", "stts", "ctts", "cslg", "stss", "stps", "sdtp", "stsc",
+            "stsz", "stz2", "stco", "co64", "sgpd", "sbgp", "subs", "saiz",
+            "saio", "padb", "stsh", "stdp"
+        ];
+        let children = loop_and_get_children(parser, &atoms)?;
+
+        Ok(children)
+    }
+}
+
+// Sums the sample counts of a run length table, making sure a hostile file
+// can't describe more samples than we're willing to expand.
+fn check_sample_count<I: Iterator<Item = u32>>(parser: &MParserView, counts: I, table: &str) -> Result<u64, String> {
+    let total = counts.map(|c| c as u64).sum::<u64>();
+    if total > parser.get_limits().max_table_entries as u64 {
+        return Err(format!("{} describes {} samples, limit is {}", table, total, parser.get_limits().max_table_entries));
+    }
+
+    Ok(total)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeToSampleEntry {
+    pub sample_count: u32,
+    pub sample_delta: u32
+}
+
+pub struct TimeToSampleAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<TimeToSampleEntry>
+}
+
+impl TimeToSampleAtom {
+    pub fn new() -> TimeToSampleAtom {
+        TimeToSampleAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+
+    pub fn sample_count(&self) -> u64 {
+        self.entries.iter().map(|e| e.sample_count as u64).sum()
+    }
+
+    pub fn total_duration(&self) -> u64 {
+        self.entries.iter().map(|e| (e.sample_count as u64) * (e.sample_delta as u64)).sum()
+    }
+}
+
+impl Default for TimeToSampleAtom {
+    fn default() -> TimeToSampleAtom { TimeToSampleAtom::new() }
+}
+
+impl AtomParser for TimeToSampleAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 8, "stts")?;
+
+        self.entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let sample_count = parser.read_u32()?;
+            let sample_delta = parser.read_u32()?;
+            self.entries.push(TimeToSampleEntry { sample_count, sample_delta });
+        }
+
+        check_sample_count(parser, self.entries.iter().map(|e| e.sample_count), "stts")?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompositionOffsetEntry {
+    pub sample_count: u32,
+    pub sample_offset: i64
+}
+
+pub struct CompositionOffsetAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<CompositionOffsetEntry>
+}
+
+impl CompositionOffsetAtom {
+    pub fn new() -> CompositionOffsetAtom {
+        CompositionOffsetAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+}
+
+impl Default for CompositionOffsetAtom {
+    fn default() -> CompositionOffsetAtom { CompositionOffsetAtom::new() }
+}
+
+impl AtomParser for CompositionOffsetAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 8, "ctts")?;
+
+        self.entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let sample_count = parser.read_u32()?;
+            let raw_offset   = parser.read_u32()?;
+            // Version 1 offsets are signed. Version 0 ones are meant to be
+            // unsigned but plenty of muxers write negative offsets there too,
+            // and an offset over 2^31 is never what was intended.
+            let sample_offset = raw_offset as i32 as i64;
+            self.entries.push(CompositionOffsetEntry { sample_count, sample_offset });
+        }
+
+        check_sample_count(parser, self.entries.iter().map(|e| e.sample_count), "ctts")?;
+
+        Ok(())
+    }
+}
+
+// cslg, which relates composition and decode times when ctts has negative
+// offsets. 64 bit fields in version 1.
+pub struct CompositionShiftAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub composition_to_dts_shift: i64,
+    pub least_decode_to_display_delta: i64,
+    pub greatest_decode_to_display_delta: i64,
+    pub composition_start_time: i64,
+    pub composition_end_time: i64
+}
+
+impl CompositionShiftAtom {
+    pub fn new() -> CompositionShiftAtom {
+        CompositionShiftAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            composition_to_dts_shift: 0,
+            least_decode_to_display_delta: 0,
+            greatest_decode_to_display_delta: 0,
+            composition_start_time: 0,
+            composition_end_time: 0
+        }
+    }
+}
+
+impl Default for CompositionShiftAtom {
+    fn default() -> CompositionShiftAtom { CompositionShiftAtom::new() }
+}
+
+impl AtomParser for CompositionShiftAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+
+        let mut fields = [0i64; 5];
+        for field in fields.iter_mut() {
+            *field = if self.version == 1 {
+                parser.read_u64()? as i64
+            } else {
+                parser.read_u32()? as i32 as i64
+            };
+        }
+
+        self.composition_to_dts_shift         = fields[0];
+        self.least_decode_to_display_delta    = fields[1];
+        self.greatest_decode_to_display_delta = fields[2];
+        self.composition_start_time           = fields[3];
+        self.composition_end_time             = fields[4];
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleToChunkEntry {
+    pub first_chunk: u32,
+    pub samples_per_chunk: u32,
+    pub sample_description_index: u32
+}
+
+pub struct SampleToChunkAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<SampleToChunkEntry>
+}
+
+impl SampleToChunkAtom {
+    pub fn new() -> SampleToChunkAtom {
+        SampleToChunkAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+}
+
+impl Default for SampleToChunkAtom {
+    fn default() -> SampleToChunkAtom { SampleToChunkAtom::new() }
+}
+
+impl AtomParser for SampleToChunkAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 12, "stsc")?;
+
+        self.entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let first_chunk              = parser.read_u32()?;
+            let samples_per_chunk        = parser.read_u32()?;
+            let sample_description_index = parser.read_u32()?;
+
+            let previous = self.entries.last().map(|e| e.first_chunk).unwrap_or(0);
+            if first_chunk <= previous {
+                return Err(format!("stsc first chunk {} follows {}", first_chunk, previous));
+            }
+
+            self.entries.push(SampleToChunkEntry { first_chunk, samples_per_chunk, sample_description_index });
+        }
+
+        Ok(())
+    }
+}
+
+// stco or co64, the offsets are widened to 64 bits either way
+pub struct ChunkOffsetAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    // true for co64
+    pub large: bool,
+    pub offsets: Vec<u64>
+}
+
+impl ChunkOffsetAtom {
+    pub fn new() -> ChunkOffsetAtom {
+        ChunkOffsetAtom { location: 0, size: 0, version: 0, flags: 0, large: false, offsets: vec![] }
+    }
+}
+
+impl Default for ChunkOffsetAtom {
+    fn default() -> ChunkOffsetAtom { ChunkOffsetAtom::new() }
+}
+
+impl AtomParser for ChunkOffsetAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        self.large                    = parser.read_string()? == "co64";
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        let table = if self.large { "co64" } else { "stco" };
+        parser.check_table_entries(entry_count as u64, if self.large { 8 } else { 4 }, table)?;
+
+        self.offsets = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let offset = if self.large { parser.read_u64()? } else { parser.read_u32()? as u64 };
+            self.offsets.push(offset);
+        }
+
+        Ok(())
+    }
+}
+
+// stsz or stz2. When sample_size is non zero every sample has that size and
+// sizes is empty.
+pub struct SampleSizeAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    // 32 for stsz, 4, 8 or 16 for stz2
+    pub field_size: u8,
+    pub sample_size: u32,
+    pub sample_count: u32,
+    pub sizes: Vec<u32>
+}
+
+impl SampleSizeAtom {
+    pub fn new() -> SampleSizeAtom {
+        SampleSizeAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            field_size: 32, sample_size: 0, sample_count: 0, sizes: vec![]
+        }
+    }
+
+    pub fn size_of(&self, sample: usize) -> u32 {
+        if self.sample_size != 0 {
+            self.sample_size
+        } else {
+            self.sizes.get(sample).cloned().unwrap_or(0)
+        }
+    }
+}
+
+impl Default for SampleSizeAtom {
+    fn default() -> SampleSizeAtom { SampleSizeAtom::new() }
+}
+
+impl AtomParser for SampleSizeAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        let compact                   = parser.read_string()? == "stz2";
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+
+        if compact {
+            parser.move_cursor(3)?; // reserved
+            self.field_size           = parser.read_u8()?;
+            self.sample_size          = 0;
+            if !matches!(self.field_size, 4 | 8 | 16) {
+                return Err(format!("stz2 field size {} isn't 4, 8 or 16", self.field_size));
+            }
+        } else {
+            self.field_size           = 32;
+            self.sample_size          = parser.read_u32()?;
+        }
+        self.sample_count             = parser.read_u32()?;
+
+        // the count is limited even when no table follows as it's what every
+        // per sample expansion is sized by
+        parser.check_table_entries(self.sample_count as u64, 0, "stsz")?;
+        if self.sample_size != 0 {
+            return Ok(());
+        }
+
+        // 32 is plain stsz, stz2 was checked above
+        let table_bytes = ((self.sample_count as u64) * (self.field_size as u64)).div_ceil(8);
+        parser.check_table_entries(table_bytes, 1, "stsz")?;
+
+        self.sizes = Vec::with_capacity(self.sample_count as usize);
+        let mut i = 0;
+        while i < self.sample_count {
+            match self.field_size {
+                4  => {
+                    // two to a byte, high nibble first
+                    let b = parser.read_u8()?;
+                    self.sizes.push((b >> 4) as u32);
+                    if i + 1 < self.sample_count {
+                        self.sizes.push((b & 0xf) as u32);
+                        i += 1;
+                    }
+                },
+                8  => self.sizes.push(parser.read_u8()? as u32),
+                16 => self.sizes.push(parser.read_u16()? as u32),
+                _  => self.sizes.push(parser.read_u32()?)
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+}
+
+// Which samples are sync samples. A track without stss is one where every
+// sample is a sync sample, which isn't the same as an empty stss (no sync
+// samples at all), so the two are kept apart.
+#[derive(Clone, Copy)]
+pub enum SyncSamples<'a> {
+    All,
+    Listed(&'a SyncSampleAtom)
+}
+
+impl<'a> SyncSamples<'a> {
+    // index is 0 based
+    pub fn is_sync(&self, index: usize) -> bool {
+        match *self {
+            SyncSamples::All          => true,
+            SyncSamples::Listed(stss) => stss.contains(index)
+        }
+    }
+
+    // The closest sync sample at or before index
+    pub fn at_or_before(&self, index: usize) -> Option<usize> {
+        match *self {
+            SyncSamples::All          => Some(index),
+            SyncSamples::Listed(stss) => stss.at_or_before(index)
+        }
+    }
+}
+
+// stss or stps (partial sync samples, e.g. open GOP I frames). Sample numbers
+// are 1 based and increasing.
+pub struct SyncSampleAtom {
+    pub location: usize,
+    pub size: u32,
+    // stss or stps
+    pub typ: String,
+    pub version: u8,
+    pub flags: u32,
+    pub sample_numbers: Vec<u32>
+}
+
+impl SyncSampleAtom {
+    pub fn new() -> SyncSampleAtom {
+        SyncSampleAtom { location: 0, size: 0, typ: "stss".to_string(), version: 0, flags: 0, sample_numbers: vec![] }
+    }
+
+    // index is 0 based
+    pub fn contains(&self, index: usize) -> bool {
+        self.sample_numbers.binary_search(&((index as u32).wrapping_add(1))).is_ok()
+    }
+
+    // index and the result are 0 based
+    pub fn at_or_before(&self, index: usize) -> Option<usize> {
+        let number = (index as u64) + 1;
+        let after = self.sample_numbers.partition_point(|&n| (n as u64) <= number);
+        if after == 0 {
+            return None;
+        }
+
+        Some((self.sample_numbers[after - 1] - 1) as usize)
+    }
+}
+
+impl Default for SyncSampleAtom {
+    fn default() -> SyncSampleAtom { SyncSampleAtom::new() }
+}
+
+impl AtomParser for SyncSampleAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        self.typ                      = parser.read_string()?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 4, &self.typ)?;
+
+        self.sample_numbers = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let sample_number = parser.read_u32()?;
+
+            let previous = self.sample_numbers.last().cloned().unwrap_or(0);
+            if sample_number <= previous {
+                return Err(format!("{} sample {} follows {}", self.typ, sample_number, previous));
+            }
+
+            self.sample_numbers.push(sample_number);
+        }
+
+        Ok(())
+    }
+}
+
+// One sdtp byte: is_leading, sample_depends_on, sample_is_depended_on and
+// sample_has_redundancy, two bits each. For each of them 0 means unknown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleDependency {
+    // 1 leading with a dependency before the sync sample (not decodable when
+    // starting there), 2 not leading, 3 leading without such a dependency
+    pub is_leading: u8,
+    // 1 depends on other samples, 2 doesn't (an I picture)
+    pub depends_on: u8,
+    // 1 other samples depend on this one, 2 none do (disposable)
+    pub is_depended_on: u8,
+    // 1 has redundant coding, 2 doesn't
+    pub has_redundancy: u8
+}
+
+impl SampleDependency {
+    pub fn from_byte(b: u8) -> SampleDependency {
+        SampleDependency {
+            is_leading: (b >> 6) & 3,
+            depends_on: (b >> 4) & 3,
+            is_depended_on: (b >> 2) & 3,
+            has_redundancy: b & 3
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        (self.is_leading << 6) | (self.depends_on << 4) | (self.is_depended_on << 2) | self.has_redundancy
+    }
+
+    // A leading sample that can't be decoded when decoding starts at the
+    // sync sample before it
+    pub fn is_undecodable_leading(&self) -> bool { self.is_leading == 1 }
+
+    pub fn is_disposable(&self) -> bool { self.is_depended_on == 2 }
+}
+
+// sdtp has no count, it has one byte per sample up to the end of the atom
+pub struct SampleDependencyAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<SampleDependency>
+}
+
+impl SampleDependencyAtom {
+    pub fn new() -> SampleDependencyAtom {
+        SampleDependencyAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+}
+
+impl Default for SampleDependencyAtom {
+    fn default() -> SampleDependencyAtom { SampleDependencyAtom::new() }
+}
+
+impl AtomParser for SampleDependencyAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.get_remaining_bytes();
+        parser.check_table_entries(entry_count as u64, 1, "sdtp")?;
+
+        self.entries = parser.read_bytes(entry_count)?.into_iter()
+            .map(SampleDependency::from_byte)
+            .collect();
+
+        Ok(())
+    }
+}
+
+/* ================================ Sample groups ================================ */
+
+// One sgpd entry. Types we don't know, or can't size in a version 0 sgpd,
+// are kept as bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleGroupEntry {
+    // roll (audio pre-roll or gradual decoding refresh) and prol: how many
+    // samples before (negative) or after this one decoding has to start
+    Roll { roll_distance: i16 },
+    // rap , random access points that aren't sync samples
+    RandomAccess { num_leading_samples_known: bool, num_leading_samples: u8 },
+    // sync, the NAL unit type of sync samples
+    Sync { nal_unit_type: u8 },
+    // seig, CENC encryption parameters for the samples in the group
+    Encryption {
+        crypt_byte_block: u8,
+        skip_byte_block: u8,
+        is_protected: bool,
+        per_sample_iv_size: u8,
+        kid: [u8; 16],
+        constant_iv: Vec<u8>
+    },
+    Unknown(Vec<u8>)
+}
+
+impl SampleGroupEntry {
+    // Parses an entry of grouping_type from the start of data, returning it
+    // and how many bytes it took.
+    pub fn parse(grouping_type: &str, data: &[u8]) -> Result<(SampleGroupEntry, usize), String> {
+        match grouping_type {
+            "roll" | "prol" => {
+                let roll_distance = read_u16_at(data, 0)? as i16;
+                Ok((SampleGroupEntry::Roll { roll_distance }, 2))
+            },
+            "rap " => {
+                let b = read_u8_at(data, 0)?;
+                Ok((SampleGroupEntry::RandomAccess {
+                    num_leading_samples_known: b & 0x80 != 0,
+                    num_leading_samples: b & 0x7f
+                }, 1))
+            },
+            "sync" => {
+                let b = read_u8_at(data, 0)?;
+                Ok((SampleGroupEntry::Sync { nal_unit_type: b & 0x3f }, 1))
+            },
+            "seig" => {
+                let blocks             = read_u8_at(data, 1)?;
+                let is_protected       = read_u8_at(data, 2)? == 1;
+                let per_sample_iv_size = read_u8_at(data, 3)?;
+                let mut kid = [0u8; 16];
+                kid.copy_from_slice(read_bytes_at(data, 4, 16)?);
+
+                let mut length = 20;
+                let mut constant_iv = vec![];
+                if is_protected && per_sample_iv_size == 0 {
+                    let iv_size = read_u8_at(data, 20)? as usize;
+                    constant_iv = read_bytes_at(data, 21, iv_size)?.to_vec();
+                    length += 1 + iv_size;
+                }
+
+                Ok((SampleGroupEntry::Encryption {
+                    crypt_byte_block: blocks >> 4,
+                    skip_byte_block: blocks & 0xf,
+                    is_protected,
+                    per_sample_iv_size,
+                    kid,
+                    constant_iv
+                }, length))
+            },
+            _ => Ok((SampleGroupEntry::Unknown(data.to_vec()), data.len()))
+        }
+    }
+
+    // Whether parse can tell where an entry of grouping_type ends, which
+    // version 0 sgpd relies on
+    pub fn has_known_size(grouping_type: &str) -> bool {
+        matches!(grouping_type, "roll" | "prol" | "rap " | "sync" | "seig")
+    }
+}
+
+// sgpd. Version 1 gives every entry's length, either default_length or
+// before each entry when that's 0. Version 0 doesn't, so only types whose
+// size we know can be read.
+pub struct SampleGroupDescriptionAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub grouping_type: String,
+    pub default_length: u32,
+    // version 2, the entry for samples sbgp doesn't map
+    pub default_sample_description_index: u32,
+    pub entries: Vec<SampleGroupEntry>
+}
+
+impl SampleGroupDescriptionAtom {
+    pub fn new() -> SampleGroupDescriptionAtom {
+        SampleGroupDescriptionAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            grouping_type: String::new(), default_length: 0, default_sample_description_index: 0,
+            entries: vec![]
+        }
+    }
+
+    // index is 1 based, as in sbgp
+    pub fn entry(&self, index: u32) -> Option<&SampleGroupEntry> {
+        self.entries.get((index as usize).checked_sub(1)?)
+    }
+}
+
+impl Default for SampleGroupDescriptionAtom {
+    fn default() -> SampleGroupDescriptionAtom { SampleGroupDescriptionAtom::new() }
+}
+
+impl AtomParser for SampleGroupDescriptionAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.grouping_type            = parser.read_string()?;
+        if self.version >= 1 {
+            self.default_length       = parser.read_u32()?;
+        }
+        if self.version >= 2 {
+            self.default_sample_description_index = parser.read_u32()?;
+        }
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 1, "sgpd")?;
+
+        // entries we can't find the end of are left unread
+        if self.version == 0 && !SampleGroupEntry::has_known_size(&self.grouping_type) {
+            return Ok(());
+        }
+
+        let data = parser.read_bytes(parser.get_remaining_bytes())?;
+        let mut offset = 0;
+        for _ in 0..entry_count {
+            let length = if self.version == 0 {
+                None
+            } else if self.default_length != 0 {
+                Some(self.default_length as usize)
+            } else {
+                let length = read_u32_at(&data, offset)? as usize;
+                offset += 4;
+                Some(length)
+            };
+
+            let rest = match length {
+                Some(length) => read_bytes_at(&data, offset, length)?,
+                None         => &data[offset.min(data.len())..]
+            };
+            let (entry, used) = SampleGroupEntry::parse(&self.grouping_type, rest)?;
+
+            offset += length.unwrap_or(used);
+            self.entries.push(entry);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleToGroupEntry {
+    pub sample_count: u32,
+    // 1 based into the sgpd of the same grouping type, 0 for no group
+    pub group_description_index: u32
+}
+
+// sbgp, run lengths of samples mapped to sgpd entries
+pub struct SampleToGroupAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub grouping_type: String,
+    // version 1 only
+    pub grouping_type_parameter: Option<u32>,
+    pub entries: Vec<SampleToGroupEntry>
+}
+
+impl SampleToGroupAtom {
+    pub fn new() -> SampleToGroupAtom {
+        SampleToGroupAtom {
+            location: 0, size: 0, version: 0, flags: 0,
+            grouping_type: String::new(), grouping_type_parameter: None, entries: vec![]
+        }
+    }
+
+    // The group description index of sample index (0 based), which is 0
+    // when the table puts it in no group, or None past the end of the table
+    pub fn group_of(&self, index: usize) -> Option<u32> {
+        let mut first = 0u64;
+        for entry in &self.entries {
+            first += entry.sample_count as u64;
+            if (index as u64) < first {
+                return Some(entry.group_description_index);
+            }
+        }
+        None
+    }
+
+    // The same mapping for samples first..first + count only, renumbered
+    // from 0. Runs are cut at the ends of the range and neighbours that end
+    // up with the same group are merged.
+    pub fn trimmed(&self, first: usize, count: usize) -> SampleToGroupAtom {
+        let (start, end) = (first as u64, (first + count) as u64);
+        let mut entries: Vec<SampleToGroupEntry> = vec![];
+
+        let mut run_start = 0u64;
+        for entry in &self.entries {
+            let run_end = run_start + entry.sample_count as u64;
+            let kept = run_end.min(end).saturating_sub(run_start.max(start));
+            run_start = run_end;
+            if kept == 0 {
+                continue;
+            }
+
+            match entries.last_mut() {
+                Some(last) if last.group_description_index == entry.group_description_index => {
+                    last.sample_count += kept as u32;
+                },
+                _ => entries.push(SampleToGroupEntry {
+                    sample_count: kept as u32,
+                    group_description_index: entry.group_description_index
+                })
+            }
+        }
+
+        SampleToGroupAtom {
+            location: 0,
+            size: 0,
+            version: self.version,
+            flags: self.flags,
+            grouping_type: self.grouping_type.clone(),
+            grouping_type_parameter: self.grouping_type_parameter,
+            entries
+        }
+    }
+}
+
+impl Default for SampleToGroupAtom {
+    fn default() -> SampleToGroupAtom { SampleToGroupAtom::new() }
+}
+
+impl AtomParser for SampleToGroupAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.grouping_type            = parser.read_string()?;
+        if self.version == 1 {
+            self.grouping_type_parameter = Some(parser.read_u32()?);
+        }
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 8, "sbgp")?;
+
+        self.entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let sample_count            = parser.read_u32()?;
+            let group_description_index = parser.read_u32()?;
+            self.entries.push(SampleToGroupEntry { sample_count, group_description_index });
+        }
+
+        check_sample_count(parser, self.entries.iter().map(|e| e.sample_count), "sbgp")?;
+
+        Ok(())
+    }
+}
+
+/* ============================ Auxiliary information ============================ */
+
+// saiz and saio carry an aux_info_type and parameter when flags bit 0 is
+// set, otherwise the type is implied by the sample entry, e.g. the scheme
+// of an encrypted track
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuxInfoType {
+    pub aux_info_type: String,
+    pub aux_info_type_parameter: u32
+}
+
+fn read_aux_info_type(parser: &mut MParserView, flags: u32) -> Result<Option<AuxInfoType>, String> {
+    if flags & 1 == 0 {
+        return Ok(None);
+    }
+
+    let aux_info_type           = parser.read_string()?;
+    let aux_info_type_parameter = parser.read_u32()?;
+    Ok(Some(AuxInfoType { aux_info_type, aux_info_type_parameter }))
+}
+
+// saiz, the size of each sample's auxiliary information
+pub struct SampleAuxInfoSizesAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub aux_info_type: Option<AuxInfoType>,
+    // used for every sample when not 0, otherwise sizes has one per sample
+    pub default_sample_info_size: u8,
+    pub sample_count: u32,
+    pub sizes: Vec<u8>
+}
+
+impl SampleAuxInfoSizesAtom {
+    pub fn new() -> SampleAuxInfoSizesAtom {
+        SampleAuxInfoSizesAtom {
+            location: 0, size: 0, version: 0, flags: 0, aux_info_type: None,
+            default_sample_info_size: 0, sample_count: 0, sizes: vec![]
+        }
+    }
+
+    // index is 0 based, samples past sample_count have no information
+    pub fn size_of(&self, index: usize) -> u8 {
+        if index >= self.sample_count as usize {
+            0
+        } else if self.default_sample_info_size != 0 {
+            self.default_sample_info_size
+        } else {
+            self.sizes.get(index).cloned().unwrap_or(0)
+        }
+    }
+
+    // The sizes of samples first..first + count only
+    pub fn trimmed(&self, first: usize, count: usize) -> SampleAuxInfoSizesAtom {
+        let covered = (self.sample_count as usize).saturating_sub(first).min(count);
+        let sizes = if self.default_sample_info_size != 0 {
+            vec![]
+        } else {
+            self.sizes.iter().skip(first).take(covered).cloned().collect()
+        };
+
+        SampleAuxInfoSizesAtom {
+            location: 0,
+            size: 0,
+            version: self.version,
+            flags: self.flags,
+            aux_info_type: self.aux_info_type.clone(),
+            default_sample_info_size: self.default_sample_info_size,
+            sample_count: covered as u32,
+            sizes
+        }
+    }
+}
+
+impl Default for SampleAuxInfoSizesAtom {
+    fn default() -> SampleAuxInfoSizesAtom { SampleAuxInfoSizesAtom::new() }
+}
+
+impl AtomParser for SampleAuxInfoSizesAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.aux_info_type            = read_aux_info_type(parser, self.flags)?;
+        self.default_sample_info_size = parser.read_u8()?;
+        self.sample_count             = parser.read_u32()?;
+
+        if self.default_sample_info_size == 0 {
+            parser.check_table_entries(self.sample_count as u64, 1, "saiz")?;
+            self.sizes = parser.read_bytes(self.sample_count as usize)?;
+        }
+
+        Ok(())
+    }
+}
+
+// saio, where the auxiliary information is in the file. Either one offset
+// for all samples, the information of each following on from the one
+// before, or one offset per chunk.
+pub struct SampleAuxInfoOffsetsAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub aux_info_type: Option<AuxInfoType>,
+    pub offsets: Vec<u64>
+}
+
+impl SampleAuxInfoOffsetsAtom {
+    pub fn new() -> SampleAuxInfoOffsetsAtom {
+        SampleAuxInfoOffsetsAtom { location: 0, size: 0, version: 0, flags: 0, aux_info_type: None, offsets: vec![] }
+    }
+}
+
+impl Default for SampleAuxInfoOffsetsAtom {
+    fn default() -> SampleAuxInfoOffsetsAtom { SampleAuxInfoOffsetsAtom::new() }
+}
+
+impl AtomParser for SampleAuxInfoOffsetsAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        self.aux_info_type            = read_aux_info_type(parser, self.flags)?;
+        let entry_count               = parser.read_u32()?;
+        let entry_size = if self.version == 1 { 8 } else { 4 };
+        parser.check_table_entries(entry_count as u64, entry_size, "saio")?;
+
+        self.offsets = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let offset = if self.version == 1 { parser.read_u64()? } else { parser.read_u32()? as u64 };
+            self.offsets.push(offset);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubSample {
+    pub size: u32,
+    pub priority: u8,
+    pub discardable: u8,
+    pub codec_specific_parameters: u32
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubSampleEntry {
+    // samples since the previous entry's, the first counts from 0
+    pub sample_delta: u32,
+    pub subsamples: Vec<SubSample>
+}
+
+// subs, how samples split into sub-samples. Only samples with sub-samples
+// have an entry.
+pub struct SubSampleInformationAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<SubSampleEntry>
+}
+
+impl SubSampleInformationAtom {
+    pub fn new() -> SubSampleInformationAtom {
+        SubSampleInformationAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+
+    // The entries of samples first..first + count only, their deltas
+    // renumbered from the first kept sample
+    pub fn trimmed(&self, first: usize, count: usize) -> SubSampleInformationAtom {
+        let (start, end) = (first as u64, (first + count) as u64);
+        let mut entries = vec![];
+
+        // 1 based sample numbers, as the deltas count them
+        let mut number = 0u64;
+        let mut previous = start;
+        for entry in &self.entries {
+            number += entry.sample_delta as u64;
+            if number <= start || number > end {
+                continue;
+            }
+
+            entries.push(SubSampleEntry { sample_delta: (number - previous) as u32, subsamples: entry.subsamples.clone() });
+            previous = number;
+        }
+
+        SubSampleInformationAtom { location: 0, size: 0, version: self.version, flags: self.flags, entries }
+    }
+}
+
+impl Default for SubSampleInformationAtom {
+    fn default() -> SubSampleInformationAtom { SubSampleInformationAtom::new() }
+}
+
+impl AtomParser for SubSampleInformationAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 6, "subs")?;
+
+        let size_bytes = if self.version == 1 { 4 } else { 2 };
+        self.entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let sample_delta    = parser.read_u32()?;
+            let subsample_count = parser.read_u16()?;
+            parser.check_table_entries(subsample_count as u64, size_bytes + 6, "subs")?;
+
+            let mut subsamples = Vec::with_capacity(subsample_count as usize);
+            for _ in 0..subsample_count {
+                let size = if self.version == 1 { parser.read_u32()? } else { parser.read_u16()? as u32 };
+                subsamples.push(SubSample {
+                    size,
+                    priority:                  parser.read_u8()?,
+                    discardable:               parser.read_u8()?,
+                    codec_specific_parameters: parser.read_u32()?
+                });
+            }
+
+            self.entries.push(SubSampleEntry { sample_delta, subsamples });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sbgp(grouping_type: &str, runs: &[(u32, u32)]) -> SampleToGroupAtom {
+        let mut sbgp = SampleToGroupAtom::new();
+        sbgp.grouping_type = grouping_type.to_string();
+        for &(sample_count, group_description_index) in runs {
+            sbgp.entries.push(SampleToGroupEntry { sample_count, group_description_index });
+        }
+        sbgp
+    }
+
+    fn runs(sbgp: &SampleToGroupAtom) -> Vec<(u32, u32)> {
+        sbgp.entries.iter().map(|e| (e.sample_count, e.group_description_index)).collect()
+    }
+
+    #[test]
+    fn group_membership() {
+        let mut stbl = SampleTableAtom::new();
+        let mut sgpd = SampleGroupDescriptionAtom::new();
+        sgpd.grouping_type = "roll".to_string();
+        sgpd.version = 2;
+        sgpd.default_sample_description_index = 2;
+        stbl.sgpd.push(sgpd);
+        stbl.sbgp.push(sbgp("roll", &[(2, 1), (3, 0)]));
+
+        // mapped, explicitly in no group, then past the end of the table
+        assert_eq!(stbl.group_description_index(1, "roll", None), 1);
+        assert_eq!(stbl.group_description_index(4, "roll", None), 0);
+        assert_eq!(stbl.group_description_index(5, "roll", None), 2);
+        // no sbgp for the parameter, so every sample takes the default
+        assert_eq!(stbl.group_description_index(0, "roll", Some(1)), 2);
+        assert_eq!(stbl.group_description_index(0, "rap ", None), 0);
+    }
+
+    #[test]
+    fn trimmed_groups() {
+        let table = sbgp("rap ", &[(3, 1), (2, 0), (4, 1), (5, 2)]);
+
+        // cut inside the first and last kept runs
+        assert_eq!(runs(&table.trimmed(1, 10)), [(2, 1), (2, 0), (4, 1), (2, 2)]);
+        // neighbouring runs that end up in the same group are merged
+        let merged = sbgp("rap ", &[(3, 1), (2, 1), (4, 2)]);
+        assert_eq!(runs(&merged.trimmed(2, 4)), [(3, 1), (1, 2)]);
+        // past the end of the table
+        assert_eq!(runs(&table.trimmed(12, 10)), [(2, 2)]);
+        assert!(table.trimmed(20, 5).entries.is_empty());
+    }
+}
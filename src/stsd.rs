@@ -0,0 +1,491 @@
+use ac3::{Ac3SpecificBox, Ec3SpecificBox};
+use alac::AlacSpecificConfig;
+use atoms::*;
+use av1::Av1Config;
+use avc::*;
+use codecs::codec_string;
+use dovi::*;
+use esds::EsDescriptor;
+use fixed::*;
+use flac::FlacSpecificBox;
+use hevc::HevcDecoderConfig;
+use opus::OpusSpecificBox;
+use parser::*;
+use pcm::PcmFormat;
+use reader::*;
+use visual::*;
+use vp9::VpCodecConfig;
+
+/* ============================= Sample descriptions ============================= */
+
+// stsd. Each entry is a box whose type is the codec (avc1, mp4a, ...), with
+// fields that depend on whether it's a visual or an audio entry, followed by
+// child boxes such as avcC or esds.
+pub struct SampleDescriptionAtom {
+    pub location: usize,
+    pub size: u32,
+    pub version: u8,
+    pub flags: u32,
+    pub entries: Vec<SampleEntry>
+}
+
+impl SampleDescriptionAtom {
+    pub fn new() -> SampleDescriptionAtom {
+        SampleDescriptionAtom { location: 0, size: 0, version: 0, flags: 0, entries: vec![] }
+    }
+
+    // index is 1 based, as in stsc
+    pub fn entry(&self, index: u32) -> Option<&SampleEntry> {
+        self.entries.get((index as usize).checked_sub(1)?)
+    }
+}
+
+impl Default for SampleDescriptionAtom {
+    fn default() -> SampleDescriptionAtom { SampleDescriptionAtom::new() }
+}
+
+impl AtomParser for SampleDescriptionAtom {
+    fn parse_self(&mut self, parser: &mut MParserView) -> Result<(), String> {
+        self.location                 = parser.get_position();
+        self.size                     = parser.read_u32()?;
+        parser.move_cursor(4)?;
+        self.version                  = parser.read_u8()?;
+        self.flags                    = parser.read_flags()?;
+        let entry_count               = parser.read_u32()?;
+        parser.check_table_entries(entry_count as u64, 8, "stsd")?;
+
+        let location = parser.get_position();
+        let data = parser.read_bytes(parser.get_remaining_bytes())?;
+        let reader = AtomReader::with_location(&data, location);
+
+        for atom in reader.iter().take(entry_count as usize) {
+            let atom = atom?;
+            parser.count_atom()?;
+            self.entries.push(SampleEntry::parse(&atom, self.version, parser.get_limits())?);
+        }
+
+        Ok(())
+    }
+}
+
+// What kind of entry a sample entry type is. The handler type would say for
+// sure but it lives outside stbl, and the codec types are distinct enough.
+pub fn sample_entry_kind(typ: &str) -> &'static str {
+    match typ {
+        "avc1" | "avc2" | "avc3" | "avc4" | "hvc1" | "hev1" | "dvh1" | "dvhe" |
+        "dva1" | "dvav" | "av01" | "dav1" | "vp08" | "vp09" | "mp4v" | "encv" |
+        "s263" | "h263" | "jpeg" | "mjpa" | "mjpb" | "mjp2" | "apcn" | "apch" |
+        "apcs" | "apco" | "ap4h" | "ap4x" | "2vuy" | "v210" | "yuv2" => "vide",
+        "mp4a" | "ac-3" | "ec-3" | "ac-4" | "Opus" | "fLaC" | "alac" | "lpcm" |
+        "sowt" | "twos" | "in24" | "in32" | "fl32" | "fl64" | "ipcm" | "fpcm" |
+        "ulaw" | "alaw" | "ima4" | ".mp3" | "samr" | "sawb" | "enca" | "dtsc" |
+        "dtsh" | "dtsl" | "dtse" | "mha1" | "mhm1" => "soun",
+        _ => ""
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SampleEntryFields {
+    Visual(VisualSampleEntry),
+    Audio(AudioSampleEntry),
+    // the bytes after data_reference_index, for entries we don't know
+    Other(Vec<u8>)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VisualSampleEntry {
+    pub width: u16,
+    pub height: u16,
+    // pixels per inch, normally 72
+    pub horizontal_resolution: UFixed16_16,
+    pub vertical_resolution: UFixed16_16,
+    pub frame_count: u16,
+    pub compressor_name: String,
+    pub depth: u16
+}
+
+// The ISO AudioSampleEntry or a QuickTime sound description. QuickTime
+// version 1 adds packet sizes, version 2 replaces the fields with 32 bit
+// channel counts and a 64 bit float sample rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioSampleEntry {
+    pub version: u16,
+    pub channel_count: u32,
+    // bits per sample
+    pub sample_size: u32,
+    pub sample_rate: f64,
+    // QuickTime version 1
+    pub samples_per_packet: u32,
+    pub bytes_per_packet: u32,
+    pub bytes_per_frame: u32,
+    pub bytes_per_sample: u32,
+    // QuickTime version 2
+    pub format_specific_flags: u32,
+    pub const_bytes_per_audio_packet: u32,
+    pub const_lpcm_frames_per_audio_packet: u32
+}
+
+// A box inside a sample entry, kept as is for the codec specific parsers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampleEntryChild {
+    pub location: usize,
+    pub typ: String,
+    pub payload: Vec<u8>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleEntry {
+    pub location: usize,
+    pub size: u32,
+    pub typ: String,
+    pub data_reference_index: u16,
+    pub fields: SampleEntryFields,
+    pub children: Vec<SampleEntryChild>
+}
+
+impl SampleEntry {
+    // stsd_version decides between the ISO and QuickTime audio layouts: in a
+    // version 1 stsd an audio entry of version 1 is the ISO one, which has no
+    // extra fields.
+    pub fn parse(atom: &AtomRef, stsd_version: u8, limits: &ParseLimits) -> Result<SampleEntry, String> {
+        let typ = atom.typ.to_string();
+        let data_reference_index = atom.read_u16(6)?;
+
+        let (fields, children_offset) = match sample_entry_kind(&typ) {
+            "vide" => {
+                let visual = VisualSampleEntry {
+                    width:                 atom.read_u16(24)?,
+                    height:                atom.read_u16(26)?,
+                    horizontal_resolution: UFixed16_16::from_bits(atom.read_u32(28)?),
+                    vertical_resolution:   UFixed16_16::from_bits(atom.read_u32(32)?),
+                    frame_count:           atom.read_u16(40)?,
+                    compressor_name:       pascal_string(atom.read_bytes(42, 32)?),
+                    depth:                 atom.read_u16(74)?
+                };
+                (SampleEntryFields::Visual(visual), 78)
+            },
+            "soun" => {
+                let (audio, offset) = AudioSampleEntry::parse(atom, stsd_version)?;
+                (SampleEntryFields::Audio(audio), offset)
+            },
+            _ => (SampleEntryFields::Other(atom.payload()[8.min(atom.payload().len())..].to_vec()), atom.payload().len())
+        };
+
+        let mut children = vec![];
+        if children_offset < atom.payload().len() {
+            let reader = atom.children_at(children_offset)?;
+            let mut offset = 0;
+            while offset < reader.data().len() {
+                let child = match reader.atom_at(offset) {
+                    Ok(c)  => c,
+                    Err(e) => {
+                        // QuickTime entries are sometimes ended by a few zero bytes
+                        let padding = reader.data()[offset..].iter().all(|&b| b == 0);
+                        if limits.child_must_fit_in_parent && !padding {
+                            return Err(e);
+                        }
+                        break;
+                    }
+                };
+                if child.typ.0 == [0; 4] {
+                    break;
+                }
+
+                children.push(SampleEntryChild {
+                    location: child.location,
+                    typ: child.typ.to_string(),
+                    payload: child.payload().to_vec()
+                });
+                offset += child.size();
+            }
+        }
+
+        Ok(SampleEntry {
+            location: atom.location,
+            size: atom.size() as u32,
+            typ,
+            data_reference_index,
+            fields,
+            children
+        })
+    }
+
+    pub fn child(&self, typ: &str) -> Option<&SampleEntryChild> {
+        self.children.iter().find(|c| c.typ == typ)
+    }
+
+    pub fn visual(&self) -> Option<&VisualSampleEntry> {
+        match self.fields {
+            SampleEntryFields::Visual(ref v) => Some(v),
+            _ => None
+        }
+    }
+
+    pub fn audio(&self) -> Option<&AudioSampleEntry> {
+        match self.fields {
+            SampleEntryFields::Audio(ref a) => Some(a),
+            _ => None
+        }
+    }
+
+    // avcC of an avc1/avc3 entry, None for other codecs
+    pub fn avc_config(&self) -> Result<Option<AvcDecoderConfig>, String> {
+        match self.child("avcC") {
+            Some(avcc) => Ok(Some(AvcDecoderConfig::parse(&avcc.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // hvcC of an hvc1/hev1 entry, or a Dolby Vision one based on HEVC
+    pub fn hevc_config(&self) -> Result<Option<HevcDecoderConfig>, String> {
+        match self.child("hvcC") {
+            Some(hvcc) => Ok(Some(HevcDecoderConfig::parse(&hvcc.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // esds of an mp4a or mp4v entry
+    pub fn esds(&self) -> Result<Option<EsDescriptor>, String> {
+        match self.child_or_in_wave("esds") {
+            Some(esds) => Ok(Some(EsDescriptor::parse(esds)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // dOps of an Opus entry
+    pub fn opus_config(&self) -> Result<Option<OpusSpecificBox>, String> {
+        match self.child("dOps") {
+            Some(dops) => Ok(Some(OpusSpecificBox::parse(&dops.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // dfLa of a fLaC entry
+    pub fn flac_config(&self) -> Result<Option<FlacSpecificBox>, String> {
+        match self.child("dfLa") {
+            Some(dfla) => Ok(Some(FlacSpecificBox::parse(&dfla.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // dac3 of an ac-3 entry
+    pub fn ac3_config(&self) -> Result<Option<Ac3SpecificBox>, String> {
+        match self.child("dac3") {
+            Some(dac3) => Ok(Some(Ac3SpecificBox::parse(&dac3.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // dec3 of an ec-3 entry
+    pub fn ec3_config(&self) -> Result<Option<Ec3SpecificBox>, String> {
+        match self.child("dec3") {
+            Some(dec3) => Ok(Some(Ec3SpecificBox::parse(&dec3.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // The magic cookie of an alac entry
+    pub fn alac_config(&self) -> Result<Option<AlacSpecificConfig>, String> {
+        if self.typ != "alac" {
+            return Ok(None);
+        }
+        match self.child_or_in_wave("alac") {
+            Some(alac) => Ok(Some(AlacSpecificConfig::parse(alac)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // The RFC 6381 codecs parameter, e.g. "avc1.640028"
+    pub fn codec_string(&self) -> Result<String, String> {
+        codec_string(self)
+    }
+
+    // Frame layout of an uncompressed audio entry
+    pub fn pcm_format(&self) -> Result<Option<PcmFormat>, String> {
+        PcmFormat::from_entry(self)
+    }
+
+    // The payload of a child box, or of a box inside the wave box QuickTime
+    // sound descriptions use for their codec configuration
+    pub fn child_or_in_wave(&self, typ: &str) -> Option<&[u8]> {
+        if let Some(child) = self.child(typ) {
+            return Some(&child.payload);
+        }
+
+        let wave = self.child("wave")?;
+        let reader = AtomReader::with_location(&wave.payload, wave.location + 8);
+        reader.find(typ).map(|atom| atom.payload())
+    }
+
+    // av1C of an av01 entry
+    pub fn av1_config(&self) -> Result<Option<Av1Config>, String> {
+        match self.child("av1C") {
+            Some(av1c) => Ok(Some(Av1Config::parse(&av1c.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // vpcC of a vp08 or vp09 entry
+    pub fn vp_config(&self) -> Result<Option<VpCodecConfig>, String> {
+        match self.child("vpcC") {
+            Some(vpcc) => Ok(Some(VpCodecConfig::parse(&vpcc.payload)?)),
+            None       => Ok(None)
+        }
+    }
+
+    // From whichever of dvcC, dvvC and dvwC the entry has. hvc1, avc1 and
+    // av01 entries have one too when Dolby Vision is layered on a stream
+    // other players can show.
+    pub fn dolby_vision_config(&self) -> Result<Option<DoviDecoderConfig>, String> {
+        match DOVI_CONFIG_BOXES.iter().filter_map(|&typ| self.child(typ)).next() {
+            Some(child) => Ok(Some(DoviDecoderConfig::parse(&child.payload)?)),
+            None        => Ok(None)
+        }
+    }
+
+    // The first colr, which is the preferred one when there are several
+    pub fn colour_information(&self) -> Result<Option<ColourInformation>, String> {
+        self.parse_child("colr", ColourInformation::parse)
+    }
+
+    // Code points from an nclx/nclc colr, or else from the codec
+    // configuration: the VUI of the first SPS, the AV1 sequence header or
+    // vpcC
+    pub fn colour(&self) -> Result<Option<ColourDescription>, String> {
+        for colr in self.children.iter().filter(|c| c.typ == "colr") {
+            if let Some(description) = ColourInformation::parse(&colr.payload)?.description() {
+                return Ok(Some(description));
+            }
+        }
+
+        if let Some(avcc) = self.avc_config()? {
+            return Ok(avcc.first_sps()?.and_then(|sps| sps.colour()));
+        }
+        if let Some(hvcc) = self.hevc_config()? {
+            return Ok(hvcc.first_sps()?.and_then(|sps| sps.colour()));
+        }
+        if let Some(av1c) = self.av1_config()? {
+            return Ok(av1c.sequence_header()?.map(|header| ColourDescription {
+                colour_primaries:         header.colour_primaries,
+                transfer_characteristics: header.transfer_characteristics,
+                matrix_coefficients:      header.matrix_coefficients,
+                full_range:               header.full_range
+            }));
+        }
+        Ok(self.vp_config()?.map(|vpcc| ColourDescription {
+            colour_primaries:         vpcc.colour_primaries,
+            transfer_characteristics: vpcc.transfer_characteristics,
+            matrix_coefficients:      vpcc.matrix_coefficients,
+            full_range:               vpcc.full_range
+        }))
+    }
+
+    pub fn pixel_aspect_ratio(&self) -> Result<Option<PixelAspectRatio>, String> {
+        self.parse_child("pasp", PixelAspectRatio::parse)
+    }
+
+    pub fn clean_aperture(&self) -> Result<Option<CleanAperture>, String> {
+        self.parse_child("clap", CleanAperture::parse)
+    }
+
+    pub fn mastering_display(&self) -> Result<Option<MasteringDisplayColourVolume>, String> {
+        self.parse_child("mdcv", MasteringDisplayColourVolume::parse)
+    }
+
+    pub fn content_light_level(&self) -> Result<Option<ContentLightLevel>, String> {
+        self.parse_child("clli", ContentLightLevel::parse)
+    }
+
+    pub fn bit_rate(&self) -> Result<Option<BitRate>, String> {
+        self.parse_child("btrt", BitRate::parse)
+    }
+
+    pub fn field_handling(&self) -> Result<Option<FieldHandling>, String> {
+        self.parse_child("fiel", FieldHandling::parse)
+    }
+
+    // Width over height as shown: the clean aperture when there is one,
+    // stretched by the pixel aspect ratio
+    pub fn display_aspect_ratio(&self) -> Result<Option<f64>, String> {
+        let visual = match self.visual() {
+            Some(v) => v,
+            None    => return Ok(None)
+        };
+        let (width, height) = match self.clean_aperture()? {
+            Some(clap) => (clap.width(), clap.height()),
+            None       => (visual.width as f64, visual.height as f64)
+        };
+        if width <= 0.0 || height <= 0.0 {
+            return Ok(None);
+        }
+
+        let ratio = width / height;
+        match self.pixel_aspect_ratio()? {
+            Some(pasp) => Ok(Some(ratio * (pasp.h_spacing as f64) / (pasp.v_spacing as f64))),
+            None       => Ok(Some(ratio))
+        }
+    }
+
+    fn parse_child<T, F>(&self, typ: &str, parse: F) -> Result<Option<T>, String>
+        where F: Fn(&[u8]) -> Result<T, String> {
+        match self.child(typ) {
+            Some(child) => Ok(Some(parse(&child.payload)?)),
+            None        => Ok(None)
+        }
+    }
+
+    // Bytes in the length before each NAL unit of a sample, for the codecs
+    // that store samples that way
+    pub fn nal_length_size(&self) -> Result<Option<u8>, String> {
+        if let Some(config) = self.avc_config()? {
+            return Ok(Some(config.length_size));
+        }
+        Ok(self.hevc_config()?.map(|c| c.length_size))
+    }
+}
+
+impl AudioSampleEntry {
+    // Returns the entry and where its child boxes start in the payload
+    fn parse(atom: &AtomRef, stsd_version: u8) -> Result<(AudioSampleEntry, usize), String> {
+        let version = atom.read_u16(8)?;
+        let mut audio = AudioSampleEntry {
+            version,
+            channel_count: atom.read_u16(16)? as u32,
+            sample_size: atom.read_u16(18)? as u32,
+            sample_rate: UFixed16_16::from_bits(atom.read_u32(24)?).to_f64(),
+            samples_per_packet: 0,
+            bytes_per_packet: 0,
+            bytes_per_frame: 0,
+            bytes_per_sample: 0,
+            format_specific_flags: 0,
+            const_bytes_per_audio_packet: 0,
+            const_lpcm_frames_per_audio_packet: 0
+        };
+
+        match version {
+            1 if stsd_version == 0 => {
+                audio.samples_per_packet = atom.read_u32(28)?;
+                audio.bytes_per_packet   = atom.read_u32(32)?;
+                audio.bytes_per_frame    = atom.read_u32(36)?;
+                audio.bytes_per_sample   = atom.read_u32(40)?;
+                Ok((audio, 44))
+            },
+            2 => {
+                audio.sample_rate                        = f64::from_bits(atom.read_u64(32)?);
+                audio.channel_count                      = atom.read_u32(40)?;
+                audio.sample_size                        = atom.read_u32(48)?;
+                audio.format_specific_flags              = atom.read_u32(52)?;
+                audio.const_bytes_per_audio_packet       = atom.read_u32(56)?;
+                audio.const_lpcm_frames_per_audio_packet = atom.read_u32(60)?;
+                Ok((audio, 64))
+            },
+            _ => Ok((audio, 28))
+        }
+    }
+}
+
+// compressorname is a length byte followed by up to 31 characters
+fn pascal_string(data: &[u8]) -> String {
+    let length = (data[0] as usize).min(data.len() - 1);
+    String::from_utf8_lossy(&data[1..1 + length]).trim_end_matches('\0').to_string()
+}
@@ -1,60 +1,402 @@
 use atoms::*;
+use av1;
+use avc;
+use codecs::*;
+use hevc;
+use layout::*;
+use matrix::*;
+use opus::*;
 use parser::*;
-use std::str;
+use samples::*;
+use std::fs::File;
+use std::io::prelude::*;
+use stsd::*;
+use writer::*;
 
 
-fn find_moov(data: &[u8]) -> Result<usize, String> {
-    let mut m = 0;
-    for i in 0..data.len() {
-        match data[i] as char {
-            'm' => if m == 0 {
-                m = m + 1;
-            } else {
-                m = 0;
-            },
-            'o' => if m == 1 || m == 2 {
-                m = m + 1;
-            } else {
-                m = 0;
-            },
-            'v' => if m == 3 {
-                let moov_pos = i - 3;
+pub fn trim_video(video_in: &str, video_out: &str, start: f32, stop: f32) -> Result<(), String> {
+    trim_video_with_options(video_in, video_out, start, stop, OutputOptions::default())
+}
+
+pub fn trim_video_with_options(video_in: &str, video_out: &str, start: f32, stop: f32,
+                               options: OutputOptions) -> Result<(), String> {
+    println!("Trimming {} into {} from {} to {}", video_in, video_out, start, stop);
 
-                let mut chars = &mut [0; 4];
-                chars[0] = data[moov_pos];
-                chars[1] = data[moov_pos + 1];
-                chars[2] = data[moov_pos + 2];
-                chars[3] = data[moov_pos + 3];
-                println!("Found moov atom at {}, sanity check: {}",
-                         moov_pos,
-                         str::from_utf8(chars).unwrap());
+    if !(start >= 0.0 && stop > start) {
+        return Err(format!("Can't trim from {} to {}", start, stop));
+    }
+
+    let (parser, moov) = read_movie(video_in)?;
+    let movie_time_scale = match moov.mvhd {
+        Some(ref mvhd) if mvhd.time_scale != 0 => mvhd.time_scale,
+        _ => return Err("Movie has no time scale".to_string())
+    };
 
-                return Ok(moov_pos - 4);
-            } else {
-                m = 0;
-            },
-            _ => m = 0
+    let mut selections = vec![];
+    for trak in &moov.traks {
+        let track_id = trak.tkhd.as_ref().map(|t| t.track_id).unwrap_or(0);
+        let selection = select_samples(&parser.data, trak, movie_time_scale, start as f64, stop as f64)?;
+
+        match selection {
+            Some(ref s) => println!("Track {} keeps samples {} to {}", track_id, s.first, s.end),
+            None        => return Err(format!("Track {} has no sync sample to start from", track_id))
+        }
+        if let Some(ref s) = selection {
+            if s.end > s.first {
+                check_cut_point(&parser.data, trak, s.first)?;
+            }
         }
+        selections.extend(selection);
     }
 
-    Err(format!("Could not find moov atom"))
+    MovieWriter::new(&parser.data, &moov, selections, options)?.write(video_out)
 }
 
+// Writes every sample of video_in into video_out, e.g. to interleave it
+pub fn remux_video(video_in: &str, video_out: &str, options: OutputOptions) -> Result<(), String> {
+    println!("Remuxing {} into {}", video_in, video_out);
 
-pub fn trim_video(video_in: &str, video_out: &str, start: f32, stop: f32) -> Result<(), String> {
-    println!("Trimming {} into {} from {} to {}", video_in, video_out, start, stop);
+    let (parser, moov) = read_movie(video_in)?;
+
+    let mut selections = vec![];
+    for trak in &moov.traks {
+        let samples = trak.sample_index()?.len();
+        selections.push(TrackSelection { first: 0, end: samples, edits: None });
+    }
+
+    MovieWriter::new(&parser.data, &moov, selections, options)?.write(video_out)
+}
+
+// Copies video_in to video_out with every video track's tkhd matrix
+// replaced by one showing it rotated clockwise by rotation degrees. Only the
+// matrix bytes change, and mirroring is kept.
+pub fn set_rotation(video_in: &str, video_out: &str, rotation: u16) -> Result<(), String> {
+    let (parser, moov) = read_movie(video_in)?;
+    let mut data = parser.data.clone();
+
+    for trak in &moov.traks {
+        let tkhd = match trak.tkhd {
+            Some(ref tkhd) if trak.sample_entries().iter().any(|e| e.visual().is_some()) => tkhd,
+            _ => continue
+        };
+        let mirrored = tkhd.orientation().is_some_and(|o| o.mirrored);
+        let matrix = Matrix::for_orientation(Orientation::new(rotation, mirrored)?, tkhd.track_width, tkhd.track_height);
+
+        let location = tkhd.matrix_location();
+        if location + 36 > data.len() {
+            return Err(format!("tkhd of track {} is cut short", tkhd.track_id));
+        }
+        for (i, bits) in matrix.to_bits().iter().enumerate() {
+            data[location + 4 * i..location + 4 * i + 4].copy_from_slice(&bits.to_be_bytes());
+        }
+        println!("Track {} now rotated by {} degrees", tkhd.track_id, rotation);
+    }
+
+    let mut out = match File::create(video_out) {
+        Ok(f)  => f,
+        Err(e) => return Err(format!("Couldn't create video out: {}", e))
+    };
+    match out.write_all(&data) {
+        Ok(_)  => Ok(()),
+        Err(e) => Err(format!("Couldn't write video out: {}", e))
+    }
+}
+
+// Prints each track's codec and format, and the codecs parameter for the
+// whole file as a playlist or MIME type would give it
+pub fn describe_video(video_in: &str) -> Result<(), String> {
+    let (_, moov) = read_movie(video_in)?;
+
+    let (mut codecs, mut supplemental) = (vec![], vec![]);
+    for trak in &moov.traks {
+        let track_id = trak.tkhd.as_ref().map(|t| t.track_id).unwrap_or(0);
+        let entry = match trak.sample_entry(1) {
+            Some(entry) => entry,
+            None        => {
+                println!("Track {}: no sample entry", track_id);
+                continue;
+            }
+        };
+
+        let codec = entry.codec_string()?;
+        let index = trak.sample_index()?;
+        println!("Track {}: {}, {}, {} samples, {:.3}s", track_id, codec, describe_entry(entry, trak.tkhd.as_ref())?,
+                 index.len(), index.media_time_to_seconds(index.duration() as i64));
+        codecs.push(codec);
+        supplemental.extend(supplemental_codec_string(entry)?);
+    }
+
+    println!("codecs=\"{}\"", codecs_parameter(&codecs));
+    if !supplemental.is_empty() {
+        println!("supplemental codecs=\"{}\"", codecs_parameter(&supplemental));
+    }
+    Ok(())
+}
+
+// Dimensions for video, rate and channels for audio, with the channel
+// layout or sample format when the codec has one
+fn describe_entry(entry: &SampleEntry, tkhd: Option<&TrakHeaderAtom>) -> Result<String, String> {
+    if let Some(visual) = entry.visual() {
+        let mut description = format!("{}x{}", visual.width, visual.height);
+        if let Some(tkhd) = tkhd {
+            let (width, height) = tkhd.display_size();
+            description.push_str(&format!(", shown at {}x{}", width.round(), height.round()));
+            match tkhd.orientation() {
+                Some(o) => description.push_str(&format!(", rotated {}{}", o.rotation, if o.mirrored { " mirrored" } else { "" })),
+                None    => description.push_str(&format!(", matrix {}", tkhd.matrix))
+            }
+        }
+        if let Some(ratio) = entry.display_aspect_ratio()? {
+            description.push_str(&format!(", display aspect {:.4}", ratio));
+        }
+        if let Some(colour) = entry.colour()? {
+            description.push_str(&format!(", colour {}/{}/{} {} range", colour.colour_primaries,
+                                          colour.transfer_characteristics, colour.matrix_coefficients,
+                                          if colour.full_range { "full" } else { "limited" }));
+        }
+        if let Some(mdcv) = entry.mastering_display()? {
+            description.push_str(&format!(", mastering display {}-{} cd/m²", mdcv.min_luminance(), mdcv.max_luminance()));
+        }
+        if let Some(clli) = entry.content_light_level()? {
+            description.push_str(&format!(", MaxCLL {} MaxFALL {}", clli.max_content_light_level,
+                                          clli.max_pic_average_light_level));
+        }
+        if let Some(dovi) = entry.dolby_vision_config()? {
+            description.push_str(&format!(", Dolby Vision profile {} level {} (RPU {}, EL {}, BL {}, compatible with {})",
+                                          dovi.profile_name(), dovi.level, dovi.rpu_present, dovi.el_present,
+                                          dovi.bl_present, dovi.compatibility()));
+        }
+        if let Some(fiel) = entry.field_handling()? {
+            description.push_str(&format!(", {}", fiel.field_order()));
+        }
+        return Ok(description);
+    }
+    let audio = match entry.audio() {
+        Some(audio) => audio,
+        None        => return Ok(entry.typ.clone())
+    };
+
+    let asc = match entry.esds()? {
+        Some(esds) => esds.audio_specific_config()?,
+        None       => None
+    };
+
+    let mut description = format!("{} Hz, {} channels", audio.sample_rate, audio.channel_count);
+    if let Some(asc) = asc {
+        description = format!("{}, {} Hz, {} channels", asc.profile_name(), asc.output_sampling_frequency(), asc.channel_count);
+    } else if let Some(dac3) = entry.ac3_config()? {
+        description = format!("{} Hz, {} channels ({})", dac3.sample_rate(), dac3.channel_count(), dac3.channel_layout());
+    } else if let Some(dec3) = entry.ec3_config()? {
+        description = format!("{} Hz, {} channels ({})", dec3.sample_rate().unwrap_or(0), dec3.channel_count(), dec3.channel_layout());
+    } else if let Some(pcm) = entry.pcm_format()? {
+        description = format!("{}, {}", pcm.name(), description);
+    }
+    Ok(description)
+}
+
+// Parses video_in's moov and checks its samples are all in the file
+fn read_movie(video_in: &str) -> Result<(MParser, MoovAtom), String> {
+    let mut parser = MParser::new(video_in)?;
+
+    for issue in &FileLayout::walk(&parser.data).issues {
+        println!("Warning: {}", issue);
+    }
 
-    let mut parser = match MParser::new(video_in) {
-        Ok(p)  => p,
-        Err(e) => panic!(e)
+    let atoms = MovieAtoms::parse_file(&mut parser)?;
+    let moov = match atoms.moov {
+        Some(moov) => moov,
+        None       => return Err("Could not find moov atom".to_string())
     };
 
-    let moov_pos = try!(find_moov(&parser.data));
+    for trak in &moov.traks {
+        if trak.stbl().is_some() {
+            trak.sample_index()?.check_locations(parser.get_size() as u64)?;
+        }
+    }
+
+    Ok((parser, moov))
+}
+
+// Looks inside the first kept sample of NAL unit based tracks, as stss
+// is sometimes wrong about sync samples and avc3/hev1 streams can't be
+// decoded before their first in band parameter sets.
+fn check_cut_point(data: &[u8], trak: &TrakAtom, first: usize) -> Result<(), String> {
+    let index = trak.sample_index()?;
+    let sample = match index.get(first) {
+        Some(s) => s,
+        None    => return Ok(())
+    };
+    let entry = match trak.sample_entry(sample.sample_description_index) {
+        Some(e) => e,
+        None    => return Ok(())
+    };
+    let bytes = &data[sample.offset as usize..(sample.offset as usize) + (sample.size as usize)];
+
+    // A sample that can't be parsed is only worth a warning, not the trim
+    let warn_unless = |checked: Result<bool, String>, problem: &str| match checked {
+        Ok(true)  => (),
+        Ok(false) => println!("Warning: sample {} {}", first, problem),
+        Err(e)    => println!("Warning: sample {} can't be read: {}", first, e)
+    };
+
+    if let Some(config) = entry.avc_config()? {
+        warn_unless(avc::sample_has_idr(bytes, config.length_size),
+                    "is marked sync but has no IDR slice");
+        if config.sps.is_empty() {
+            warn_unless(avc::sample_has_parameter_sets(bytes, config.length_size),
+                        "has no SPS and PPS to start decoding from");
+        }
+    }
+
+    if entry.av1_config()?.is_some() {
+        warn_unless(av1::sample_has_sequence_header(bytes),
+                    "has no sequence header to start decoding from");
+    }
 
-    let mut atoms = MovieAtoms::new();
-    let mut view = parser.get_view_at(moov_pos);
-    try!(atoms.parse(&mut view, 0));
+    if let Some(config) = entry.hevc_config()? {
+        warn_unless(hevc::sample_irap_type(bytes, config.length_size).map(|t| t.is_some()),
+                    "is marked sync but has no IRAP picture");
+        if config.nal_units(hevc::NAL_SPS).next().is_none() {
+            warn_unless(hevc::sample_has_parameter_sets(bytes, config.length_size),
+                        "has no VPS, SPS and PPS to start decoding from");
+        }
+    }
 
     Ok(())
 }
 
+// Whether a sync sample really starts a key frame. Only checked for AV1,
+// where some muxers mark every frame with a sequence header as sync.
+fn is_key_frame(data: &[u8], trak: &TrakAtom, sample: &Sample) -> bool {
+    let entry = match trak.sample_entry(sample.sample_description_index) {
+        Some(e) => e,
+        None    => return true
+    };
+    let config = match entry.av1_config() {
+        Ok(Some(c)) => c,
+        _           => return true
+    };
+    let reduced = match config.sequence_header() {
+        Ok(Some(header)) => header.reduced_still_picture_header,
+        _                => false
+    };
+
+    let bytes = &data[sample.offset as usize..(sample.offset as usize) + (sample.size as usize)];
+    match av1::sample_is_keyframe(bytes, reduced) {
+        Ok(true)  => true,
+        Ok(false) => {
+            println!("Warning: sample {} is marked sync but is not a key frame, skipping it", sample.index);
+            false
+        },
+        Err(e) => {
+            println!("Warning: sample {} is marked sync but can't be read: {}", sample.index, e);
+            false
+        }
+    }
+}
+
+// Samples to decode before first for it to come out right, from the roll
+// group when there is one or else from the codec. AAC and MP3 frames
+// overlap the one before and Opus takes 80 ms to converge, while FLAC,
+// ALAC, AC-3 and PCM frames stand alone.
+fn pre_roll(trak: &TrakAtom, index: &SampleIndex, first: usize) -> Result<usize, String> {
+    if let Some(distance) = index.roll_distance(first) {
+        return Ok(((-(distance as i64)).max(0) as usize).min(first));
+    }
+
+    let sample = match index.get(first) {
+        Some(s) => s,
+        None    => return Ok(0)
+    };
+    let entry = match trak.sample_entry(sample.sample_description_index) {
+        Some(e) => e,
+        None    => return Ok(0)
+    };
+
+    match entry.typ.as_str() {
+        "mp4a" | ".mp3" => Ok(1.min(first)),
+        "Opus" => {
+            let pre_roll = (OPUS_PRE_ROLL as u64) * (index.time_scale() as u64) / (OPUS_SAMPLE_RATE as u64);
+            let target = sample.dts.saturating_sub(pre_roll);
+            Ok(first - index.sample_at_decode_time(target).unwrap_or(first).min(first))
+        },
+        _ => Ok(0)
+    }
+}
+
+// Where the media starts without an edit list. Opus streams begin with
+// pre_skip samples of decoder priming, which the edit list should hide.
+fn priming(trak: &TrakAtom, time_scale: u32) -> Result<i64, String> {
+    let dops = match trak.sample_entry(1) {
+        Some(entry) => entry.opus_config()?,
+        None        => None
+    };
+
+    match dops {
+        Some(dops) => Ok((dops.pre_skip as i64) * (time_scale as i64) / (OPUS_SAMPLE_RATE as i64)),
+        None       => Ok(0)
+    }
+}
+
+// The samples of trak needed to present start..stop seconds of the movie,
+// and the edit list that shows exactly that span. Decoding has to start at a
+// sync sample, so the samples before start that come with it are hidden by
+// the edit. None when there's no sync sample to start from.
+fn select_samples(data: &[u8], trak: &TrakAtom, movie_time_scale: u32, start: f64, stop: f64) -> Result<Option<TrackSelection>, String> {
+    let index = trak.sample_index()?;
+    let nothing = TrackSelection { first: 0, end: 0, edits: Some(vec![]) };
+
+    // where the track's own edit list puts its media on the movie timeline
+    let (delay, media_start) = match trak.elst() {
+        Some(elst) => elst.start(),
+        None       => (0, priming(trak, index.time_scale())?)
+    };
+    let delay = (delay as f64) / (movie_time_scale as f64);
+    let to_media_time = |seconds: f64| media_start + index.seconds_to_media_time(seconds - delay);
+
+    let from = to_media_time(start.max(delay));
+    let to = to_media_time(stop);
+    if index.is_empty() || to <= from || from >= index.presentation_end() {
+        return Ok(Some(nothing));
+    }
+
+    let first = match index.cut_point_where(from, |s| is_key_frame(data, trak, s)) {
+        Some(first) => first - pre_roll(trak, &index, first)?,
+        None        => return Ok(None)
+    };
+
+    // everything decoded up to the last sample presented before stop, as
+    // samples presented earlier can still come later in decode order. None
+    // decoded after to - least offset is presented before it.
+    let (least, _) = index.composition_offset_range();
+    let end = index.iter_from(first)
+        .take_while(|s| (s.dts as i64) + least < to)
+        .filter(|s| s.pts < to)
+        .last()
+        .map(|s| (s.index as usize) + 1)
+        .unwrap_or(first + 1);
+
+    let kept = || index.iter_from(first).take(end - first);
+    let base_dts = index.get(first).map(|s| s.dts).unwrap_or(0);
+    let shown_from = kept().map(|s| s.pts).min().unwrap_or(0).max(from);
+    let shown_to = kept().map(|s| s.pts + (s.duration as i64)).max().unwrap_or(0).min(to);
+
+    let mut edits = vec![];
+    let leading = ((delay - start) * (movie_time_scale as f64)).round();
+    if leading > 0.0 {
+        edits.push(edit(leading as u64, -1));
+    }
+    let shown = ((shown_to - shown_from).max(0) as f64) * (movie_time_scale as f64) / (index.time_scale() as f64);
+
+    // media_time is on the output's presentation timeline: decode times
+    // start from 0 at the first kept sample and the writer moves
+    // composition times up by the same shift so none is negative
+    let shift = presentation_shift(kept(), base_dts);
+    let media_time = shown_from - (base_dts as i64) + shift;
+    if media_time < 0 {
+        return Err(format!("Track starts at media time {} before its first sample", media_time));
+    }
+    edits.push(edit(shown.round() as u64, media_time));
+
+    Ok(Some(TrackSelection { first, end, edits: Some(edits) }))
+}
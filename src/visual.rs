@@ -0,0 +1,193 @@
+use avc::ColourDescription;
+use reader::*;
+
+/* ============================ Visual entry metadata ============================ */
+
+// colr, either code points or an ICC profile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ColourInformation {
+    // nclx, or QuickTime's nclc, which has no range flag and is read as
+    // limited range
+    Nclx(ColourDescription),
+    // prof for a full ICC profile, rICC for a restricted one
+    Icc { restricted: bool, profile: Vec<u8> },
+    Unknown(String)
+}
+
+impl ColourInformation {
+    pub fn parse(data: &[u8]) -> Result<ColourInformation, String> {
+        let colour_type = String::from_utf8_lossy(read_bytes_at(data, 0, 4)?).to_string();
+
+        match colour_type.as_str() {
+            "nclx" | "nclc" => {
+                let mut code_points = [0u8; 3];
+                for (i, code_point) in code_points.iter_mut().enumerate() {
+                    let value = read_u16_at(data, 4 + 2 * i)?;
+                    if value > 255 {
+                        return Err(format!("colr: reserved code point {}", value));
+                    }
+                    *code_point = value as u8;
+                }
+                let full_range = colour_type == "nclx" && read_u8_at(data, 10)? & 0x80 != 0;
+
+                Ok(ColourInformation::Nclx(ColourDescription {
+                    colour_primaries:         code_points[0],
+                    transfer_characteristics: code_points[1],
+                    matrix_coefficients:      code_points[2],
+                    full_range
+                }))
+            },
+            "prof" | "rICC" => Ok(ColourInformation::Icc { restricted: colour_type == "rICC", profile: data[4..].to_vec() }),
+            _ => Ok(ColourInformation::Unknown(colour_type))
+        }
+    }
+
+    pub fn description(&self) -> Option<ColourDescription> {
+        match *self {
+            ColourInformation::Nclx(description) => Some(description),
+            _ => None
+        }
+    }
+}
+
+// pasp, the shape of a pixel as horizontal over vertical spacing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelAspectRatio {
+    pub h_spacing: u32,
+    pub v_spacing: u32
+}
+
+impl PixelAspectRatio {
+    pub fn parse(data: &[u8]) -> Result<PixelAspectRatio, String> {
+        let pasp = PixelAspectRatio { h_spacing: read_u32_at(data, 0)?, v_spacing: read_u32_at(data, 4)? };
+        if pasp.h_spacing == 0 || pasp.v_spacing == 0 {
+            return Err(format!("pasp: spacing of {}:{}", pasp.h_spacing, pasp.v_spacing));
+        }
+        Ok(pasp)
+    }
+}
+
+// clap, the clean aperture as fractions: its size, and its centre's offset
+// from the centre of the picture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CleanAperture {
+    pub width: (u32, u32),
+    pub height: (u32, u32),
+    pub horizontal_offset: (i32, u32),
+    pub vertical_offset: (i32, u32)
+}
+
+impl CleanAperture {
+    pub fn parse(data: &[u8]) -> Result<CleanAperture, String> {
+        let mut values = [0u32; 8];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = read_u32_at(data, 4 * i)?;
+        }
+        if values[1] == 0 || values[3] == 0 || values[5] == 0 || values[7] == 0 {
+            return Err("clap: fraction with a denominator of 0".to_string());
+        }
+
+        Ok(CleanAperture {
+            width:             (values[0], values[1]),
+            height:            (values[2], values[3]),
+            horizontal_offset: (values[4] as i32, values[5]),
+            vertical_offset:   (values[6] as i32, values[7])
+        })
+    }
+
+    pub fn width(&self) -> f64 { (self.width.0 as f64) / (self.width.1 as f64) }
+
+    pub fn height(&self) -> f64 { (self.height.0 as f64) / (self.height.1 as f64) }
+}
+
+// mdcv, SMPTE ST 2086 mastering display colour volume. Chromaticities are
+// in units of 0.00002, luminances in units of 0.0001 cd/m².
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MasteringDisplayColourVolume {
+    // x and y of the green, blue and red primaries, in that order
+    pub display_primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32
+}
+
+impl MasteringDisplayColourVolume {
+    pub fn parse(data: &[u8]) -> Result<MasteringDisplayColourVolume, String> {
+        let mut display_primaries = [(0, 0); 3];
+        for (i, primary) in display_primaries.iter_mut().enumerate() {
+            *primary = (read_u16_at(data, 4 * i)?, read_u16_at(data, 4 * i + 2)?);
+        }
+
+        Ok(MasteringDisplayColourVolume {
+            display_primaries,
+            white_point:   (read_u16_at(data, 12)?, read_u16_at(data, 14)?),
+            max_luminance: read_u32_at(data, 16)?,
+            min_luminance: read_u32_at(data, 20)?
+        })
+    }
+
+    // cd/m²
+    pub fn max_luminance(&self) -> f64 { (self.max_luminance as f64) / 10000.0 }
+
+    pub fn min_luminance(&self) -> f64 { (self.min_luminance as f64) / 10000.0 }
+}
+
+// clli, in cd/m²
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    pub max_content_light_level: u16,
+    pub max_pic_average_light_level: u16
+}
+
+impl ContentLightLevel {
+    pub fn parse(data: &[u8]) -> Result<ContentLightLevel, String> {
+        Ok(ContentLightLevel {
+            max_content_light_level:     read_u16_at(data, 0)?,
+            max_pic_average_light_level: read_u16_at(data, 2)?
+        })
+    }
+}
+
+// btrt, bits per second apart from the buffer size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitRate {
+    pub buffer_size_db: u32,
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32
+}
+
+impl BitRate {
+    pub fn parse(data: &[u8]) -> Result<BitRate, String> {
+        Ok(BitRate {
+            buffer_size_db: read_u32_at(data, 0)?,
+            max_bitrate:    read_u32_at(data, 4)?,
+            avg_bitrate:    read_u32_at(data, 8)?
+        })
+    }
+}
+
+// fiel, QuickTime's field handling: 1 for progressive, 2 for interlaced
+// with detail giving the field order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldHandling {
+    pub fields: u8,
+    pub detail: u8
+}
+
+impl FieldHandling {
+    pub fn parse(data: &[u8]) -> Result<FieldHandling, String> {
+        Ok(FieldHandling { fields: read_u8_at(data, 0)?, detail: read_u8_at(data, 1)? })
+    }
+
+    pub fn is_interlaced(&self) -> bool { self.fields == 2 }
+
+    // detail 1 and 9 put the top field first, 6 and 14 the bottom one
+    pub fn field_order(&self) -> &'static str {
+        match (self.fields, self.detail) {
+            (1, _)           => "progressive",
+            (_, 1) | (_, 9)  => "top field first",
+            (_, 6) | (_, 14) => "bottom field first",
+            _                => "interlaced"
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use reader::*;
+
+/* ================================= VP8 / VP9 ================================= */
+
+// VPCodecConfigurationRecord, the payload of vpcC in vp08 and vp09 entries.
+// Only version 1 is parsed, version 0 was a draft with another layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VpCodecConfig {
+    pub version: u8,
+    pub flags: u32,
+    pub profile: u8,
+    pub level: u8,
+    pub bit_depth: u8,
+    // 0 4:2:0 vertical, 1 4:2:0 colocated, 2 4:2:2, 3 4:4:4
+    pub chroma_subsampling: u8,
+    pub full_range: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub codec_initialization_data: Vec<u8>
+}
+
+impl VpCodecConfig {
+    // data is the payload of the full box, version and flags included
+    pub fn parse(data: &[u8]) -> Result<VpCodecConfig, String> {
+        let version = read_u8_at(data, 0)?;
+        if version != 1 {
+            return Err(format!("vpcC: unsupported version {}", version));
+        }
+
+        let depth_and_chroma = read_u8_at(data, 6)?;
+        let init_size = read_u16_at(data, 10)? as usize;
+
+        Ok(VpCodecConfig {
+            version,
+            flags:                     read_u24_at(data, 1)?,
+            profile:                   read_u8_at(data, 4)?,
+            level:                     read_u8_at(data, 5)?,
+            bit_depth:                 depth_and_chroma >> 4,
+            chroma_subsampling:        (depth_and_chroma >> 1) & 0x7,
+            full_range:                depth_and_chroma & 0x1 != 0,
+            colour_primaries:          read_u8_at(data, 7)?,
+            transfer_characteristics:  read_u8_at(data, 8)?,
+            matrix_coefficients:       read_u8_at(data, 9)?,
+            codec_initialization_data: read_bytes_at(data, 12, init_size)?.to_vec()
+        })
+    }
+
+    pub fn chroma_format(&self) -> &'static str {
+        match self.chroma_subsampling {
+            0 | 1 => "4:2:0",
+            2     => "4:2:2",
+            _     => "4:4:4"
+        }
+    }
+}
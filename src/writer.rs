@@ -0,0 +1,1056 @@
+use atoms::*;
+use fixed::*;
+use layout::*;
+use reader::*;
+use samples::*;
+use stbl::*;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+/* ================================ Byte writers ================================ */
+
+pub fn write_u8(out: &mut Vec<u8>, v: u8) { out.push(v); }
+
+pub fn write_u16(out: &mut Vec<u8>, v: u16) { out.extend_from_slice(&v.to_be_bytes()); }
+
+pub fn write_u32(out: &mut Vec<u8>, v: u32) { out.extend_from_slice(&v.to_be_bytes()); }
+
+pub fn write_u64(out: &mut Vec<u8>, v: u64) { out.extend_from_slice(&v.to_be_bytes()); }
+
+pub fn write_version_and_flags(out: &mut Vec<u8>, version: u8, flags: u32) {
+    write_u32(out, ((version as u32) << 24) | (flags & 0xff_ffff));
+}
+
+// Size of the header write_atom puts in front of payload_size bytes
+pub fn atom_header_size(payload_size: u64) -> u64 {
+    if payload_size + 8 > u32::MAX as u64 { 16 } else { 8 }
+}
+
+// Writes the size and type header, using a 64 bit size only when needed
+pub fn write_atom_header(out: &mut Vec<u8>, typ: &str, payload_size: u64) {
+    if atom_header_size(payload_size) == 16 {
+        write_u32(out, 1);
+        out.extend_from_slice(typ.as_bytes());
+        write_u64(out, payload_size + 16);
+    } else {
+        write_u32(out, (payload_size + 8) as u32);
+        out.extend_from_slice(typ.as_bytes());
+    }
+}
+
+pub fn write_atom(out: &mut Vec<u8>, typ: &str, payload: &[u8]) {
+    write_atom_header(out, typ, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+// Writes an atom whose payload is produced by body, e.g. a container from
+// its rebuilt children
+pub fn write_atom_with<F>(out: &mut Vec<u8>, typ: &str, body: F) -> Result<(), String>
+    where F: FnOnce(&mut Vec<u8>) -> Result<(), String> {
+    let mut payload = vec![];
+    body(&mut payload)?;
+    write_atom(out, typ, &payload);
+    Ok(())
+}
+
+/* ================================ Atom writers ================================ */
+
+// Serializes a parsed atom back into its file form, header included. The
+// location and size it was parsed with are ignored.
+pub trait AtomWriter {
+    fn write(&self, out: &mut Vec<u8>);
+}
+
+impl AtomWriter for TimeToSampleAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32(&mut payload, entry.sample_count);
+            write_u32(&mut payload, entry.sample_delta);
+        }
+        write_atom(out, "stts", &payload);
+    }
+}
+
+impl AtomWriter for CompositionOffsetAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32(&mut payload, entry.sample_count);
+            write_u32(&mut payload, entry.sample_offset as i32 as u32);
+        }
+        write_atom(out, "ctts", &payload);
+    }
+}
+
+impl AtomWriter for CompositionShiftAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        let fields = [
+            self.composition_to_dts_shift,
+            self.least_decode_to_display_delta,
+            self.greatest_decode_to_display_delta,
+            self.composition_start_time,
+            self.composition_end_time
+        ];
+        for &field in &fields {
+            if self.version == 1 {
+                write_u64(&mut payload, field as u64);
+            } else {
+                write_u32(&mut payload, field as i32 as u32);
+            }
+        }
+        write_atom(out, "cslg", &payload);
+    }
+}
+
+impl AtomWriter for SampleToChunkAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32(&mut payload, entry.first_chunk);
+            write_u32(&mut payload, entry.samples_per_chunk);
+            write_u32(&mut payload, entry.sample_description_index);
+        }
+        write_atom(out, "stsc", &payload);
+    }
+}
+
+impl AtomWriter for ChunkOffsetAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.offsets.len() as u32);
+        for &offset in &self.offsets {
+            if self.large {
+                write_u64(&mut payload, offset);
+            } else {
+                write_u32(&mut payload, offset as u32);
+            }
+        }
+        write_atom(out, if self.large { "co64" } else { "stco" }, &payload);
+    }
+}
+
+impl AtomWriter for SampleSizeAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+
+        if self.field_size == 32 {
+            write_u32(&mut payload, self.sample_size);
+            write_u32(&mut payload, self.sample_count);
+            if self.sample_size == 0 {
+                for &size in &self.sizes {
+                    write_u32(&mut payload, size);
+                }
+            }
+            write_atom(out, "stsz", &payload);
+            return;
+        }
+
+        write_u32(&mut payload, self.field_size as u32);
+        write_u32(&mut payload, self.sample_count);
+        match self.field_size {
+            4  => {
+                for pair in self.sizes.chunks(2) {
+                    let low = pair.get(1).cloned().unwrap_or(0);
+                    write_u8(&mut payload, ((pair[0] as u8) << 4) | (low as u8 & 0xf));
+                }
+            },
+            8  => for &size in &self.sizes { write_u8(&mut payload, size as u8); },
+            _  => for &size in &self.sizes { write_u16(&mut payload, size as u16); }
+        }
+        write_atom(out, "stz2", &payload);
+    }
+}
+
+impl AtomWriter for SyncSampleAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.sample_numbers.len() as u32);
+        for &number in &self.sample_numbers {
+            write_u32(&mut payload, number);
+        }
+        write_atom(out, &self.typ, &payload);
+    }
+}
+
+impl AtomWriter for SampleDependencyAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        for entry in &self.entries {
+            write_u8(&mut payload, entry.to_byte());
+        }
+        write_atom(out, "sdtp", &payload);
+    }
+}
+
+impl AtomWriter for SampleToGroupAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        let version = if self.grouping_type_parameter.is_some() { 1 } else { 0 };
+        write_version_and_flags(&mut payload, version, self.flags);
+        payload.extend_from_slice(self.grouping_type.as_bytes());
+        if let Some(parameter) = self.grouping_type_parameter {
+            write_u32(&mut payload, parameter);
+        }
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32(&mut payload, entry.sample_count);
+            write_u32(&mut payload, entry.group_description_index);
+        }
+        write_atom(out, "sbgp", &payload);
+    }
+}
+
+fn write_aux_info_type(out: &mut Vec<u8>, aux_info_type: &Option<AuxInfoType>) {
+    if let Some(ref t) = *aux_info_type {
+        out.extend_from_slice(t.aux_info_type.as_bytes());
+        write_u32(out, t.aux_info_type_parameter);
+    }
+}
+
+impl AtomWriter for SampleAuxInfoSizesAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        let flags = if self.aux_info_type.is_some() { self.flags | 1 } else { self.flags & !1 };
+        write_version_and_flags(&mut payload, self.version, flags);
+        write_aux_info_type(&mut payload, &self.aux_info_type);
+        write_u8(&mut payload, self.default_sample_info_size);
+        write_u32(&mut payload, self.sample_count);
+        if self.default_sample_info_size == 0 {
+            payload.extend_from_slice(&self.sizes);
+        }
+        write_atom(out, "saiz", &payload);
+    }
+}
+
+impl AtomWriter for SampleAuxInfoOffsetsAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        let flags = if self.aux_info_type.is_some() { self.flags | 1 } else { self.flags & !1 };
+        write_version_and_flags(&mut payload, self.version, flags);
+        write_aux_info_type(&mut payload, &self.aux_info_type);
+        write_u32(&mut payload, self.offsets.len() as u32);
+        for &offset in &self.offsets {
+            if self.version == 1 {
+                write_u64(&mut payload, offset);
+            } else {
+                write_u32(&mut payload, offset as u32);
+            }
+        }
+        write_atom(out, "saio", &payload);
+    }
+}
+
+impl AtomWriter for SubSampleInformationAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32(&mut payload, entry.sample_delta);
+            write_u16(&mut payload, entry.subsamples.len() as u16);
+            for subsample in &entry.subsamples {
+                if self.version == 1 {
+                    write_u32(&mut payload, subsample.size);
+                } else {
+                    write_u16(&mut payload, subsample.size as u16);
+                }
+                write_u8(&mut payload, subsample.priority);
+                write_u8(&mut payload, subsample.discardable);
+                write_u32(&mut payload, subsample.codec_specific_parameters);
+            }
+        }
+        write_atom(out, "subs", &payload);
+    }
+}
+
+impl AtomWriter for EditListAtom {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+        write_version_and_flags(&mut payload, self.version, self.flags);
+        write_u32(&mut payload, self.entries.len() as u32);
+        for entry in &self.entries {
+            if self.version == 1 {
+                write_u64(&mut payload, entry.segment_duration);
+                write_u64(&mut payload, entry.media_time as u64);
+            } else {
+                write_u32(&mut payload, entry.segment_duration as u32);
+                write_u32(&mut payload, entry.media_time as i32 as u32);
+            }
+            write_u32(&mut payload, entry.media_rate.to_bits());
+        }
+        write_atom(out, "elst", &payload);
+    }
+}
+
+/* ================================ Movie writer ================================ */
+
+// Which samples of a track go into the output, first..end in decode order,
+// and the edit list to give it. None keeps the track's own edts, an empty
+// list writes none.
+pub struct TrackSelection {
+    pub first: usize,
+    pub end: usize,
+    pub edits: Option<Vec<EditListEntry>>
+}
+
+impl TrackSelection {
+    pub fn len(&self) -> usize { self.end - self.first }
+
+    pub fn is_empty(&self) -> bool { self.end <= self.first }
+}
+
+// How the output's chunks are formed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OutputOptions {
+    // Rebuild the chunks so each holds about this many seconds of its
+    // track, interleaving the tracks' chunks by time. None keeps the
+    // source's chunks and their order.
+    pub interleave: Option<f64>
+}
+
+// Bytes copied from the source in one go
+#[derive(Clone, Copy, Debug)]
+struct SourceRange {
+    offset: u64,
+    size: u64
+}
+
+// Samples of one track written out as one chunk. When rechunking they can
+// come from more than one place in the source.
+struct OutputChunk {
+    track: usize,
+    count: usize,
+    sample_description_index: u32,
+    // the source chunk or interleave slot the samples came from
+    group: u64,
+    // chunks are written in order of this
+    position: (u64, usize),
+    sources: Vec<SourceRange>,
+    size: u64,
+    // from the start of the mdat payload
+    offset: u64
+}
+
+// The kept samples' auxiliary information for one saio of a track, copied
+// into the mdat after the chunks
+struct OutputAuxInfo {
+    track: usize,
+    // which of the track's saio it's for
+    table: usize,
+    sources: Vec<SourceRange>,
+    size: u64,
+    // from the start of the mdat payload
+    offset: u64
+}
+
+// Per-sample tables with no rebuilding support. Copying them would describe
+// the wrong samples, so they're left out.
+const UNTRIMMED_TABLES: [&str; 3] = ["padb", "stdp", "stsh"];
+
+// Writes a non-fragmented movie holding only the selected samples of each
+// track: ftyp and other top level atoms first, then a rebuilt moov, then a
+// single mdat with the kept chunks, in their original order unless the
+// options ask for them to be interleaved.
+pub struct MovieWriter<'a> {
+    data: &'a [u8],
+    moov: &'a MoovAtom,
+    moov_node: AtomNode<'a>,
+    top_level: Vec<AtomRef<'a>>,
+    indexes: Vec<SampleIndex<'a>>,
+    selections: Vec<TrackSelection>,
+    options: OutputOptions,
+    chunks: Vec<OutputChunk>,
+    aux_info: Vec<OutputAuxInfo>
+}
+
+impl<'a> MovieWriter<'a> {
+    pub fn new(data: &'a [u8], moov: &'a MoovAtom, selections: Vec<TrackSelection>,
+               options: OutputOptions) -> Result<MovieWriter<'a>, String> {
+        if let Some(interleave) = options.interleave {
+            if !interleave.is_finite() || interleave <= 0.0 {
+                return Err(format!("Can't interleave every {}s", interleave));
+            }
+        }
+
+        let reader = AtomReader::new(data);
+        let mut moov_node = None;
+        let mut top_level = vec![];
+
+        for atom in FileLayout::walk(data).atoms {
+            match atom.typ.as_str() {
+                "moov" => moov_node = Some(AtomNode::parse(reader.atom_at(atom.location)?)?),
+                "moof" | "mfra" => return Err("Fragmented files aren't supported".to_string()),
+                "mdat" | "free" | "skip" | "wide" => {},
+                _ => match reader.atom_at(atom.location) {
+                    Ok(a)  => top_level.push(a),
+                    Err(e) => println!("Warning: leaving out {}: {}", atom, e)
+                }
+            }
+        }
+
+        let moov_node = match moov_node {
+            Some(node) => node,
+            None       => return Err("Could not find moov atom".to_string())
+        };
+        if moov_node.child("mvex").is_some() {
+            return Err("Fragmented files aren't supported".to_string());
+        }
+
+        let trak_count = moov_node.children_of_type("trak").count();
+        if trak_count != moov.traks.len() || trak_count != selections.len() {
+            return Err(format!("{} trak atoms but {} parsed tracks and {} selections",
+                               trak_count, moov.traks.len(), selections.len()));
+        }
+
+        let mut indexes = vec![];
+        for (trak, selection) in moov.traks.iter().zip(&selections) {
+            let index = trak.sample_index()?;
+            if selection.end > index.len() || selection.first > selection.end {
+                return Err(format!("Samples {}..{} selected from a track of {}",
+                                   selection.first, selection.end, index.len()));
+            }
+            indexes.push(index);
+        }
+
+        let mut writer = MovieWriter {
+            data, moov, moov_node, top_level, indexes, selections, options, chunks: vec![], aux_info: vec![]
+        };
+        writer.plan_chunks()?;
+        writer.plan_aux_info()?;
+
+        Ok(writer)
+    }
+
+    // Groups each track's kept samples into chunks, either along the source
+    // chunks or by interleave slot, and lays them out in source order or
+    // slot order respectively
+    fn plan_chunks(&mut self) -> Result<(), String> {
+        let mut chunks = vec![];
+
+        for (track, (index, selection)) in self.indexes.iter().zip(&self.selections).enumerate() {
+            let mut current: Option<OutputChunk> = None;
+            let base_dts = index.get(selection.first).map(|s| s.dts).unwrap_or(0);
+
+            for sample in index.iter_from(selection.first).take(selection.len()) {
+                if sample.end() > self.data.len() as u64 {
+                    return Err(format!("Sample {} of track {} @ {} with size {} is past the end of the file",
+                                       sample.index, track + 1, sample.offset, sample.size));
+                }
+
+                let group = match self.options.interleave {
+                    Some(seconds) => {
+                        let time = index.media_time_to_seconds((sample.dts - base_dts) as i64);
+                        (time / seconds).floor() as u64
+                    },
+                    None => sample.chunk as u64
+                };
+
+                if let Some(ref mut chunk) = current {
+                    if chunk.group == group && chunk.sample_description_index == sample.sample_description_index {
+                        let last = chunk.sources.last_mut().expect("chunks start with a sample");
+                        if last.offset + last.size == sample.offset {
+                            last.size += sample.size as u64;
+                        } else {
+                            chunk.sources.push(SourceRange { offset: sample.offset, size: sample.size as u64 });
+                        }
+                        chunk.count += 1;
+                        chunk.size += sample.size as u64;
+                        continue;
+                    }
+                }
+
+                chunks.extend(current.take());
+                let position = match self.options.interleave {
+                    Some(_) => (group, track),
+                    None    => (sample.offset, track)
+                };
+                current = Some(OutputChunk {
+                    track,
+                    count: 1,
+                    sample_description_index: sample.sample_description_index,
+                    group,
+                    position,
+                    sources: vec![SourceRange { offset: sample.offset, size: sample.size as u64 }],
+                    size: sample.size as u64,
+                    offset: 0
+                });
+            }
+
+            chunks.extend(current);
+        }
+
+        // stable, so a track's chunks keep their order within a slot
+        chunks.sort_by_key(|c| c.position);
+
+        let mut offset = 0u64;
+        for chunk in chunks.iter_mut() {
+            chunk.offset = offset;
+            offset += chunk.size;
+        }
+
+        self.chunks = chunks;
+        Ok(())
+    }
+
+    // Finds the kept samples' auxiliary information through each saio and
+    // the saiz of the same type. saio gives either one offset that all the
+    // track's information follows on from, or one offset per chunk.
+    fn plan_aux_info(&mut self) -> Result<(), String> {
+        let mut aux_info = vec![];
+        let mut offset = self.chunks.iter().map(|c| c.size).sum::<u64>();
+
+        for (track, (index, selection)) in self.indexes.iter().zip(&self.selections).enumerate() {
+            let stbl = match self.moov.traks[track].stbl() {
+                Some(stbl) => stbl,
+                None       => continue
+            };
+            let chunk_count = stbl.stco.as_ref().map(|s| s.offsets.len()).unwrap_or(0);
+
+            for (table, saio) in stbl.saio.iter().enumerate() {
+                let saiz = match stbl.saiz.iter().find(|z| z.aux_info_type == saio.aux_info_type) {
+                    Some(saiz) => saiz,
+                    None       => return Err(format!("saio of track {} has no matching saiz", track + 1))
+                };
+                if saio.offsets.is_empty() || (saio.offsets.len() != 1 && saio.offsets.len() != chunk_count) {
+                    return Err(format!("saio of track {} has {} offsets for {} chunks",
+                                       track + 1, saio.offsets.len(), chunk_count));
+                }
+                let per_chunk = saio.offsets.len() > 1;
+
+                let mut sources: Vec<SourceRange> = vec![];
+                let mut chunk = 0;
+                let mut next = saio.offsets[0];
+                for sample in index.iter().take(selection.end) {
+                    if per_chunk && sample.chunk != chunk {
+                        chunk = sample.chunk;
+                        next = saio.offsets[(chunk as usize).saturating_sub(1).min(chunk_count - 1)];
+                    }
+
+                    let size = saiz.size_of(sample.index as usize) as u64;
+                    if (sample.index as usize) >= selection.first && size > 0 {
+                        if next.saturating_add(size) > self.data.len() as u64 {
+                            return Err(format!("Auxiliary information of sample {} of track {} @ {} is past the end of the file",
+                                               sample.index, track + 1, next));
+                        }
+                        match sources.last_mut() {
+                            Some(last) if last.offset + last.size == next => last.size += size,
+                            _ => sources.push(SourceRange { offset: next, size })
+                        }
+                    }
+                    next = next.saturating_add(size);
+                }
+
+                let size = sources.iter().map(|s| s.size).sum::<u64>();
+                aux_info.push(OutputAuxInfo { track, table, sources, size, offset });
+                offset += size;
+            }
+        }
+
+        self.aux_info = aux_info;
+        Ok(())
+    }
+
+    pub fn write(&self, video_out: &str) -> Result<(), String> {
+        let head = self.build_head()?;
+
+        let file = match File::create(video_out) {
+            Ok(f)  => f,
+            Err(e) => return Err(format!("Couldn't create video out: {}", e))
+        };
+        let mut out = BufWriter::new(file);
+
+        let mut result = out.write_all(&head);
+        let sources = self.chunks.iter().flat_map(|c| c.sources.iter())
+            .chain(self.aux_info.iter().flat_map(|a| a.sources.iter()));
+        for source in sources {
+            let bytes = &self.data[source.offset as usize..(source.offset + source.size) as usize];
+            result = result.and_then(|_| out.write_all(bytes));
+        }
+
+        match result.and_then(|_| out.flush()) {
+            Ok(_)  => Ok(()),
+            Err(e) => Err(format!("Couldn't write video out: {}", e))
+        }
+    }
+
+    // Everything before the chunks: ftyp and the other top level atoms, the
+    // rebuilt moov, then the mdat header
+    fn build_head(&self) -> Result<Vec<u8>, String> {
+        let mut head = vec![];
+        if let Some(ftyp) = self.top_level.iter().find(|a| a.typ == "ftyp") {
+            head.extend_from_slice(ftyp.data);
+        }
+        for atom in self.top_level.iter().filter(|a| a.typ != "ftyp") {
+            head.extend_from_slice(atom.data);
+        }
+
+        let mdat_size = self.chunks.iter().map(|c| c.size).sum::<u64>()
+            + self.aux_info.iter().map(|a| a.size).sum::<u64>();
+        let mdat_header = atom_header_size(mdat_size);
+
+        // stco vs co64 changes the size of moov, so size it with 32 bit
+        // offsets first and only switch when the offsets don't fit
+        let mut large = false;
+        let mut moov_size = self.build_moov(0, large)?.len() as u64;
+        if (head.len() as u64) + moov_size + mdat_header + mdat_size > u32::MAX as u64 {
+            large = true;
+            moov_size = self.build_moov(0, large)?.len() as u64;
+        }
+
+        let base = (head.len() as u64) + moov_size + mdat_header;
+        head.extend(self.build_moov(base, large)?);
+        write_atom_header(&mut head, "mdat", mdat_size);
+        Ok(head)
+    }
+
+    fn build_moov(&self, base: u64, large: bool) -> Result<Vec<u8>, String> {
+        let mut track_durations = vec![];
+        for (trak, selection) in self.moov.traks.iter().zip(&self.selections) {
+            let duration = match selection.edits {
+                Some(ref edits) => edits.iter().map(|e| e.segment_duration).sum(),
+                None            => trak.tkhd.as_ref().map(|t| t.duration).unwrap_or(0)
+            };
+            track_durations.push(duration);
+        }
+        let movie_duration = track_durations.iter().cloned().max().unwrap_or(0);
+
+        let mut out = vec![];
+        write_atom_with(&mut out, "moov", |payload| {
+            let mut track = 0;
+            for child in &self.moov_node.children {
+                match child.atom.typ.as_str() {
+                    Some("mvhd") => payload.extend(with_duration(&child.atom, 16, 24, movie_duration)?),
+                    Some("trak") => {
+                        self.build_trak(payload, child, track, track_durations[track], base, large)?;
+                        track += 1;
+                    },
+                    _ => payload.extend_from_slice(child.atom.data)
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(out)
+    }
+
+    fn build_trak(&self, out: &mut Vec<u8>, node: &AtomNode, track: usize, duration: u64,
+                  base: u64, large: bool) -> Result<(), String> {
+        let selection = &self.selections[track];
+        let has_edts = node.child("edts").is_some();
+
+        write_atom_with(out, "trak", |payload| {
+            for child in &node.children {
+                match child.atom.typ.as_str() {
+                    Some("tkhd") => {
+                        payload.extend(with_duration(&child.atom, 20, 28, duration)?);
+                        if !has_edts {
+                            write_edits(payload, &selection.edits);
+                        }
+                    },
+                    Some("edts") => match selection.edits {
+                        Some(_) => write_edits(payload, &selection.edits),
+                        None    => payload.extend_from_slice(child.atom.data)
+                    },
+                    Some("mdia") => self.build_mdia(payload, child, track, base, large)?,
+                    _ => payload.extend_from_slice(child.atom.data)
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn build_mdia(&self, out: &mut Vec<u8>, node: &AtomNode, track: usize, base: u64, large: bool) -> Result<(), String> {
+        let index = &self.indexes[track];
+        let selection = &self.selections[track];
+        let duration = match (index.get(selection.first), index.get(selection.end.wrapping_sub(1))) {
+            (Some(first), Some(last)) if !selection.is_empty() => last.dts + (last.duration as u64) - first.dts,
+            _ => 0
+        };
+
+        write_atom_with(out, "mdia", |payload| {
+            for child in &node.children {
+                match child.atom.typ.as_str() {
+                    Some("mdhd") => payload.extend(with_duration(&child.atom, 16, 24, duration)?),
+                    Some("minf") => {
+                        write_atom_with(payload, "minf", |minf| {
+                            for grandchild in &child.children {
+                                match grandchild.atom.typ.as_str() {
+                                    Some("stbl") => self.build_stbl(minf, grandchild, track, base, large)?,
+                                    _ => minf.extend_from_slice(grandchild.atom.data)
+                                }
+                            }
+                            Ok(())
+                        })?;
+                    },
+                    _ => payload.extend_from_slice(child.atom.data)
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn build_stbl(&self, out: &mut Vec<u8>, node: &AtomNode, track: usize, base: u64, large: bool) -> Result<(), String> {
+        let stbl = match self.moov.traks[track].stbl() {
+            Some(stbl) => stbl,
+            None       => return Err(format!("Track {} has no stbl", track + 1))
+        };
+        let index = &self.indexes[track];
+        let selection = &self.selections[track];
+        let (first, count) = (selection.first, selection.len());
+
+        let samples = || index.iter_from(first).take(count);
+        let base_dts = index.get(first).map(|s| s.dts).unwrap_or(0);
+        let shift = presentation_shift(samples(), base_dts);
+
+        write_atom_with(out, "stbl", |payload| {
+            let mut sbgp = stbl.sbgp.iter();
+            let mut subs = stbl.subs.iter();
+            let mut saiz = stbl.saiz.iter();
+            let mut saio = stbl.saio.iter().enumerate();
+
+            for child in &node.children {
+                let typ = child.atom.typ.as_str().unwrap_or("");
+                match typ {
+                    "stts" => time_to_sample(samples()).write(payload),
+                    "ctts" => composition_offsets(samples(), shift).write(payload),
+                    "cslg" => composition_shift(samples(), base_dts, shift).write(payload),
+                    "stss" | "stps" => {
+                        let table = if typ == "stss" { &stbl.stss } else { &stbl.stps };
+                        if let Some(ref table) = *table {
+                            trimmed_sync_samples(table, first, count).write(payload);
+                        }
+                    },
+                    "sdtp" => {
+                        if let Some(ref sdtp) = stbl.sdtp {
+                            let mut trimmed = SampleDependencyAtom::new();
+                            trimmed.version = sdtp.version;
+                            trimmed.flags = sdtp.flags;
+                            trimmed.entries = sdtp.entries.iter().skip(first).take(count).cloned().collect();
+                            trimmed.write(payload);
+                        }
+                    },
+                    "sbgp" => {
+                        if let Some(group) = sbgp.next() {
+                            group.trimmed(first, count).write(payload);
+                        }
+                    },
+                    "subs" => {
+                        if let Some(table) = subs.next() {
+                            table.trimmed(first, count).write(payload);
+                        }
+                    },
+                    "saiz" => {
+                        if let Some(table) = saiz.next() {
+                            table.trimmed(first, count).write(payload);
+                        }
+                    },
+                    "saio" => {
+                        if let Some((n, table)) = saio.next() {
+                            self.aux_info_offsets(track, n, table, base, large).write(payload);
+                        }
+                    },
+                    "senc" => return Err(format!("Track {} has a senc in its stbl, which can't be trimmed", track + 1)),
+                    "stsc" => self.sample_to_chunk(track).write(payload),
+                    "stsz" | "stz2" => if index.sizes_count_frames() {
+                        frame_counts(count).write(payload)
+                    } else {
+                        sample_sizes(samples()).write(payload)
+                    },
+                    "stco" | "co64" => self.chunk_offsets(track, base, large).write(payload),
+                    t if UNTRIMMED_TABLES.contains(&t) => println!("Leaving out {} of track {}", t, track + 1),
+                    _ => payload.extend_from_slice(child.atom.data)
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn sample_to_chunk(&self, track: usize) -> SampleToChunkAtom {
+        let mut stsc = SampleToChunkAtom::new();
+        let chunks = self.chunks.iter().filter(|c| c.track == track);
+
+        for (i, chunk) in chunks.enumerate() {
+            let same = stsc.entries.last().is_some_and(|e| {
+                e.samples_per_chunk == chunk.count as u32 && e.sample_description_index == chunk.sample_description_index
+            });
+            if !same {
+                stsc.entries.push(SampleToChunkEntry {
+                    first_chunk: i as u32 + 1,
+                    samples_per_chunk: chunk.count as u32,
+                    sample_description_index: chunk.sample_description_index
+                });
+            }
+        }
+
+        stsc
+    }
+
+    // A saio pointing at where plan_aux_info put the track's information
+    fn aux_info_offsets(&self, track: usize, table: usize, source: &SampleAuxInfoOffsetsAtom,
+                        base: u64, large: bool) -> SampleAuxInfoOffsetsAtom {
+        let offset = self.aux_info.iter()
+            .find(|a| a.track == track && a.table == table)
+            .map(|a| a.offset)
+            .unwrap_or(0);
+
+        let mut saio = SampleAuxInfoOffsetsAtom::new();
+        saio.version = if large { 1 } else { 0 };
+        saio.flags = source.flags;
+        saio.aux_info_type = source.aux_info_type.clone();
+        saio.offsets = vec![base + offset];
+        saio
+    }
+
+    fn chunk_offsets(&self, track: usize, base: u64, large: bool) -> ChunkOffsetAtom {
+        let mut stco = ChunkOffsetAtom::new();
+        stco.large = large;
+        stco.offsets = self.chunks.iter().filter(|c| c.track == track).map(|c| base + c.offset).collect();
+        stco
+    }
+}
+
+// Copy of a mvhd, tkhd or mdhd with its duration replaced. The duration is
+// at v0_offset or, 64 bits wide, at v1_offset into the payload.
+fn with_duration(atom: &AtomRef, v0_offset: usize, v1_offset: usize, duration: u64) -> Result<Vec<u8>, String> {
+    let mut data = atom.data.to_vec();
+    let start = atom.header_size;
+
+    if atom.version()? == 1 {
+        read_u64_at(atom.payload(), v1_offset)?;
+        data[start + v1_offset..start + v1_offset + 8].copy_from_slice(&duration.to_be_bytes());
+    } else {
+        read_u32_at(atom.payload(), v0_offset)?;
+        let duration = duration.min(u32::MAX as u64) as u32;
+        data[start + v0_offset..start + v0_offset + 4].copy_from_slice(&duration.to_be_bytes());
+    }
+
+    Ok(data)
+}
+
+fn write_edits(out: &mut Vec<u8>, edits: &Option<Vec<EditListEntry>>) {
+    let edits = match *edits {
+        Some(ref edits) if !edits.is_empty() => edits,
+        _ => return
+    };
+
+    let mut elst = EditListAtom::new();
+    let fits = |e: &EditListEntry| e.segment_duration <= u32::MAX as u64 && e.media_time == e.media_time as i32 as i64;
+    elst.version = if edits.iter().all(fits) { 0 } else { 1 };
+    elst.entries = edits.clone();
+
+    let mut edts = vec![];
+    elst.write(&mut edts);
+    write_atom(out, "edts", &edts);
+}
+
+fn time_to_sample<I: Iterator<Item = Sample>>(samples: I) -> TimeToSampleAtom {
+    let mut stts = TimeToSampleAtom::new();
+    for sample in samples {
+        match stts.entries.last_mut() {
+            Some(entry) if entry.sample_delta == sample.duration => entry.sample_count += 1,
+            _ => stts.entries.push(TimeToSampleEntry { sample_count: 1, sample_delta: sample.duration })
+        }
+    }
+    stts
+}
+
+// How far the kept samples' composition times have to move up so that none
+// comes before the first kept decode time, where the output media starts.
+// Only negative composition offsets make it more than 0.
+pub fn presentation_shift<I: Iterator<Item = Sample>>(samples: I, base_dts: u64) -> i64 {
+    samples.map(|s| (base_dts as i64) - s.pts).max().unwrap_or(0).max(0)
+}
+
+fn composition_offsets<I: Iterator<Item = Sample>>(samples: I, shift: i64) -> CompositionOffsetAtom {
+    let mut ctts = CompositionOffsetAtom::new();
+    for sample in samples {
+        let offset = sample.pts + shift - (sample.dts as i64);
+        if offset < 0 {
+            ctts.version = 1;
+        }
+        match ctts.entries.last_mut() {
+            Some(entry) if entry.sample_offset == offset => entry.sample_count += 1,
+            _ => ctts.entries.push(CompositionOffsetEntry { sample_count: 1, sample_offset: offset })
+        }
+    }
+    ctts
+}
+
+// cslg worked out again for the kept samples, with times relative to the
+// first kept decode time and composition times moved up by shift
+fn composition_shift<I: Iterator<Item = Sample>>(samples: I, base_dts: u64, shift: i64) -> CompositionShiftAtom {
+    let mut cslg = CompositionShiftAtom::new();
+
+    let (mut least, mut greatest) = (i64::MAX, i64::MIN);
+    let (mut start, mut end) = (i64::MAX, i64::MIN);
+    for sample in samples {
+        let pts = sample.pts + shift;
+        let offset = pts - (sample.dts as i64);
+        least = least.min(offset);
+        greatest = greatest.max(offset);
+        start = start.min(pts);
+        end = end.max(pts + (sample.duration as i64));
+    }
+    if least > greatest {
+        return cslg;
+    }
+
+    cslg.least_decode_to_display_delta    = least;
+    cslg.greatest_decode_to_display_delta = greatest;
+    cslg.composition_to_dts_shift         = (-least).max(0);
+    cslg.composition_start_time           = start - base_dts as i64;
+    cslg.composition_end_time             = end - base_dts as i64;
+
+    let fields = [
+        cslg.composition_to_dts_shift,
+        cslg.least_decode_to_display_delta,
+        cslg.greatest_decode_to_display_delta,
+        cslg.composition_start_time,
+        cslg.composition_end_time
+    ];
+    cslg.version = if fields.iter().all(|&f| f == f as i32 as i64) { 0 } else { 1 };
+
+    cslg
+}
+
+fn trimmed_sync_samples(table: &SyncSampleAtom, first: usize, count: usize) -> SyncSampleAtom {
+    let mut trimmed = SyncSampleAtom::new();
+    trimmed.typ = table.typ.clone();
+    trimmed.sample_numbers = table.sample_numbers.iter()
+        .map(|&n| (n as usize) - 1)
+        .filter(|&i| i >= first && i < first + count)
+        .map(|i| (i - first + 1) as u32)
+        .collect();
+    trimmed
+}
+
+fn sample_sizes<I: Iterator<Item = Sample> + Clone>(samples: I) -> SampleSizeAtom {
+    let mut stsz = SampleSizeAtom::new();
+    let sizes = samples.map(|s| s.size);
+
+    let first = sizes.clone().next().unwrap_or(0);
+    stsz.sample_count = sizes.clone().count() as u32;
+    if first != 0 && sizes.clone().all(|size| size == first) {
+        stsz.sample_size = first;
+    } else {
+        stsz.sizes = sizes.collect();
+    }
+
+    stsz
+}
+
+// The QuickTime PCM stsz, where every sample is one frame and has size 1
+fn frame_counts(count: usize) -> SampleSizeAtom {
+    let mut stsz = SampleSizeAtom::new();
+    stsz.sample_size = 1;
+    stsz.sample_count = count as u32;
+    stsz
+}
+
+// An edit presenting media_time onwards for segment_duration, at normal rate
+pub fn edit(segment_duration: u64, media_time: i64) -> EditListEntry {
+    EditListEntry { segment_duration, media_time, media_rate: Fixed16_16::from_int(1) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::MParser;
+
+    fn read_movie() -> (MParser, MoovAtom) {
+        let mut parser = MParser::new("videos/input.mp4").unwrap();
+        let moov = MovieAtoms::parse_file(&mut parser).unwrap().moov.unwrap();
+        (parser, moov)
+    }
+
+    fn everything(moov: &MoovAtom) -> Vec<TrackSelection> {
+        moov.traks.iter()
+            .map(|t| TrackSelection { first: 0, end: t.sample_index().unwrap().len(), edits: None })
+            .collect()
+    }
+
+    fn contains(data: &[u8], typ: &str) -> bool {
+        data.windows(4).any(|w| w == typ.as_bytes())
+    }
+
+    #[test]
+    fn keeps_source_chunks_in_file_order() {
+        let (parser, moov) = read_movie();
+        let writer = MovieWriter::new(&parser.data, &moov, everything(&moov), OutputOptions::default()).unwrap();
+
+        for (track, index) in writer.indexes.iter().enumerate() {
+            let chunks = writer.chunks.iter().filter(|c| c.track == track).collect::<Vec<_>>();
+            let source_chunks = index.iter().map(|s| s.chunk).max().unwrap() as usize;
+            assert_eq!(chunks.len(), source_chunks);
+            assert_eq!(chunks.iter().map(|c| c.count).sum::<usize>(), index.len());
+        }
+        assert!(writer.chunks.windows(2).all(|w| w[0].sources[0].offset < w[1].sources[0].offset));
+        assert!(writer.chunks.windows(2).all(|w| w[0].offset + w[0].size == w[1].offset));
+    }
+
+    #[test]
+    fn interleaves_by_slot() {
+        let (parser, moov) = read_movie();
+        let options = OutputOptions { interleave: Some(0.5) };
+        let writer = MovieWriter::new(&parser.data, &moov, everything(&moov), options).unwrap();
+
+        // slot by slot, and within a slot track by track
+        assert!(writer.chunks.windows(2).all(|w| (w[0].group, w[0].track) < (w[1].group, w[1].track)));
+
+        for (track, index) in writer.indexes.iter().enumerate() {
+            let mut samples = index.iter();
+            for chunk in writer.chunks.iter().filter(|c| c.track == track) {
+                for sample in samples.by_ref().take(chunk.count) {
+                    let slot = (index.media_time_to_seconds(sample.dts as i64) / 0.5).floor() as u64;
+                    assert_eq!(slot, chunk.group);
+                }
+            }
+            assert!(samples.next().is_none());
+
+            // stsc gives back each chunk's sample count
+            let counts = writer.chunks.iter().filter(|c| c.track == track).map(|c| c.count as u32).collect::<Vec<_>>();
+            let stsc = writer.sample_to_chunk(track);
+            let described = (1..=counts.len() as u32).map(|chunk| {
+                stsc.entries.iter().rev().find(|e| e.first_chunk <= chunk).unwrap().samples_per_chunk
+            }).collect::<Vec<_>>();
+            assert_eq!(described, counts);
+            assert!(counts.len() > 1 && counts.len() < index.len());
+        }
+    }
+
+    #[test]
+    fn switches_to_co64_past_4_gb() {
+        let (parser, moov) = read_movie();
+        let mut writer = MovieWriter::new(&parser.data, &moov, everything(&moov), OutputOptions::default()).unwrap();
+
+        let head = writer.build_head().unwrap();
+        assert!(contains(&head, "stco") && !contains(&head, "co64"));
+
+        // pretend the first chunk is big enough to push the rest past 4 GB
+        writer.chunks[0].size += u32::MAX as u64;
+        for chunk in writer.chunks.iter_mut().skip(1) {
+            chunk.offset += u32::MAX as u64;
+        }
+        let head = writer.build_head().unwrap();
+        assert!(contains(&head, "co64") && !contains(&head, "stco"));
+
+        let last = writer.chunks.last().unwrap();
+        let offsets = writer.chunk_offsets(last.track, 0, true);
+        assert_eq!(offsets.offsets.last(), Some(&last.offset));
+        assert!(last.offset > u32::MAX as u64);
+    }
+}